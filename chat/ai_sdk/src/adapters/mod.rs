@@ -0,0 +1,87 @@
+mod ollama;
+mod openai;
+
+use crate::{AiConfig, AiMessage, AiService};
+use chat_core::{AgentArgs, AgentError};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use std::env;
+
+pub use ollama::OllamaAdapter;
+pub use openai::OpenAiAdapter;
+
+#[derive(Debug, Clone)]
+pub enum AiAdapter {
+    OpenAi(OpenAiAdapter),
+    Ollama(OllamaAdapter),
+}
+
+impl AiAdapter {
+    /// Build the adapter from the agent args, missing settings are taken from the provider config.
+    /// The url and the api key always come from the provider config, so that agents couldn't send
+    /// the key of the server elsewhere.
+    pub fn try_new(args: &AgentArgs, config: &AiConfig) -> Result<Self, AgentError> {
+        let provider = config.provider(args);
+        let base_url = &provider.base_url;
+        let model = args.model_args().model.as_ref().unwrap_or(&provider.model);
+
+        let adapter = match args {
+            AgentArgs::Openai(args) => {
+                let api_key = match &provider.api_key {
+                    Some(key) => key.clone(),
+                    None => env::var("OPENAI_API_KEY").map_err(|_| {
+                        AgentError::InvalidConfig("openai api key is not configured".to_string())
+                    })?,
+                };
+                Self::OpenAi(
+                    OpenAiAdapter::new(base_url, api_key, model)
+                        .with_temperature(args.temperature)
+                        .with_max_tokens(args.max_tokens),
+                )
+            }
+            AgentArgs::Ollama(args) => Self::Ollama(
+                OllamaAdapter::new(base_url, model)
+                    .with_temperature(args.temperature)
+                    .with_max_tokens(args.max_tokens),
+            ),
+        };
+        Ok(adapter)
+    }
+}
+
+impl AiService for AiAdapter {
+    async fn complete(&self, messages: &[AiMessage]) -> Result<String, AgentError> {
+        match self {
+            AiAdapter::OpenAi(adapter) => adapter.complete(messages).await,
+            AiAdapter::Ollama(adapter) => adapter.complete(messages).await,
+        }
+    }
+}
+
+/// Send the request and parse the json body, map failures into `AgentError`
+async fn send_json<T: DeserializeOwned>(req: RequestBuilder) -> Result<T, AgentError> {
+    let resp = req.send().await.map_err(map_reqwest_error)?;
+    let status = resp.status();
+    if !status.is_success() {
+        let message = resp.text().await.unwrap_or_default();
+        return Err(match status {
+            StatusCode::TOO_MANY_REQUESTS => AgentError::RateLimited(message),
+            _ => AgentError::Upstream {
+                status: status.as_u16(),
+                message,
+            },
+        });
+    }
+
+    resp.json::<T>()
+        .await
+        .map_err(|e| AgentError::InvalidResponse(e.to_string()))
+}
+
+fn map_reqwest_error(e: reqwest::Error) -> AgentError {
+    if e.is_timeout() {
+        AgentError::Timeout(e.to_string())
+    } else {
+        AgentError::Network(e.to_string())
+    }
+}
@@ -0,0 +1,103 @@
+use super::send_json;
+use crate::{AiMessage, AiService};
+use chat_core::AgentError;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+/// Adapter for ollama chat API (`POST {base_url}/api/chat`)
+#[derive(Debug, Clone)]
+pub struct OllamaAdapter {
+    client: Client,
+    base_url: String,
+    model: String,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [AiMessage],
+    stream: bool,
+    options: ChatOptions,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    message: AiMessage,
+}
+
+impl OllamaAdapter {
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.into(),
+            model: model.into(),
+            temperature: None,
+            max_tokens: None,
+        }
+    }
+
+    pub fn with_temperature(mut self, temperature: Option<f32>) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: Option<u32>) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+}
+
+impl AiService for OllamaAdapter {
+    async fn complete(&self, messages: &[AiMessage]) -> Result<String, AgentError> {
+        let url = format!("{}/api/chat", self.base_url.trim_end_matches('/'));
+        let body = ChatRequest {
+            model: &self.model,
+            messages,
+            stream: false,
+            options: ChatOptions {
+                temperature: self.temperature,
+                num_predict: self.max_tokens,
+            },
+        };
+
+        let resp: ChatResponse = send_json(self.client.post(url).json(&body)).await?;
+        Ok(resp.message.content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_reply, MockServer};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn ollama_complete_should_work() -> Result<()> {
+        let server = MockServer::start().await?;
+        let adapter =
+            OllamaAdapter::new(server.ollama_url(), "llama-mock").with_max_tokens(Some(64));
+
+        let messages = vec![
+            AiMessage::system("You are a helpful assistant"),
+            AiMessage::user("Hello"),
+        ];
+        let ret = adapter.complete(&messages).await?;
+        assert_eq!(ret, mock_reply("llama-mock", "Hello"));
+
+        let adapter = OllamaAdapter::new(server.ollama_url(), "mock-error");
+        let ret = adapter.complete(&messages).await;
+        assert!(matches!(ret, Err(AgentError::Upstream { status: 500, .. })));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use super::send_json;
+use crate::{AiMessage, AiService};
+use chat_core::AgentError;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+/// Adapter for OpenAI compatible chat completion APIs (`POST {base_url}/chat/completions`)
+#[derive(Debug, Clone)]
+pub struct OpenAiAdapter {
+    client: Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    messages: &'a [AiMessage],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionResponse {
+    choices: Vec<CompletionChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionChoice {
+    message: AiMessage,
+}
+
+impl OpenAiAdapter {
+    pub fn new(
+        base_url: impl Into<String>,
+        api_key: impl Into<String>,
+        model: impl Into<String>,
+    ) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.into(),
+            api_key: api_key.into(),
+            model: model.into(),
+            temperature: None,
+            max_tokens: None,
+        }
+    }
+
+    pub fn with_temperature(mut self, temperature: Option<f32>) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: Option<u32>) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+}
+
+impl AiService for OpenAiAdapter {
+    async fn complete(&self, messages: &[AiMessage]) -> Result<String, AgentError> {
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        let body = CompletionRequest {
+            model: &self.model,
+            messages,
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+        };
+        let req = self.client.post(url).bearer_auth(&self.api_key).json(&body);
+
+        let resp: CompletionResponse = send_json(req).await?;
+        resp.choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .ok_or_else(|| AgentError::InvalidResponse("no choices in response".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_reply, MockServer};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn openai_complete_should_work() -> Result<()> {
+        let server = MockServer::start().await?;
+        let adapter = OpenAiAdapter::new(server.openai_url(), "test-key", "gpt-mock")
+            .with_temperature(Some(0.1));
+
+        let messages = vec![
+            AiMessage::system("You are a helpful assistant"),
+            AiMessage::user("Hello"),
+        ];
+        let ret = adapter.complete(&messages).await?;
+        assert_eq!(ret, mock_reply("gpt-mock", "Hello"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn openai_errors_should_be_mapped() -> Result<()> {
+        let server = MockServer::start().await?;
+        let messages = vec![AiMessage::user("Hello")];
+
+        let adapter = OpenAiAdapter::new(server.openai_url(), "", "gpt-mock");
+        let ret = adapter.complete(&messages).await;
+        assert!(matches!(ret, Err(AgentError::Upstream { status: 401, .. })));
+
+        let adapter = OpenAiAdapter::new(server.openai_url(), "test-key", "mock-rate-limit");
+        let ret = adapter.complete(&messages).await;
+        assert!(matches!(ret, Err(AgentError::RateLimited(_))));
+
+        let adapter = OpenAiAdapter::new(server.openai_url(), "test-key", "mock-error");
+        let ret = adapter.complete(&messages).await;
+        assert!(matches!(ret, Err(AgentError::Upstream { status: 500, .. })));
+
+        let adapter = OpenAiAdapter::new("http://127.0.0.1:1", "test-key", "gpt-mock");
+        let ret = adapter.complete(&messages).await;
+        assert!(matches!(ret, Err(AgentError::Network(_))));
+
+        Ok(())
+    }
+}
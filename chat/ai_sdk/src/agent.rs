@@ -0,0 +1,173 @@
+use crate::{AiAdapter, AiConfig, AiMessage, AiService};
+use chat_core::{Agent, AgentContext, AgentDecision, AgentError, AgentType, ChatAgent, Message};
+
+/// An agent backed by a model adapter, its decision depends on the agent type:
+///
+/// - proxy: the answer of the model replaces the message content
+/// - reply: the answer of the model is posted as a reply
+/// - tap: the model is not called at all
+#[derive(Debug, Clone)]
+pub struct AiAgent {
+    pub name: String,
+    pub r#type: AgentType,
+    pub prompt: String,
+    pub adapter: AiAdapter,
+}
+
+impl AiAgent {
+    pub fn new(
+        name: impl Into<String>,
+        r#type: AgentType,
+        prompt: impl Into<String>,
+        adapter: AiAdapter,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            r#type,
+            prompt: prompt.into(),
+            adapter,
+        }
+    }
+
+    pub fn try_new(agent: &ChatAgent, config: &AiConfig) -> Result<Self, AgentError> {
+        agent
+            .args
+            .validate()
+            .map_err(|e| AgentError::InvalidConfig(format!("agent {}: {}", agent.name, e)))?;
+        let adapter = AiAdapter::try_new(&agent.args, config)?;
+        Ok(Self::new(
+            &agent.name,
+            agent.r#type.clone(),
+            &agent.prompt,
+            adapter,
+        ))
+    }
+}
+
+impl Agent for AiAgent {
+    async fn process(&self, msg: Message, ctx: &AgentContext) -> Result<AgentDecision, AgentError> {
+        if self.r#type == AgentType::Tap {
+            return Ok(AgentDecision::None);
+        }
+
+        let mut messages = vec![AiMessage::system(&self.prompt)];
+        // proxy agents only transform the message itself, reply agents join the conversation
+        if self.r#type == AgentType::Reply {
+            messages.extend(
+                ctx.history()
+                    .iter()
+                    .map(|m| AiMessage::user(format!("{}: {}", ctx.sender_name(m), m.content))),
+            );
+        }
+        messages.push(AiMessage::user(msg.content));
+        let answer = self.adapter.complete(&messages).await?;
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Err(AgentError::InvalidResponse(format!(
+                "agent {} got an empty answer",
+                self.name
+            )));
+        }
+
+        let decision = match self.r#type {
+            AgentType::Proxy => AgentDecision::Modify(answer.to_string()),
+            AgentType::Reply => AgentDecision::Reply(answer.to_string()),
+            AgentType::Tap => AgentDecision::None,
+        };
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock::{mock_reply, MockServer},
+        ProviderConfig,
+    };
+    use anyhow::Result;
+    use chat_core::{AgentArgs, Chat, ChatType, ModelArgs, SenderType};
+    use chrono::Utc;
+
+    #[tokio::test]
+    async fn ai_agent_should_decide_by_type() -> Result<()> {
+        let server = MockServer::start().await?;
+        let config = server.config();
+
+        let args = AgentArgs::Ollama(ModelArgs {
+            model: Some("m".to_string()),
+            ..Default::default()
+        });
+        let mut agent = chat_agent(AgentType::Proxy, args);
+        let ai = AiAgent::try_new(&agent, &config)?;
+        let ret = ai.process(message("hello"), &context(&agent)).await?;
+        assert!(matches!(ret, AgentDecision::Modify(s) if s == mock_reply("m", "hello")));
+
+        agent.r#type = AgentType::Reply;
+        let ai = AiAgent::try_new(&agent, &config)?;
+        let ret = ai.process(message("hello"), &context(&agent)).await?;
+        assert!(matches!(ret, AgentDecision::Reply(s) if s == mock_reply("m", "hello")));
+
+        agent.r#type = AgentType::Tap;
+        let ai = AiAgent::try_new(&agent, &config)?;
+        let ret = ai.process(message("hello"), &context(&agent)).await?;
+        assert!(matches!(ret, AgentDecision::None));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ai_agent_with_invalid_args_should_fail() {
+        let openai = ProviderConfig {
+            api_key: Some("test-key".to_string()),
+            ..ProviderConfig::openai()
+        };
+        let config = AiConfig::new(openai, ProviderConfig::ollama());
+
+        let args = AgentArgs::Openai(ModelArgs {
+            temperature: Some(3.0),
+            ..Default::default()
+        });
+        let agent = chat_agent(AgentType::Proxy, args);
+        let ret = AiAgent::try_new(&agent, &config);
+        assert!(matches!(ret, Err(AgentError::InvalidConfig(_))));
+
+        let agent = chat_agent(AgentType::Proxy, AgentArgs::default());
+        assert!(AiAgent::try_new(&agent, &config).is_ok());
+    }
+
+    fn chat_agent(r#type: AgentType, args: AgentArgs) -> ChatAgent {
+        ChatAgent {
+            id: 1,
+            chat_id: 1,
+            user_id: 6,
+            name: "test".to_string(),
+            r#type,
+            prompt: "You are a test agent".to_string(),
+            args,
+            invalid_args: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn context(agent: &ChatAgent) -> AgentContext {
+        let chat = Chat {
+            id: 1,
+            ws_id: 1,
+            name: None,
+            r#type: ChatType::Single,
+            members: vec![1, 2],
+            agents: vec![agent.id],
+            created_at: Utc::now(),
+        };
+        AgentContext::new(chat, agent.clone(), 1024).with_messages(vec![message("hi")])
+    }
+
+    fn message(content: &str) -> Message {
+        Message {
+            id: 1,
+            ..Message::draft(1, 1, SenderType::User, content)
+        }
+    }
+}
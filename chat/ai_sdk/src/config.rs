@@ -0,0 +1,58 @@
+use chat_core::AgentArgs;
+use serde::{Deserialize, Serialize};
+
+/// Server side settings of the model providers, shared by all agents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiConfig {
+    #[serde(default = "ProviderConfig::openai")]
+    pub openai: ProviderConfig,
+    #[serde(default = "ProviderConfig::ollama")]
+    pub ollama: ProviderConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderConfig {
+    pub base_url: String,
+    /// if not set, fall back to env `OPENAI_API_KEY` for openai
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// model used when the agent doesn't specify one
+    pub model: String,
+}
+
+impl ProviderConfig {
+    pub fn openai() -> Self {
+        Self {
+            base_url: "https://api.openai.com/v1".to_string(),
+            api_key: None,
+            model: "gpt-4o-mini".to_string(),
+        }
+    }
+
+    pub fn ollama() -> Self {
+        Self {
+            base_url: "http://localhost:11434".to_string(),
+            api_key: None,
+            model: "llama3.2".to_string(),
+        }
+    }
+}
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        Self::new(ProviderConfig::openai(), ProviderConfig::ollama())
+    }
+}
+
+impl AiConfig {
+    pub fn new(openai: ProviderConfig, ollama: ProviderConfig) -> Self {
+        Self { openai, ollama }
+    }
+
+    pub fn provider(&self, args: &AgentArgs) -> &ProviderConfig {
+        match args {
+            AgentArgs::Openai(_) => &self.openai,
+            AgentArgs::Ollama(_) => &self.ollama,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+mod adapters;
+mod agent;
+mod config;
+
+#[cfg(feature = "test-util")]
+pub mod mock;
+
+use chat_core::AgentError;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+pub use adapters::{AiAdapter, OllamaAdapter, OpenAiAdapter};
+pub use agent::AiAgent;
+pub use config::{AiConfig, ProviderConfig};
+
+pub trait AiService {
+    /// Send the conversation to the model and return the content of its answer
+    fn complete(
+        &self,
+        messages: &[AiMessage],
+    ) -> impl Future<Output = Result<String, AgentError>> + Send;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AiMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl AiMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+}
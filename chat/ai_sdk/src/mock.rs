@@ -0,0 +1,137 @@
+//! A deterministic in-process model server for offline tests.
+//!
+//! It serves both `POST /v1/chat/completions` (openai) and `POST /api/chat` (ollama). The answer is
+//! always `mock_reply(model, <last user message>)`, unless the model name asks for a failure:
+//!
+//! - `mock-error`: 500
+//! - `mock-rate-limit`: 429
+//! - `mock-slow`: answers after 5 seconds
+
+use crate::{AiConfig, AiMessage, ProviderConfig, Role};
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{net::SocketAddr, time::Duration};
+use tokio::net::TcpListener;
+
+#[derive(Debug, Clone)]
+pub struct MockServer {
+    addr: SocketAddr,
+}
+
+#[derive(Debug, Deserialize)]
+struct MockRequest {
+    model: String,
+    messages: Vec<AiMessage>,
+}
+
+pub fn mock_reply(model: &str, content: &str) -> String {
+    format!("[{}] {}", model, content)
+}
+
+impl MockServer {
+    pub async fn start() -> std::io::Result<Self> {
+        let app = Router::new()
+            .route("/v1/chat/completions", post(openai_handler))
+            .route("/api/chat", post(ollama_handler));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .expect("mock server failed");
+        });
+
+        Ok(Self { addr })
+    }
+
+    pub fn openai_url(&self) -> String {
+        format!("http://{}/v1", self.addr)
+    }
+
+    pub fn ollama_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Provider config pointing both adapters to this server
+    pub fn config(&self) -> AiConfig {
+        AiConfig::new(
+            ProviderConfig {
+                base_url: self.openai_url(),
+                api_key: Some("mock-key".to_string()),
+                model: "mock".to_string(),
+            },
+            ProviderConfig {
+                base_url: self.ollama_url(),
+                api_key: None,
+                model: "mock".to_string(),
+            },
+        )
+    }
+}
+
+async fn openai_handler(headers: HeaderMap, Json(req): Json<MockRequest>) -> Response {
+    let authorized = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|key| !key.trim().is_empty());
+    if !authorized {
+        return error(StatusCode::UNAUTHORIZED, "invalid api key");
+    }
+
+    match answer(&req).await {
+        Ok(content) => Json(json!({
+            "id": "chatcmpl-mock",
+            "object": "chat.completion",
+            "model": req.model,
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": content },
+                "finish_reason": "stop"
+            }]
+        }))
+        .into_response(),
+        Err(resp) => resp,
+    }
+}
+
+async fn ollama_handler(Json(req): Json<MockRequest>) -> Response {
+    match answer(&req).await {
+        Ok(content) => Json(json!({
+            "model": req.model,
+            "message": { "role": "assistant", "content": content },
+            "done": true
+        }))
+        .into_response(),
+        Err(resp) => resp,
+    }
+}
+
+async fn answer(req: &MockRequest) -> Result<String, Response> {
+    match req.model.as_str() {
+        "mock-error" => return Err(error(StatusCode::INTERNAL_SERVER_ERROR, "mock failure")),
+        "mock-rate-limit" => return Err(error(StatusCode::TOO_MANY_REQUESTS, "slow down")),
+        "mock-slow" => tokio::time::sleep(Duration::from_secs(5)).await,
+        _ => {}
+    }
+
+    let content = req
+        .messages
+        .iter()
+        .rev()
+        .find(|m| m.role == Role::User)
+        .map(|m| m.content.as_str())
+        .unwrap_or_default();
+    Ok(mock_reply(&req.model, content))
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": { "message": message } }))).into_response()
+}
@@ -5,17 +5,32 @@ pub mod middlewares;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::future::Future;
 use thiserror::Error;
 pub use utils::*;
 use utoipa::ToSchema;
 
-#[allow(async_fn_in_trait)]
 pub trait Agent {
-    async fn process(&self, msg: Message, ctx: &AgentContext) -> Result<AgentDecision, AgentError>;
+    // the future is `Send` so that agents could be run in spawned tasks
+    fn process(
+        &self,
+        msg: Message,
+        ctx: &AgentContext,
+    ) -> impl Future<Output = Result<AgentDecision, AgentError>> + Send;
 }
 
+/// What an agent knows about the conversation it is acting on
 #[derive(Debug, Clone)]
-pub struct AgentContext {}
+pub struct AgentContext {
+    pub chat: Chat,
+    /// the latest messages before the one being processed, oldest first
+    pub messages: Vec<Message>,
+    pub members: Vec<ChatUser>,
+    /// the agent being invoked
+    pub agent: ChatAgent,
+    /// max characters of history the agent should pass to the model
+    pub budget: usize,
+}
 
 #[derive(Debug, Clone)]
 pub enum AgentDecision {
@@ -29,6 +44,24 @@ pub enum AgentDecision {
 pub enum AgentError {
     #[error("Network error: {0}")]
     Network(String),
+
+    #[error("Agent unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("Invalid agent config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Upstream error {status}: {message}")]
+    Upstream { status: u16, message: String },
+
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
+    #[error("Request timeout: {0}")]
+    Timeout(String),
+
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
 }
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
@@ -44,6 +77,10 @@ pub struct User {
     #[serde(skip)]
     pub password_hash: Option<String>,
     pub created_at: DateTime<Utc>,
+    /// scopes of the API token the user is authenticated with, `None` for user tokens
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub scopes: Option<Vec<ApiScope>>,
 }
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
@@ -61,6 +98,47 @@ pub struct ChatUser {
     pub id: i64,
     pub fullname: String,
     pub email: String,
+    /// bot users are the identity of chat agents
+    #[sqlx(default)]
+    #[serde(default)]
+    pub is_bot: bool,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub role: WorkspaceRole,
+}
+
+/// Role of a user in the workspace
+#[derive(
+    Debug, Clone, Copy, Default, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type,
+)]
+#[sqlx(type_name = "workspace_role", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum WorkspaceRole {
+    Owner,
+    Admin,
+    #[default]
+    Member,
+    /// guests could chat but not manage anything
+    Guest,
+}
+
+/// What an API token is allowed to do, users signed in themselves could do everything
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "api_scope", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum ApiScope {
+    ReadChats,
+    SendMessages,
+    UploadFiles,
+}
+
+/// Role of a member in the chat, members without a role are plain members
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "chat_role", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum ChatRole {
+    Creator,
+    Admin,
 }
 
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq, PartialOrd, sqlx::Type)]
@@ -99,11 +177,78 @@ pub struct Message {
     pub chat_id: i64,
     #[serde(alias = "senderId")]
     pub sender_id: i64,
+    #[serde(alias = "senderType", default)]
+    pub sender_type: SenderType,
     pub content: String,
     pub modified_content: Option<String>,
     pub files: Vec<String>,
+    /// the thread the message replies to
+    #[serde(alias = "parentId", default)]
+    pub parent_id: Option<i64>,
+    /// number of replies in the thread started by the message
+    #[serde(alias = "replyCount", default)]
+    pub reply_count: i32,
+    #[serde(alias = "lastReplyAt", default)]
+    pub last_reply_at: Option<DateTime<Utc>>,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
+    /// set when the sender edited the content
+    #[serde(alias = "editedAt", default)]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// set when the message is deleted, the content and files are cleared
+    #[serde(alias = "deletedAt", default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// aggregated reactions, only loaded when listing messages
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub reactions: Vec<ReactionCount>,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MessageReaction {
+    #[serde(alias = "messageId")]
+    pub message_id: i64,
+    #[serde(alias = "userId")]
+    pub user_id: i64,
+    pub emoji: String,
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChatReadState {
+    #[serde(alias = "chatId")]
+    pub chat_id: i64,
+    #[serde(alias = "userId")]
+    pub user_id: i64,
+    #[serde(alias = "lastReadMessageId")]
+    pub last_read_message_id: i64,
+    #[serde(alias = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+    /// users who reacted, in the order of their reactions
+    pub user_ids: Vec<i64>,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type,
+)]
+#[sqlx(type_name = "sender_type", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum SenderType {
+    #[default]
+    User,
+    Agent,
+    /// posted by an external system through an incoming webhook
+    Integration,
 }
 
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq, PartialOrd, sqlx::Type)]
@@ -123,14 +268,150 @@ pub enum AgentType {
 pub struct ChatAgent {
     pub id: i64,
     pub chat_id: i64,
+    /// the bot user the agent posts as
+    pub user_id: i64,
     pub name: String,
     pub r#type: AgentType,
     pub prompt: String,
-    pub args: serde_json::Value, // TODO: change to custom type
+    #[sqlx(json)]
+    pub args: AgentArgs,
+    /// why the stored args couldn't be parsed, the agent is not run and `args` are the default ones
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invalid_args: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// Model settings of the agent, tagged by the adapter kind, e.g. `{"adapter": "ollama", "model": "llama3.2"}`
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "adapter", rename_all = "camelCase")]
+pub enum AgentArgs {
+    #[serde(alias = "openAi", alias = "open_ai")]
+    Openai(ModelArgs),
+    Ollama(ModelArgs),
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ModelArgs {
+    /// model name, use the server default if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+}
+
+impl Default for AgentArgs {
+    fn default() -> Self {
+        Self::Openai(ModelArgs::default())
+    }
+}
+
+impl AgentArgs {
+    pub fn model_args(&self) -> &ModelArgs {
+        match self {
+            Self::Openai(args) | Self::Ollama(args) => args,
+        }
+    }
+
+    /// Check the values that couldn't be expressed by the type
+    pub fn validate(&self) -> Result<(), String> {
+        let args = self.model_args();
+        if let Some(model) = &args.model {
+            if model.trim().is_empty() {
+                return Err("model cannot be empty".to_string());
+            }
+        }
+        if let Some(temperature) = args.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err(format!(
+                    "temperature must be between 0 and 2, but got {}",
+                    temperature
+                ));
+            }
+        }
+        if args.max_tokens == Some(0) {
+            return Err("maxTokens must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl AgentContext {
+    pub fn new(chat: Chat, agent: ChatAgent, budget: usize) -> Self {
+        Self {
+            chat,
+            messages: vec![],
+            members: vec![],
+            agent,
+            budget,
+        }
+    }
+
+    pub fn with_messages(mut self, messages: Vec<Message>) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    pub fn with_members(mut self, members: Vec<ChatUser>) -> Self {
+        self.members = members;
+        self
+    }
+
+    /// The most recent messages that fit into the budget, oldest first
+    pub fn history(&self) -> &[Message] {
+        let mut used = 0;
+        let mut start = self.messages.len();
+        for msg in self.messages.iter().rev() {
+            used += msg.content.chars().count();
+            if used > self.budget {
+                break;
+            }
+            start -= 1;
+        }
+        &self.messages[start..]
+    }
+
+    /// Name of the message sender, falls back to the user id if not a member
+    pub fn sender_name(&self, msg: &Message) -> String {
+        self.members
+            .iter()
+            .find(|u| u.id == msg.sender_id)
+            .map(|u| u.fullname.clone())
+            .unwrap_or_else(|| format!("user {}", msg.sender_id))
+    }
+}
+
+impl Message {
+    /// A message that is not saved yet
+    pub fn draft(
+        chat_id: i64,
+        sender_id: i64,
+        sender_type: SenderType,
+        content: impl Into<String>,
+    ) -> Self {
+        Self {
+            id: 0,
+            chat_id,
+            sender_id,
+            sender_type,
+            content: content.into(),
+            modified_content: None,
+            files: vec![],
+            parent_id: None,
+            reply_count: 0,
+            last_reply_at: None,
+            created_at: Utc::now(),
+            edited_at: None,
+            deleted_at: None,
+            reactions: vec![],
+        }
+    }
+}
+
 impl User {
     pub fn new(id: i64, fullname: &str, email: &str) -> Self {
         Self {
@@ -141,6 +422,110 @@ impl User {
             email: email.to_string(),
             password_hash: None,
             created_at: Utc::now(),
+            scopes: None,
         }
     }
+
+    /// Whether the user could act with the scope, always true unless authenticated with an
+    /// API token
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.contains(&scope))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agent_context_history_should_respect_budget() {
+        let messages: Vec<_> = ["first message", "second", "third"]
+            .iter()
+            .enumerate()
+            .map(|(i, content)| Message {
+                id: i as i64 + 1,
+                ..Message::draft(1, 1, SenderType::User, *content)
+            })
+            .collect();
+        let chat = Chat {
+            id: 1,
+            ws_id: 1,
+            name: None,
+            r#type: ChatType::Single,
+            members: vec![1, 2],
+            agents: vec![],
+            created_at: Utc::now(),
+        };
+        let agent = ChatAgent {
+            id: 1,
+            chat_id: 1,
+            user_id: 6,
+            name: "test".to_string(),
+            r#type: AgentType::Reply,
+            prompt: "test".to_string(),
+            args: AgentArgs::default(),
+            invalid_args: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        let ctx = AgentContext::new(chat, agent, 12).with_messages(messages);
+        let history: Vec<_> = ctx.history().iter().map(|m| m.id).collect();
+        assert_eq!(history, vec![2, 3]);
+
+        let ctx = AgentContext { budget: 0, ..ctx };
+        assert!(ctx.history().is_empty());
+    }
+
+    #[test]
+    fn agent_args_should_parse_and_validate() -> anyhow::Result<()> {
+        let args: AgentArgs =
+            serde_json::from_str(r#"{"adapter": "ollama", "model": "llama3.2"}"#)?;
+        assert_eq!(
+            args,
+            AgentArgs::Ollama(ModelArgs {
+                model: Some("llama3.2".to_string()),
+                ..Default::default()
+            })
+        );
+        assert!(args.validate().is_ok());
+
+        let args: AgentArgs = serde_json::from_str(r#"{"adapter": "openai", "maxTokens": 128}"#)?;
+        assert_eq!(args.model_args().max_tokens, Some(128));
+        assert_eq!(
+            serde_json::to_value(&args)?,
+            serde_json::json!({"adapter": "openai", "maxTokens": 128})
+        );
+
+        // missing adapter, unknown adapter or unknown fields
+        assert!(serde_json::from_str::<AgentArgs>("{}").is_err());
+        assert!(serde_json::from_str::<AgentArgs>(r#"{"adapter": "claude"}"#).is_err());
+        assert!(serde_json::from_str::<AgentArgs>(r#"{"adapter": "ollama", "foo": 1}"#).is_err());
+        // the provider url is set by the server only
+        let ret = serde_json::from_str::<AgentArgs>(
+            r#"{"adapter": "openai", "baseUrl": "https://evil.example.com/v1"}"#,
+        );
+        assert!(ret.is_err());
+
+        let args: AgentArgs = serde_json::from_str(r#"{"adapter": "openai", "temperature": 3}"#)?;
+        assert!(args.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn message_sender_type_should_round_trip() -> anyhow::Result<()> {
+        // rows sent by pg_notify use the column names
+        let row = r#"{"id": 1, "chat_id": 1, "sender_id": 6, "sender_type": "agent", "content": "hi",
+            "modified_content": null, "files": [], "created_at": "2024-12-21T00:00:00Z"}"#;
+        let msg: Message = serde_json::from_str(row)?;
+        assert_eq!(msg.sender_type, SenderType::Agent);
+
+        let value = serde_json::to_value(&msg)?;
+        assert_eq!(value["senderType"], "agent");
+
+        Ok(())
+    }
 }
@@ -1,4 +1,4 @@
-use super::TokenVerify;
+use super::{TokenVerify, API_TOKEN_PREFIX};
 use axum::{
     extract::{FromRequestParts, Query, Request, State},
     http::StatusCode,
@@ -44,10 +44,24 @@ where
             }
         };
 
-    let req = match state.verify(&token) {
-        Ok(user) => {
+    // API tokens have no session, handlers needing one are out of their scopes
+    let verified = if token.starts_with(API_TOKEN_PREFIX) {
+        state
+            .verify_api_token(&token)
+            .await
+            .map(|user| (user, None))
+    } else {
+        state
+            .verify(&token)
+            .map(|(user, session_id)| (user, Some(session_id)))
+    };
+    let req = match verified {
+        Ok((user, session_id)) => {
             let mut req = Request::from_parts(parts, body);
             req.extensions_mut().insert(user);
+            if let Some(session_id) = session_id {
+                req.extensions_mut().insert(session_id);
+            }
             req
         }
         Err(e) => {
@@ -63,7 +77,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{DecodingKey, EncodingKey, User};
+    use crate::{ApiScope, DecodingKey, EncodingKey, SessionId, User};
     use anyhow::Result;
     use axum::{body::Body, middleware::from_fn_with_state, routing::get, Router};
     use std::sync::Arc;
@@ -80,15 +94,34 @@ mod tests {
     impl TokenVerify for AppState {
         type Error = ();
 
-        fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        fn verify(&self, token: &str) -> Result<(User, SessionId), Self::Error> {
             self.0.dk.verify(token).map_err(|_| ())
         }
+
+        async fn verify_api_token(&self, token: &str) -> Result<User, Self::Error> {
+            match token {
+                "chat_pat_good" => Ok(User {
+                    scopes: Some(vec![ApiScope::ReadChats]),
+                    ..User::new(1, "Tyr Chen", "tchen@acme.org")
+                }),
+                _ => Err(()),
+            }
+        }
     }
 
     async fn handler(_req: Request) -> impl IntoResponse {
         (StatusCode::OK, "OK")
     }
 
+    async fn scoped_handler(req: Request) -> impl IntoResponse {
+        let user = req.extensions().get::<User>().unwrap();
+        let has_session = req.extensions().get::<SessionId>().is_some();
+        match (user.has_scope(ApiScope::SendMessages), has_session) {
+            (true, true) => (StatusCode::OK, "OK"),
+            _ => (StatusCode::FORBIDDEN, "Forbidden"),
+        }
+    }
+
     #[tokio::test]
     async fn test_verify_token_middleware_should_work() -> Result<()> {
         let encoding_pem = include_str!("../../fixtures/private.pem");
@@ -98,7 +131,7 @@ mod tests {
         let state = AppState(Arc::new(AppStateInner { ek, dk }));
 
         let user = User::new(1, "Tyr Chen", "tchen@acme.org");
-        let token = state.0.ek.sign(user)?;
+        let token = state.0.ek.sign(user, &SessionId("session-1".to_string()))?;
 
         let app = Router::new()
             .route("/", get(handler))
@@ -142,4 +175,55 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_verify_token_middleware_should_accept_api_tokens() -> Result<()> {
+        let encoding_pem = include_str!("../../fixtures/private.pem");
+        let decoding_pem = include_str!("../../fixtures/public.pem");
+        let ek = EncodingKey::load(encoding_pem)?;
+        let dk = DecodingKey::load(decoding_pem)?;
+        let state = AppState(Arc::new(AppStateInner { ek, dk }));
+        let token = state.0.ek.sign(
+            User::new(1, "Tyr Chen", "tchen@acme.org"),
+            &SessionId("session-1".to_string()),
+        )?;
+
+        let app = Router::new()
+            .route("/", get(handler))
+            .route("/scoped", get(scoped_handler))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state);
+
+        let req = Request::builder()
+            .uri("/")
+            .header("Authorization", "Bearer chat_pat_good")
+            .body(Body::empty())?;
+        let resp = app.clone().oneshot(req).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = Request::builder()
+            .uri("/")
+            .header("Authorization", "Bearer chat_pat_bad")
+            .body(Body::empty())?;
+        let resp = app.clone().oneshot(req).await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // the api token has neither the scope nor a session
+        let req = Request::builder()
+            .uri("/scoped")
+            .header("Authorization", "Bearer chat_pat_good")
+            .body(Body::empty())?;
+        let resp = app.clone().oneshot(req).await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // users signed in themselves have all scopes
+        let req = Request::builder()
+            .uri("/scoped")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let resp = app.oneshot(req).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        Ok(())
+    }
 }
@@ -1,10 +1,11 @@
 mod auth;
+mod rate_limit;
 mod request_id;
 mod server_time;
 
-use std::fmt;
+use std::{fmt, future::Future};
 
-use crate::User;
+use crate::{SessionId, User};
 
 use self::{request_id::set_request_id, server_time::ServerTimeLayer};
 use axum::{middleware::from_fn, Router};
@@ -17,6 +18,12 @@ use tower_http::{
 use tracing::Level;
 
 pub use auth::verify_token;
+pub use rate_limit::{
+    rate_limit_ip, rate_limit_user, too_many_requests, ClientIp, RateLimit, RateLimiter,
+};
+
+/// API tokens start with the prefix, so that they could be told apart from JWTs
+pub const API_TOKEN_PREFIX: &str = "chat_pat_";
 
 const REQUEST_ID_HEADER: &str = "x-request-id";
 const SERVER_TIME_HEADER: &str = "x-server-time";
@@ -24,7 +31,16 @@ const SERVER_TIME_HEADER: &str = "x-server-time";
 pub trait TokenVerify {
     type Error: fmt::Debug;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Error>;
+    /// Verify the token and return the user with the session of the token. Tokens of a
+    /// revoked session should be rejected.
+    fn verify(&self, token: &str) -> Result<(User, SessionId), Self::Error>;
+
+    /// Verify an API token, i.e. one with `API_TOKEN_PREFIX`, and return its user with the
+    /// scopes of the token. Revoked or expired tokens should be rejected.
+    fn verify_api_token(
+        &self,
+        token: &str,
+    ) -> impl Future<Output = Result<User, Self::Error>> + Send;
 }
 
 pub fn set_layer(app: Router) -> Router {
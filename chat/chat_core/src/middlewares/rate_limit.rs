@@ -0,0 +1,275 @@
+use crate::User;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// Prune idle buckets once there are more buckets than this
+const MAX_BUCKETS: usize = 10_000;
+
+/// A token bucket holding `burst` tokens, refilled with `per_minute` tokens per minute.
+/// Each request takes a token.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+/// Ip of the client, added to the extensions of the request by `rate_limit_ip`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Token buckets keyed by client ip or user id
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    /// peers whose `X-Forwarded-For` and `X-Real-IP` headers are trusted
+    trusted_proxies: Arc<Vec<IpAddr>>,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, per_minute: u32) -> Self {
+        Self { burst, per_minute }
+    }
+
+    fn refill_rate(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            trusted_proxies: Default::default(),
+            buckets: Default::default(),
+        }
+    }
+
+    /// Take the client ip from the forwarded headers of requests from these proxies
+    pub fn with_trusted_proxies(mut self, proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = Arc::new(proxies);
+        self
+    }
+
+    /// Take a token of the key, returns how long to wait if there is none left
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let burst = self.limit.burst as f64;
+        let rate = self.limit.refill_rate();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            // buckets refilled to full are the same as missing ones
+            buckets.retain(|_, b| b.tokens + rate * (now - b.updated_at).as_secs_f64() < burst);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated_at: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = (bucket.tokens + rate * elapsed.as_secs_f64()).min(burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        if rate <= 0.0 {
+            return Err(Duration::from_secs(60));
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+/// Limit requests per client ip. The ip is taken from `ConnectInfo`, so serve the app with
+/// `into_make_service_with_connect_info::<SocketAddr>()`, requests without it are not limited.
+/// If the peer is a trusted proxy, `X-Forwarded-For` or `X-Real-IP` set by it is used instead.
+/// The ip is passed on to the handlers as `ClientIp`.
+pub async fn rate_limit_ip(
+    State(limiter): State<RateLimiter>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if let Some(ip) = client_ip(&req, &limiter.trusted_proxies) {
+        if let Err(retry_after) = limiter.check(&format!("ip:{ip}")) {
+            return too_many_requests(retry_after);
+        }
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+    next.run(req).await
+}
+
+/// Limit requests per user, should be layered after `verify_token`
+pub async fn rate_limit_user(
+    State(limiter): State<RateLimiter>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if let Some(user) = req.extensions().get::<User>() {
+        if let Err(retry_after) = limiter.check(&format!("user:{}", user.id)) {
+            return too_many_requests(retry_after);
+        }
+    }
+    next.run(req).await
+}
+
+/// 429 with `Retry-After` in whole seconds
+pub fn too_many_requests(retry_after: Duration) -> Response {
+    let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    let msg = format!("Too many requests, retry after {secs} seconds");
+    warn!(msg);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, secs.to_string())],
+        msg,
+    )
+        .into_response()
+}
+
+/// The forwarded headers could be set by anyone, so they are used only if the peer is trusted.
+/// In `X-Forwarded-For` the client is the last ip which is not a trusted proxy.
+fn client_ip(req: &Request, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let ConnectInfo(addr) = req.extensions().get::<ConnectInfo<SocketAddr>>()?;
+    let peer = addr.ip();
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let headers = req.headers();
+    let forwarded = headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            let ips: Vec<IpAddr> = v
+                .split(',')
+                .filter_map(|ip| ip.trim().parse().ok())
+                .collect();
+            ips.iter()
+                .rev()
+                .find(|ip| !trusted_proxies.contains(ip))
+                .or(ips.first())
+                .copied()
+        });
+    forwarded
+        .or_else(|| {
+            headers
+                .get("x-real-ip")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|ip| ip.trim().parse().ok())
+        })
+        .or(Some(peer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{body::Body, middleware::from_fn_with_state, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn rate_limiter_should_refill_tokens() {
+        let limiter = RateLimiter::new(RateLimit::new(2, 60));
+        let now = Instant::now();
+
+        assert!(limiter.check_at("a", now).is_ok());
+        assert!(limiter.check_at("a", now).is_ok());
+        let retry_after = limiter.check_at("a", now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+        // other keys have their own buckets
+        assert!(limiter.check_at("b", now).is_ok());
+
+        // a token per second
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.check_at("a", later).is_ok());
+        assert!(limiter.check_at("a", later).is_err());
+    }
+
+    #[tokio::test]
+    async fn rate_limit_ip_middleware_should_work() -> Result<()> {
+        let proxy: IpAddr = "10.0.0.2".parse()?;
+        let limiter = RateLimiter::new(RateLimit::new(1, 1)).with_trusted_proxies(vec![proxy]);
+        let app = Router::new()
+            .route("/", get(|| async { "OK" }))
+            .layer(from_fn_with_state(limiter, rate_limit_ip));
+
+        let req = |peer: &str, forwarded_for: &str| {
+            Request::builder()
+                .uri("/")
+                .header("x-forwarded-for", forwarded_for)
+                .extension(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 443)))
+                .body(Body::empty())
+        };
+        let resp = app
+            .clone()
+            .oneshot(req("10.0.0.2", "192.0.2.1, 10.0.0.2")?)
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = app.clone().oneshot(req("10.0.0.2", "192.0.2.1")?).await?;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()[RETRY_AFTER], "60");
+
+        // other clients behind the proxy have their own buckets
+        let resp = app.clone().oneshot(req("10.0.0.2", "192.0.2.2")?).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_ip_should_trust_proxies_only() -> Result<()> {
+        let proxy: IpAddr = "10.0.0.2".parse()?;
+        let req = |peer: &str| {
+            Request::builder()
+                .header("x-forwarded-for", "192.0.2.1, 198.51.100.1")
+                .header("x-real-ip", "192.0.2.3")
+                .extension(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 443)))
+                .body(Body::empty())
+        };
+
+        // the client could forge the leading ips, the one added by the proxy is used
+        let ret = client_ip(&req("10.0.0.2")?, &[proxy]);
+        assert_eq!(ret, Some("198.51.100.1".parse()?));
+        // headers of untrusted peers are ignored
+        let ret = client_ip(&req("203.0.113.1")?, &[proxy]);
+        assert_eq!(ret, Some("203.0.113.1".parse()?));
+        let ret = client_ip(&req("10.0.0.2")?, &[]);
+        assert_eq!(ret, Some(proxy));
+
+        let req = Request::builder()
+            .header("x-real-ip", "192.0.2.3")
+            .extension(ConnectInfo(SocketAddr::new(proxy, 443)))
+            .body(Body::empty())?;
+        assert_eq!(client_ip(&req, &[proxy]), Some("192.0.2.3".parse()?));
+
+        // the peer is unknown without `ConnectInfo`
+        let req = Request::builder()
+            .header("x-real-ip", "192.0.2.3")
+            .body(Body::empty())?;
+        assert_eq!(client_ip(&req, &[proxy]), None);
+
+        Ok(())
+    }
+}
@@ -1,48 +1,208 @@
 use crate::User;
 use jwt_simple::prelude::*;
+use serde::de::DeserializeOwned;
+use std::{mem, sync::RwLock};
+use utoipa::ToSchema;
 
-const JWT_DURATION: u64 = 60 * 60 * 24 * 7;
+/// Access tokens are short-lived, clients renew them with the refresh token of the session
+pub const JWT_DURATION: u64 = 60 * 15;
 const JWT_ISSUER: &str = "chat_server";
 const JWT_AUDIENCE: &str = "chat_web";
 
+/// Id of the session an access token is issued for, carried as the `jti` claim
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionId(pub String);
+
+/// Signs tokens with the `kid` header set to the thumbprint of the public key
 pub struct EncodingKey(Ed25519KeyPair);
 
+/// Public keys to verify tokens with, looked up by the `kid` header of the token. It holds
+/// the current key and the previous ones, so tokens signed before a key rotation still work.
 #[allow(unused)]
-pub struct DecodingKey(Ed25519PublicKey);
+pub struct DecodingKey(RwLock<Vec<(String, Ed25519PublicKey)>>);
+
+/// JSON Web Key Set of the public keys (RFC 7517)
+#[derive(Debug, Clone, Default, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// An Ed25519 public key as JWK (RFC 8037)
+#[derive(Debug, Clone, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct Jwk {
+    /// "OKP"
+    pub kty: String,
+    /// "Ed25519"
+    pub crv: String,
+    pub kid: String,
+    /// the raw public key, base64url encoded
+    pub x: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    #[serde(rename = "use", default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<String>,
+}
 
 impl EncodingKey {
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
         let key = Ed25519KeyPair::from_pem(pem)?;
-        Ok(Self(key))
+        let kid = key_id(&key.public_key());
+        Ok(Self(key.with_key_id(&kid)))
     }
 
-    pub fn sign(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
+    /// Id of the key, same as the id of its public key in `DecodingKey`
+    pub fn kid(&self) -> String {
+        key_id(&self.0.public_key())
+    }
+
+    pub fn sign(
+        &self,
+        user: impl Into<User>,
+        session_id: &SessionId,
+    ) -> Result<String, jwt_simple::Error> {
         let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(JWT_DURATION))
             .with_issuer(JWT_ISSUER)
-            .with_audience(JWT_AUDIENCE);
+            .with_audience(JWT_AUDIENCE)
+            .with_jwt_id(&session_id.0);
+        self.0.sign(claims)
+    }
+
+    /// Sign a token for a one-time action of the user, like resetting the password. The
+    /// purpose is the audience of the token, so it's only accepted for that action.
+    pub fn sign_once(
+        &self,
+        user_id: i64,
+        purpose: &str,
+        id: &str,
+        duration: u64,
+    ) -> Result<String, jwt_simple::Error> {
+        let claims = Claims::create(Duration::from_secs(duration))
+            .with_issuer(JWT_ISSUER)
+            .with_audience(purpose)
+            .with_subject(user_id)
+            .with_jwt_id(id);
         self.0.sign(claims)
     }
 }
 
 impl DecodingKey {
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
-        let key = Ed25519PublicKey::from_pem(pem)?;
-        Ok(Self(key))
+        Self::load_all([pem])
+    }
+
+    /// Load the current public key followed by the previous ones
+    pub fn load_all(
+        pems: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Self, jwt_simple::Error> {
+        let keys = pems
+            .into_iter()
+            .map(|pem| {
+                let key = Ed25519PublicKey::from_pem(pem.as_ref())?;
+                Ok((key_id(&key), key))
+            })
+            .collect::<Result<Vec<_>, jwt_simple::Error>>()?;
+        Ok(Self(RwLock::new(keys)))
+    }
+
+    /// Load the Ed25519 keys of the key set, other keys are ignored
+    pub fn from_jwks(jwks: &Jwks) -> Result<Self, jwt_simple::Error> {
+        let mut keys = Vec::new();
+        for jwk in &jwks.keys {
+            if jwk.kty != "OKP" || jwk.crv != "Ed25519" {
+                continue;
+            }
+            let raw = Base64UrlSafeNoPadding::decode_to_vec(&jwk.x, None)?;
+            let key = Ed25519PublicKey::from_bytes(&raw)?;
+            keys.push((jwk.kid.clone(), key));
+        }
+        if keys.is_empty() {
+            return Err(jwt_simple::Error::msg("no Ed25519 key in the key set"));
+        }
+        Ok(Self(RwLock::new(keys)))
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        let keys = self
+            .0
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(kid, key)| Jwk {
+                kty: "OKP".to_string(),
+                crv: "Ed25519".to_string(),
+                kid: kid.clone(),
+                x: Base64UrlSafeNoPadding::encode_to_string(key.to_bytes())
+                    .expect("encode public key"),
+                alg: Some("EdDSA".to_string()),
+                usage: Some("sig".to_string()),
+            })
+            .collect();
+        Jwks { keys }
+    }
+
+    /// Replace the keys in place, tokens are verified with the new keys from now on
+    pub fn update(&self, other: DecodingKey) {
+        let keys = mem::take(&mut *other.0.write().unwrap());
+        *self.0.write().unwrap() = keys;
     }
 
     #[allow(unused)]
-    pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
+    pub fn verify(&self, token: &str) -> Result<(User, SessionId), jwt_simple::Error> {
+        let claims = self.verify_claims::<User>(token, JWT_AUDIENCE)?;
+        let session_id = claims
+            .jwt_id
+            .ok_or_else(|| jwt_simple::Error::msg("token without session"))?;
+        Ok((claims.custom, SessionId(session_id)))
+    }
+
+    /// Verify a token signed by `sign_once` for the purpose, returns the user id and the
+    /// token id. The caller should make sure the token id is used only once.
+    pub fn verify_once(
+        &self,
+        token: &str,
+        purpose: &str,
+    ) -> Result<(i64, String), jwt_simple::Error> {
+        let claims = self.verify_claims::<NoCustomClaims>(token, purpose)?;
+        let user_id = claims
+            .subject
+            .and_then(|sub| sub.parse().ok())
+            .ok_or_else(|| jwt_simple::Error::msg("token without user"))?;
+        let id = claims
+            .jwt_id
+            .ok_or_else(|| jwt_simple::Error::msg("token without id"))?;
+        Ok((user_id, id))
+    }
+
+    fn verify_claims<T: Serialize + DeserializeOwned>(
+        &self,
+        token: &str,
+        audience: &str,
+    ) -> Result<JWTClaims<T>, jwt_simple::Error> {
+        let metadata = Token::decode_metadata(token)?;
+        let kid = metadata
+            .key_id()
+            .ok_or_else(|| jwt_simple::Error::msg("token without key id"))?;
+
         let options = VerificationOptions {
             allowed_issuers: Some(HashSet::from_strings(&[JWT_ISSUER])),
-            allowed_audiences: Some(HashSet::from_strings(&[JWT_AUDIENCE])),
+            allowed_audiences: Some(HashSet::from_strings(&[audience])),
             ..Default::default()
         };
 
-        let claims = self.0.verify_token::<User>(token, Some(options))?;
-        Ok(claims.custom)
+        let keys = self.0.read().unwrap();
+        let (_, key) = keys
+            .iter()
+            .find(|(id, _)| id == kid)
+            .ok_or_else(|| jwt_simple::Error::msg(format!("unknown key id: {kid}")))?;
+        key.verify_token::<T>(token, Some(options))
     }
 }
 
+/// Key id of the public key, derived from the key so that it needn't be configured
+fn key_id(key: &Ed25519PublicKey) -> String {
+    key.sha256_thumbprint()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -59,9 +219,56 @@ mod tests {
 
         let user = User::new(1, "Tyr Chen", "tchen@acme.org");
 
-        let token = ek.sign(user.clone())?;
-        let user2 = dk.verify(&token)?;
+        let session_id = SessionId("session-1".to_string());
+        let token = ek.sign(user.clone(), &session_id)?;
+        let (user2, session_id2) = dk.verify(&token)?;
         assert_eq!(user, user2);
+        assert_eq!(session_id, session_id2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn jwt_sign_verify_once_should_work() -> Result<()> {
+        let ek = EncodingKey::load(include_str!("../../fixtures/private.pem"))?;
+        let dk = DecodingKey::load(include_str!("../../fixtures/public.pem"))?;
+
+        let token = ek.sign_once(1, "password_reset", "token-1", 60)?;
+        let (user_id, id) = dk.verify_once(&token, "password_reset")?;
+        assert_eq!(user_id, 1);
+        assert_eq!(id, "token-1");
+
+        // tokens are only accepted for their purpose
+        assert!(dk.verify_once(&token, "email_verify").is_err());
+        assert!(dk.verify(&token).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn jwt_verify_with_rotated_keys_should_work() -> Result<()> {
+        let decoding_pem = include_str!("../../fixtures/public.pem");
+        let old = Ed25519KeyPair::generate();
+        let old_ek = EncodingKey::load(&old.to_pem())?;
+        let new_ek = EncodingKey::load(include_str!("../../fixtures/private.pem"))?;
+        let user = User::new(1, "Tyr Chen", "tchen@acme.org");
+        let session_id = SessionId("session-1".to_string());
+        let old_token = old_ek.sign(user.clone(), &session_id)?;
+        let new_token = new_ek.sign(user, &session_id)?;
+
+        // only the new key
+        let dk = DecodingKey::load(decoding_pem)?;
+        assert!(dk.verify(&old_token).is_err());
+        assert!(dk.verify(&new_token).is_ok());
+
+        // the new key with the previous one, loaded from the key set
+        let old_pem = old.public_key().to_pem();
+        let jwks = DecodingKey::load_all([decoding_pem, old_pem.as_str()])?.jwks();
+        assert_eq!(jwks.keys.len(), 2);
+        assert_eq!(jwks.keys[0].kid, new_ek.kid());
+        dk.update(DecodingKey::from_jwks(&jwks)?);
+        assert!(dk.verify(&old_token).is_ok());
+        assert!(dk.verify(&new_token).is_ok());
 
         Ok(())
     }
@@ -1,3 +1,9 @@
 mod jwt;
+mod session;
+mod webhook;
 
-pub use jwt::{DecodingKey, EncodingKey};
+pub use jwt::{DecodingKey, EncodingKey, Jwk, Jwks, SessionId, JWT_DURATION};
+pub use session::{RevokedSessions, SESSION_REVOKED_CHANNEL};
+pub use webhook::{
+    is_public_ip, sign_webhook, verify_webhook, WEBHOOK_EVENTS, WEBHOOK_SIGNATURE_HEADER,
+};
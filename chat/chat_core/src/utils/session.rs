@@ -0,0 +1,63 @@
+use super::jwt::{SessionId, JWT_DURATION};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgExecutor;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+/// Channel notified with the session id when a session is revoked
+pub const SESSION_REVOKED_CHANNEL: &str = "session_revoked";
+
+/// Sessions revoked recently, checked on every token verification. Access tokens live at
+/// most `JWT_DURATION`, so older revocations are pruned as their tokens expired anyway.
+#[derive(Debug, Clone, Default)]
+pub struct RevokedSessions(Arc<RwLock<HashMap<String, DateTime<Utc>>>>);
+
+impl RevokedSessions {
+    pub fn is_revoked(&self, session_id: &SessionId) -> bool {
+        self.0.read().unwrap().contains_key(&session_id.0)
+    }
+
+    pub fn revoke(&self, session_id: impl Into<String>) {
+        let now = Utc::now();
+        let deadline = now - Duration::seconds(JWT_DURATION as i64);
+        let mut sessions = self.0.write().unwrap();
+        sessions.retain(|_, revoked_at| *revoked_at > deadline);
+        sessions.insert(session_id.into(), now);
+    }
+
+    /// Load sessions revoked within the lifetime of an access token, call it before
+    /// listening to `SESSION_REVOKED_CHANNEL`
+    pub async fn load<'e>(&self, executor: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+        let revoked: Vec<(String, DateTime<Utc>)> = sqlx::query_as(
+            r#"
+            SELECT id, revoked_at
+            FROM sessions
+            WHERE revoked_at > NOW() - make_interval(secs => $1)
+            "#,
+        )
+        .bind(JWT_DURATION as f64)
+        .fetch_all(executor)
+        .await?;
+
+        self.0.write().unwrap().extend(revoked);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revoked_sessions_should_work() {
+        let revoked = RevokedSessions::default();
+        let session_id = SessionId("session-1".to_string());
+        assert!(!revoked.is_revoked(&session_id));
+
+        revoked.clone().revoke("session-1");
+        assert!(revoked.is_revoked(&session_id));
+        assert!(!revoked.is_revoked(&SessionId("session-2".to_string())));
+    }
+}
@@ -0,0 +1,169 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Events could be subscribed by webhooks, the same as the `event` tag of the events in SSE
+pub const WEBHOOK_EVENTS: &[&str] = &[
+    "NewChat",
+    "AddToChat",
+    "RemoveFromChat",
+    "NewMessage",
+    "NewThreadReply",
+    "MessageUpdated",
+    "MessageDeleted",
+    "ReactionChanged",
+    "ReadReceipt",
+];
+
+/// Header of the signature of a webhook delivery, `t=<unix timestamp>,v1=<hex signature>`
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "x-chat-signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Sign the body of a webhook delivery with the secret of the subscription. The timestamp is
+/// signed along with the body, so that receivers could reject replayed deliveries.
+pub fn sign_webhook(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mac = webhook_mac(secret, timestamp, body).finalize().into_bytes();
+    format!("t={timestamp},v1={}", hex::encode(mac))
+}
+
+/// Verify the signature header of a delivery, signed within `tolerance_secs` of `now`
+pub fn verify_webhook(
+    secret: &str,
+    signature: &str,
+    body: &[u8],
+    now: i64,
+    tolerance_secs: i64,
+) -> bool {
+    let mut timestamp = None;
+    let mut mac = None;
+    for part in signature.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", v)) => timestamp = v.parse::<i64>().ok(),
+            Some(("v1", v)) => mac = hex::decode(v).ok(),
+            _ => {}
+        }
+    }
+    let (Some(timestamp), Some(mac)) = (timestamp, mac) else {
+        return false;
+    };
+    if (now - timestamp).abs() > tolerance_secs {
+        return false;
+    }
+
+    // constant time comparison
+    webhook_mac(secret, timestamp, body)
+        .verify_slice(&mac)
+        .is_ok()
+}
+
+/// Webhooks are sent from inside the network, so that they should only reach public addresses.
+/// Loopback, private, link-local, unspecified and other special purpose addresses are rejected.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ipv4(ip),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "this network" and the shared address space of carrier-grade NAT
+        || a == 0
+        || (a == 100 && (64..128).contains(&b)))
+}
+
+fn webhook_mac(secret: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webhook_signature_should_verify() {
+        let body = br#"{"event":"NewMessage"}"#;
+        let signature = sign_webhook("secret", 1_700_000_000, body);
+        assert!(signature.starts_with("t=1700000000,v1="));
+
+        assert!(verify_webhook(
+            "secret",
+            &signature,
+            body,
+            1_700_000_100,
+            300
+        ));
+        // wrong secret, tampered body or too old
+        assert!(!verify_webhook(
+            "other",
+            &signature,
+            body,
+            1_700_000_100,
+            300
+        ));
+        assert!(!verify_webhook(
+            "secret",
+            &signature,
+            b"{}",
+            1_700_000_100,
+            300
+        ));
+        assert!(!verify_webhook(
+            "secret",
+            &signature,
+            body,
+            1_700_001_000,
+            300
+        ));
+        assert!(!verify_webhook("secret", "v1=00", body, 1_700_000_000, 300));
+    }
+
+    #[test]
+    fn is_public_ip_should_reject_internal_addresses() {
+        let internal = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ];
+        for ip in internal {
+            assert!(
+                !is_public_ip(ip.parse().unwrap()),
+                "{ip} should be internal"
+            );
+        }
+        assert!(is_public_ip("93.184.215.14".parse().unwrap()));
+        assert!(is_public_ip(
+            "2606:2800:21f:cb07:6820:80da:af6b:8b2c".parse().unwrap()
+        ));
+    }
+}
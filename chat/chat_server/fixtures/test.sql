@@ -12,6 +12,19 @@ INSERT INTO users(ws_id, email, fullname, password_hash)
 (1, 'charlie@acme.org', 'Charlie Chen', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU'),
 (1, 'daisy@acme.org', 'Daisy Chen', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU');
 
+-- all users have verified their emails
+UPDATE users SET email_verified_at = created_at;
+
+-- tchen owns acme
+UPDATE workspaces SET owner_id = 1 WHERE id = 1;
+
+INSERT INTO workspace_members(ws_id, user_id, role)
+  VALUES (1, 1, 'owner'),
+(1, 2, 'member'),
+(1, 3, 'member'),
+(1, 4, 'member'),
+(1, 5, 'member');
+
 -- insert 4 chats
 -- insert public/private channel
 INSERT INTO chats(ws_id, name, type, members)
@@ -23,10 +36,19 @@ INSERT INTO chats(ws_id, type, members)
   VALUES (1, 'single', '{1,2}'),
 (1, 'group', '{1,3,4}');
 
+-- insert bot user for the agent
+INSERT INTO users(ws_id, email, fullname, password_hash, is_bot)
+  VALUES (1, 'agent-1@bot.none', 'translation', '', TRUE);
+
+INSERT INTO workspace_members(ws_id, user_id)
+  VALUES (1, 6);
+
 -- insert agent to chat
-INSERT INTO chat_agents(chat_id, name, type, prompt, args)
-  VALUES (1, 'translation', 'proxy',
-  'If language is Chinese, translate to English, if language is English, translate to Chinese. Please reply with the translated content directly. No explanation is needed. Here is the content: ', '{}');
+INSERT INTO chat_agents(chat_id, user_id, name, type, prompt, args)
+  VALUES (1, 6, 'translation', 'proxy',
+  'If language is Chinese, translate to English, if language is English, translate to Chinese. Please reply with the translated content directly. No explanation is needed. Here is the content: ', '{"adapter": "openai"}');
+
+UPDATE chats SET agents = '{1}' WHERE id = 1;
 
 INSERT INTO messages(chat_id, sender_id, content)
   VALUES (1, 1, 'Hello, world!'),
@@ -0,0 +1,506 @@
+use crate::{AppError, AppState, ListMessages};
+use ai_sdk::{AiAgent, AiConfig};
+use chat_core::{
+    Agent, AgentContext, AgentDecision, AgentError, AgentType, ChatAgent, Message, SenderType,
+};
+use std::time::Duration;
+use tokio::time;
+use tracing::{info, warn};
+
+/// Max time a single agent could take to process a message
+const AGENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of messages before the current one that agents could see
+const AGENT_HISTORY_SIZE: u64 = 20;
+
+/// Max characters of history passed to the model
+const AGENT_CONTEXT_BUDGET: usize = 4096;
+
+pub(crate) type LoadedAgents = Vec<(ChatAgent, AgentVariant)>;
+
+pub(crate) enum AgentVariant {
+    /// proxy and reply agents backed by a model adapter
+    Model(AiAgent),
+    Tap(TapAgent),
+    /// agents that could not be built from their args, they fail on every run
+    Invalid(InvalidAgent),
+}
+
+pub(crate) struct TapAgent {
+    pub name: String,
+}
+
+pub(crate) struct InvalidAgent {
+    pub name: String,
+    pub reason: String,
+}
+
+impl Agent for AgentVariant {
+    async fn process(&self, msg: Message, ctx: &AgentContext) -> Result<AgentDecision, AgentError> {
+        match self {
+            AgentVariant::Model(agent) => agent.process(msg, ctx).await,
+            AgentVariant::Tap(agent) => agent.process(msg, ctx).await,
+            AgentVariant::Invalid(agent) => agent.process(msg, ctx).await,
+        }
+    }
+}
+
+impl Agent for TapAgent {
+    async fn process(
+        &self,
+        msg: Message,
+        _ctx: &AgentContext,
+    ) -> Result<AgentDecision, AgentError> {
+        info!(
+            "Tap agent {} observed message {} in chat {}",
+            self.name, msg.id, msg.chat_id
+        );
+        Ok(AgentDecision::None)
+    }
+}
+
+impl Agent for InvalidAgent {
+    async fn process(
+        &self,
+        _msg: Message,
+        _ctx: &AgentContext,
+    ) -> Result<AgentDecision, AgentError> {
+        Err(AgentError::InvalidConfig(format!(
+            "agent {}: {}",
+            self.name, self.reason
+        )))
+    }
+}
+
+impl AgentVariant {
+    pub(crate) fn new(agent: &ChatAgent, config: &AiConfig) -> Self {
+        if agent.r#type == AgentType::Tap {
+            return AgentVariant::Tap(TapAgent {
+                name: agent.name.clone(),
+            });
+        }
+
+        match AiAgent::try_new(agent, config) {
+            Ok(agent) => AgentVariant::Model(agent),
+            Err(e) => AgentVariant::Invalid(InvalidAgent {
+                name: agent.name.clone(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+}
+
+impl AppState {
+    /// Load the agents of the chat, split into proxy agents and the rest
+    pub(crate) async fn load_agents(
+        &self,
+        chat_id: u64,
+    ) -> Result<(LoadedAgents, LoadedAgents), AppError> {
+        let agents = self.list_agents(chat_id).await?;
+        Ok(agents
+            .into_iter()
+            .map(|agent| {
+                let variant = match &agent.invalid_args {
+                    Some(reason) => AgentVariant::Invalid(InvalidAgent {
+                        name: agent.name.clone(),
+                        reason: format!("malformed args: {reason}"),
+                    }),
+                    None => AgentVariant::new(&agent, &self.config.ai),
+                };
+                (agent, variant)
+            })
+            .partition(|(agent, _)| agent.r#type == AgentType::Proxy))
+    }
+
+    /// Build the context of the agent for the message: the chat, its members and the latest messages
+    /// before the given one (all latest messages if the message is not saved yet). For thread replies
+    /// the history is the thread, starting from its parent message.
+    pub(crate) async fn build_agent_context(
+        &self,
+        agent: &ChatAgent,
+        msg: &Message,
+    ) -> Result<AgentContext, AppError> {
+        let chat = self
+            .get_chat_by_id(agent.chat_id as _)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Chat id {}", agent.chat_id)))?;
+
+        let input = ListMessages {
+            last_id: (msg.id > 0).then_some(msg.id as _),
+            limit: AGENT_HISTORY_SIZE,
+            ..Default::default()
+        };
+        let mut messages = match msg.parent_id {
+            Some(parent_id) => {
+                let mut messages = self
+                    .list_thread_messages(input, chat.id as _, parent_id as _)
+                    .await?
+                    .messages;
+                if let Some(parent) = self.get_message_by_id(chat.id as _, parent_id as _).await? {
+                    messages.push(parent);
+                }
+                messages
+            }
+            None => self.list_messages(input, chat.id as _).await?.messages,
+        };
+        messages.retain(|m| m.deleted_at.is_none());
+        messages.reverse();
+
+        // agents are not chat members, load their bot users too so that replies have a sender name
+        let mut ids = chat.members.clone();
+        ids.extend(messages.iter().map(|m| m.sender_id));
+        ids.sort_unstable();
+        ids.dedup();
+        let members = self.fetch_chat_users_by_ids(&ids).await?;
+
+        Ok(AgentContext::new(chat, agent.clone(), AGENT_CONTEXT_BUDGET)
+            .with_messages(messages)
+            .with_members(members))
+    }
+
+    /// Run the proxy agents one by one on the message before it is saved.
+    ///
+    /// - `Modify` rewrites the message into `modified_content`, later agents see the rewritten content.
+    /// - `Delete` rejects the message.
+    pub(crate) async fn run_proxy_agents<A: Agent>(
+        &self,
+        agents: &[(ChatAgent, A)],
+        msg: &mut Message,
+    ) -> Result<(), AppError> {
+        for (agent, runner) in agents {
+            let ctx = self.build_agent_context(agent, msg).await?;
+            let input = Message {
+                content: msg.modified_content.clone().unwrap_or(msg.content.clone()),
+                ..msg.clone()
+            };
+            match run_agent(agent, runner, input, &ctx, AGENT_TIMEOUT).await {
+                AgentDecision::Modify(content) => msg.modified_content = Some(content),
+                AgentDecision::Delete => {
+                    return Err(AppError::CreateMessageError(format!(
+                        "Message rejected by agent {}",
+                        agent.name
+                    )));
+                }
+                AgentDecision::Reply(_) => {
+                    warn!("Proxy agent {} cannot reply, ignored", agent.name);
+                }
+                AgentDecision::None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the reply and tap agents on the saved message, returns the replies posted.
+    pub(crate) async fn run_followup_agents<A: Agent>(
+        &self,
+        agents: &[(ChatAgent, A)],
+        msg: &Message,
+    ) -> Result<Vec<Message>, AppError> {
+        let mut replies = vec![];
+        for (agent, runner) in agents {
+            // a failed agent shouldn't keep the others from running
+            let ctx = match self.build_agent_context(agent, msg).await {
+                Ok(ctx) => ctx,
+                Err(e) => {
+                    warn!("Failed to build the context of agent {}: {}", agent.name, e);
+                    continue;
+                }
+            };
+            let decision = run_agent(agent, runner, msg.clone(), &ctx, AGENT_TIMEOUT).await;
+            match (&agent.r#type, decision) {
+                (AgentType::Reply, AgentDecision::Reply(content)) => {
+                    // replies stay in the thread of the message
+                    let draft = Message {
+                        parent_id: msg.parent_id,
+                        ..Message::draft(msg.chat_id, agent.user_id, SenderType::Agent, content)
+                    };
+                    let reply = self.insert_message(&draft).await?;
+                    info!(
+                        "Agent {} replied to message {} with message {}",
+                        agent.name, msg.id, reply.id
+                    );
+                    replies.push(reply);
+                }
+                (_, AgentDecision::None) => {}
+                (_, decision) => {
+                    warn!(
+                        "Agent {}({:?}) decision {:?} is not applicable, ignored",
+                        agent.name, agent.r#type, decision
+                    );
+                }
+            }
+        }
+        Ok(replies)
+    }
+
+    /// Run the reply and tap agents in background so that they won't block message delivery.
+    pub(crate) fn spawn_followup_agents<A>(&self, agents: Vec<(ChatAgent, A)>, msg: Message)
+    where
+        A: Agent + Send + Sync + 'static,
+    {
+        if agents.is_empty() {
+            return;
+        }
+        let state = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = state.run_followup_agents(&agents, &msg).await {
+                warn!("Failed to run agents for message {}: {}", msg.id, e);
+            }
+        });
+    }
+}
+
+/// Run a single agent with timeout. Errors and timeouts are logged and treated as no decision.
+async fn run_agent<A: Agent>(
+    agent: &ChatAgent,
+    runner: &A,
+    msg: Message,
+    ctx: &AgentContext,
+    timeout: Duration,
+) -> AgentDecision {
+    info!(
+        "Agent {}({:?}) processing message {} in chat {}",
+        agent.name, agent.r#type, msg.id, msg.chat_id
+    );
+    match time::timeout(timeout, runner.process(msg, ctx)).await {
+        Ok(Ok(decision)) => {
+            info!("Agent {} decision: {:?}", agent.name, decision);
+            decision
+        }
+        Ok(Err(e)) => {
+            warn!("Agent {} failed: {}", agent.name, e);
+            AgentDecision::None
+        }
+        Err(_) => {
+            warn!("Agent {} timed out after {:?}", agent.name, timeout);
+            AgentDecision::None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateAgent, CreateMessage, UpdateAgent};
+    use ai_sdk::mock::{mock_reply, MockServer};
+    use anyhow::Result;
+    use chat_core::{AgentArgs, ModelArgs};
+
+    struct MockAgent(AgentDecision);
+
+    impl Agent for MockAgent {
+        async fn process(
+            &self,
+            _msg: Message,
+            _ctx: &AgentContext,
+        ) -> Result<AgentDecision, AgentError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    /// Appends a mark to the content it sees
+    struct MarkAgent(&'static str);
+
+    impl Agent for MarkAgent {
+        async fn process(
+            &self,
+            msg: Message,
+            _ctx: &AgentContext,
+        ) -> Result<AgentDecision, AgentError> {
+            Ok(AgentDecision::Modify(format!("{}{}", msg.content, self.0)))
+        }
+    }
+
+    struct SlowAgent;
+
+    impl Agent for SlowAgent {
+        async fn process(
+            &self,
+            _msg: Message,
+            _ctx: &AgentContext,
+        ) -> Result<AgentDecision, AgentError> {
+            time::sleep(Duration::from_secs(5)).await;
+            Ok(AgentDecision::Modify("too late".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn run_proxy_agents_should_modify_message() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let agent = state
+            .get_agent_by_id(1, 1)
+            .await?
+            .expect("agent should exist");
+
+        let mut msg = draft_message("你好");
+        let agents = vec![(
+            agent.clone(),
+            MockAgent(AgentDecision::Modify("hello".into())),
+        )];
+        state.run_proxy_agents(&agents, &mut msg).await?;
+        assert_eq!(msg.modified_content.as_deref(), Some("hello"));
+
+        let mut msg = draft_message("spam");
+        let agents = vec![(agent.clone(), MockAgent(AgentDecision::Delete))];
+        assert!(state.run_proxy_agents(&agents, &mut msg).await.is_err());
+
+        // later agents see the rewritten content
+        let mut msg = draft_message("hi");
+        let agents = vec![(agent.clone(), MarkAgent("!")), (agent, MarkAgent("?"))];
+        state.run_proxy_agents(&agents, &mut msg).await?;
+        assert_eq!(msg.content, "hi");
+        assert_eq!(msg.modified_content.as_deref(), Some("hi!?"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_followup_agents_should_post_replies() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let mut agent = state
+            .get_agent_by_id(1, 1)
+            .await?
+            .expect("agent should exist");
+        agent.r#type = AgentType::Reply;
+        let mut tap = agent.clone();
+        tap.r#type = AgentType::Tap;
+        // its chat is gone, so its context couldn't be built
+        let mut broken = agent.clone();
+        broken.chat_id = 999;
+        let bot_id = agent.user_id;
+
+        let agents = vec![
+            (broken, MockAgent(AgentDecision::Reply("lost".into()))),
+            (agent, MockAgent(AgentDecision::Reply("I am fine".into()))),
+            (tap, MockAgent(AgentDecision::Reply("ignored".into()))),
+        ];
+        let replies = state
+            .run_followup_agents(&agents, &draft_message("How are you?"))
+            .await?;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].content, "I am fine");
+        assert_eq!(replies[0].sender_id, bot_id);
+        assert_eq!(replies[0].sender_type, SenderType::Agent);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_agent_should_time_out() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let agent = state
+            .get_agent_by_id(1, 1)
+            .await?
+            .expect("agent should exist");
+
+        let msg = draft_message("hello");
+        let ctx = state.build_agent_context(&agent, &msg).await?;
+        let decision = run_agent(&agent, &SlowAgent, msg, &ctx, Duration::from_millis(10)).await;
+        assert!(matches!(decision, AgentDecision::None));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn agents_with_mock_model_should_work() -> Result<()> {
+        let server = MockServer::start().await?;
+        let (_tdb, state) =
+            AppState::try_new_for_test_with(|config| config.ai = server.config()).await?;
+
+        let args = AgentArgs::Ollama(ModelArgs {
+            model: Some("translator".to_string()),
+            ..Default::default()
+        });
+        let input = UpdateAgent::new("Translate to English", args);
+        state.update_agent(input, 1, 1).await?;
+
+        let args = AgentArgs::Ollama(ModelArgs {
+            model: Some("echo".to_string()),
+            ..Default::default()
+        });
+        let input = CreateAgent::new("echo", AgentType::Reply, "Repeat after me", args);
+        state.create_agent(input, 1).await?;
+
+        let input = CreateMessage {
+            content: "你好".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        let msg = state.create_message(input, 1, 1).await?;
+        assert_eq!(msg.content, "你好");
+        assert_eq!(msg.modified_content, Some(mock_reply("translator", "你好")));
+
+        let (_, followups) = state.load_agents(1).await?;
+        let replies = state.run_followup_agents(&followups, &msg).await?;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].content, mock_reply("echo", "你好"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn agents_with_malformed_args_should_be_invalid() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        sqlx::query(
+            r#"UPDATE chat_agents SET args = '{"adapter":"gpt","temperature":"hot"}' WHERE id = 1"#,
+        )
+        .execute(&state.pool)
+        .await?;
+
+        let (proxies, _) = state.load_agents(1).await?;
+        assert_eq!(proxies.len(), 1);
+        assert!(matches!(proxies[0].1, AgentVariant::Invalid(_)));
+
+        let agents = state.list_agents(1).await?;
+        assert!(agents[0].invalid_args.is_some());
+        let agent = state
+            .get_agent_by_id(1, 1)
+            .await?
+            .expect("agent should exist");
+        assert!(agent.invalid_args.is_some());
+
+        // the invalid agent is skipped, the message is saved as is
+        let input = CreateMessage {
+            content: "你好".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        let msg = state.create_message(input, 1, 1).await?;
+        assert_eq!(msg.content, "你好");
+        assert_eq!(msg.modified_content, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn build_agent_context_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let agent = state
+            .get_agent_by_id(1, 1)
+            .await?
+            .expect("agent should exist");
+
+        // draft message sees all the latest messages
+        let ctx = state
+            .build_agent_context(&agent, &draft_message("hello"))
+            .await?;
+        assert_eq!(ctx.chat.id, 1);
+        assert_eq!(ctx.agent.id, agent.id);
+        assert_eq!(ctx.members.len(), 5);
+        assert_eq!(ctx.messages.len(), 10);
+        assert!(ctx.messages.windows(2).all(|w| w[0].id < w[1].id));
+
+        // saved message only sees the messages before it
+        let mut msg = draft_message("hello");
+        msg.id = 5;
+        let ctx = state.build_agent_context(&agent, &msg).await?;
+        assert_eq!(ctx.messages.len(), 4);
+        assert_eq!(ctx.messages.last().map(|m| m.id), Some(4));
+
+        Ok(())
+    }
+
+    fn draft_message(content: &str) -> Message {
+        Message::draft(1, 1, SenderType::User, content)
+    }
+}
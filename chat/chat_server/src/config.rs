@@ -1,18 +1,35 @@
-use std::{env, fs::File, path::PathBuf};
+use std::{collections::HashMap, env, fs::File, net::IpAddr, path::PathBuf};
 
+use ai_sdk::AiConfig;
 use anyhow::{bail, Result};
+use chat_core::middlewares::RateLimit;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub ai: AiConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// OpenID Connect providers users could sign in with
+    #[serde(default)]
+    pub oidc: Vec<OidcProviderConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub sk: String,
     pub pk: String,
+    /// public keys before the key rotation, tokens signed with them are still valid
+    #[serde(default)]
+    pub prev_pks: Vec<String>,
+    /// users couldn't sign in until their emails are verified
+    #[serde(default)]
+    pub require_verified_email: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +39,172 @@ pub struct ServerConfig {
     pub base_dir: PathBuf,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MailConfig {
+    /// sender of the mails
+    #[serde(default = "default_mail_from")]
+    pub from: String,
+    /// url of the web app, links in the mails point to it
+    #[serde(default = "default_app_url")]
+    pub app_url: String,
+    /// send mails with SMTP if set
+    #[serde(default)]
+    pub smtp: Option<SmtpConfig>,
+    /// otherwise append mails to the file, or print them to stdout if not set either
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// STARTTLS is required on the port
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// requests per client ip to the unauthenticated auth endpoints, like signin and signup
+    #[serde(default = "default_auth_limit")]
+    pub auth: RateLimit,
+    /// messages sent per user
+    #[serde(default = "default_message_limit")]
+    pub message: RateLimit,
+    /// messages posted per incoming webhook
+    #[serde(default = "default_webhook_limit")]
+    pub webhook: RateLimit,
+    /// requests per client ip to the incoming webhooks, including the ones with unknown tokens
+    #[serde(default = "default_webhook_ip_limit")]
+    pub webhook_ip: RateLimit,
+    #[serde(default)]
+    pub lockout: LockoutConfig,
+    /// reverse proxies in front of the server, the client ip is taken from the
+    /// `X-Forwarded-For` or `X-Real-IP` headers of their requests
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// Failed signins of an email from a client lock it out for the client for `base_secs`, doubled
+/// on every further failure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockoutConfig {
+    /// failures allowed before the lockout
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+    #[serde(default = "default_lockout_base_secs")]
+    pub base_secs: u64,
+    /// the longest lockout, failures older than it are forgotten as well
+    #[serde(default = "default_lockout_max_secs")]
+    pub max_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcProviderConfig {
+    /// id of the provider in the urls, like `/api/oidc/{name}/authorize`
+    pub name: String,
+    /// the discovery document is fetched from `{issuer}/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    /// public clients rely on PKCE only
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// the callback page of the web app registered at the provider
+    pub redirect_url: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+    /// claim of the tenant in the ID token, e.g. `tid` of Azure AD or `hd` of Google
+    #[serde(default)]
+    pub tenant_claim: Option<String>,
+    /// workspace names keyed by the tenant or the email domain, the tenant is matched first
+    #[serde(default)]
+    pub workspaces: HashMap<String, String>,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            from: default_mail_from(),
+            app_url: default_app_url(),
+            smtp: None,
+            file: None,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            auth: default_auth_limit(),
+            message: default_message_limit(),
+            webhook: default_webhook_limit(),
+            webhook_ip: default_webhook_ip_limit(),
+            lockout: LockoutConfig::default(),
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: default_max_failures(),
+            base_secs: default_lockout_base_secs(),
+            max_secs: default_lockout_max_secs(),
+        }
+    }
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
+}
+
+fn default_mail_from() -> String {
+    "Chat <noreply@chat.local>".to_string()
+}
+
+fn default_app_url() -> String {
+    "http://localhost:1420".to_string()
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_auth_limit() -> RateLimit {
+    RateLimit::new(10, 10)
+}
+
+fn default_message_limit() -> RateLimit {
+    RateLimit::new(30, 60)
+}
+
+fn default_webhook_limit() -> RateLimit {
+    RateLimit::new(20, 60)
+}
+
+fn default_webhook_ip_limit() -> RateLimit {
+    RateLimit::new(60, 120)
+}
+
+fn default_max_failures() -> u32 {
+    5
+}
+
+fn default_lockout_base_secs() -> u64 {
+    30
+}
+
+fn default_lockout_max_secs() -> u64 {
+    60 * 15
+}
+
 impl AppConfig {
     pub fn try_load() -> Result<Self> {
         // read from ./app.yml, or /etc/config/app.yml, or from env CHAT_CONFIG
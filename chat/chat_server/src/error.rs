@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Json, Response},
 };
 use serde::{Deserialize, Serialize};
@@ -22,9 +22,66 @@ pub enum AppError {
     #[error("update chat error: {0}")]
     UpdateChatError(String),
 
+    #[error("create agent error: {0}")]
+    CreateAgentError(String),
+
+    #[error("update agent error: {0}")]
+    UpdateAgentError(String),
+
     #[error("create message error: {0}")]
     CreateMessageError(String),
 
+    #[error("list messages error: {0}")]
+    ListMessagesError(String),
+
+    #[error("update message error: {0}")]
+    UpdateMessageError(String),
+
+    #[error("reaction error: {0}")]
+    ReactionError(String),
+
+    #[error("search error: {0}")]
+    SearchError(String),
+
+    #[error("invite error: {0}")]
+    InviteError(String),
+
+    #[error("update workspace error: {0}")]
+    UpdateWorkspaceError(String),
+
+    #[error("session error: {0}")]
+    SessionError(String),
+
+    #[error("invalid token: {0}")]
+    UserTokenError(String),
+
+    #[error("api token error: {0}")]
+    ApiTokenError(String),
+
+    #[error("webhook error: {0}")]
+    WebhookError(String),
+
+    #[error("email not verified: {0}")]
+    EmailNotVerified(String),
+
+    #[error("mfa error: {0}")]
+    MfaError(String),
+
+    #[error("invalid mfa code: {0}")]
+    InvalidMfaCode(String),
+
+    #[error("oidc error: {0}")]
+    OidcError(String),
+
+    #[error("too many requests, retry after {0} seconds")]
+    TooManyRequests(u64),
+
+    #[error("mail error: {0}")]
+    MailError(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
     #[error("chat file error: {0}")]
     ChatFileError(String),
 
@@ -61,7 +118,26 @@ impl IntoResponse for AppError {
             Self::EmailAlreadyExists(_) => StatusCode::CONFLICT,
             Self::CreateChatError(_) => StatusCode::BAD_REQUEST,
             Self::UpdateChatError(_) => StatusCode::BAD_REQUEST,
+            Self::CreateAgentError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateAgentError(_) => StatusCode::BAD_REQUEST,
             Self::CreateMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::ListMessagesError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::ReactionError(_) => StatusCode::BAD_REQUEST,
+            Self::SearchError(_) => StatusCode::BAD_REQUEST,
+            Self::InviteError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateWorkspaceError(_) => StatusCode::BAD_REQUEST,
+            Self::SessionError(_) => StatusCode::UNAUTHORIZED,
+            Self::UserTokenError(_) => StatusCode::BAD_REQUEST,
+            Self::ApiTokenError(_) => StatusCode::BAD_REQUEST,
+            Self::WebhookError(_) => StatusCode::BAD_REQUEST,
+            Self::EmailNotVerified(_) => StatusCode::FORBIDDEN,
+            Self::MfaError(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidMfaCode(_) => StatusCode::UNAUTHORIZED,
+            Self::OidcError(_) => StatusCode::UNAUTHORIZED,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::MailError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
             Self::ChatFileError(_) => StatusCode::BAD_REQUEST,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -71,6 +147,10 @@ impl IntoResponse for AppError {
             Self::HttpHeaderError(_) => StatusCode::UNPROCESSABLE_ENTITY,
         };
 
-        (status, Json(ErrorOutput::new(self.to_string()))).into_response()
+        let mut resp = (status, Json(ErrorOutput::new(self.to_string()))).into_response();
+        if let Self::TooManyRequests(secs) = self {
+            resp.headers_mut().insert(RETRY_AFTER, secs.into());
+        }
+        resp
     }
 }
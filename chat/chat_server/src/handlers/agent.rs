@@ -0,0 +1,104 @@
+use crate::{AppError, AppState, CreateAgent, ErrorOutput, UpdateAgent};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chat_core::ChatAgent;
+
+/// List all agents in the chat.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/agents",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "List of agents", body = Vec<ChatAgent>)
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_agent_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let agents = state.list_agents(id).await?;
+    Ok(Json(agents))
+}
+
+/// Create a new agent in the chat.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/agents",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 201, description = "Agent created", body = ChatAgent),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_agent_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateAgent>,
+) -> Result<impl IntoResponse, AppError> {
+    let agent = state.create_agent(input, id).await?;
+    Ok((StatusCode::CREATED, Json(agent)))
+}
+
+/// Update the agent's prompt and args.
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/agents/{agent_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("agent_id" = u64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Agent updated", body = ChatAgent),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 404, description = "Agent not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_agent_handler(
+    State(state): State<AppState>,
+    Path((id, agent_id)): Path<(u64, u64)>,
+    Json(input): Json<UpdateAgent>,
+) -> Result<impl IntoResponse, AppError> {
+    let agent = state.update_agent(input, id, agent_id).await?;
+    Ok((StatusCode::OK, Json(agent)))
+}
+
+/// Delete the agent from the chat.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/agents/{agent_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("agent_id" = u64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Agent deleted"),
+        (status = 404, description = "Agent not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_agent_handler(
+    State(state): State<AppState>,
+    Path((id, agent_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_agent(id, agent_id).await?;
+    Ok(StatusCode::OK)
+}
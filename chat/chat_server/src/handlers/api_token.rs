@@ -0,0 +1,76 @@
+use crate::{ApiToken, AppError, AppState, CreateApiToken, CreatedApiToken, ErrorOutput};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+/// List the API tokens of the user.
+#[utoipa::path(
+    get,
+    path = "/api/tokens",
+    responses(
+        (status = 200, description = "List of API tokens", body = Vec<ApiToken>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_api_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let tokens = state.list_api_tokens(user.id as _).await?;
+    Ok(Json(tokens))
+}
+
+/// Create an API token in the current workspace, for scripts to act as the user.
+///
+/// - The token is shown only once, use it as the bearer token.
+/// - The token could only do what its scopes allow, it never expires if `expires_in_days` is not set.
+#[utoipa::path(
+    post,
+    path = "/api/tokens",
+    responses(
+        (status = 201, description = "API token created", body = CreatedApiToken),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not available to API tokens", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_api_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateApiToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = state.create_api_token(&user, input).await?;
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+/// Revoke the API token, it is rejected right away.
+#[utoipa::path(
+    delete,
+    path = "/api/tokens/{id}",
+    params(
+        ("id" = u64, Path, description = "API token id")
+    ),
+    responses(
+        (status = 204, description = "API token revoked"),
+        (status = 404, description = "API token not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_api_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_api_token(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
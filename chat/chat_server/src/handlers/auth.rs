@@ -1,23 +1,55 @@
-use crate::{models::SigninUser, AppError, AppState, CreateUser, ErrorOutput};
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use crate::{
+    models::SigninUser, AppError, AppState, CreateUser, ErrorOutput, ForgotPassword, MfaChallenge,
+    RefreshSession, ResetPassword, SigninMfa, VerifyEmail,
+};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use chat_core::{middlewares::ClientIp, Jwks, SessionId, User, JWT_DURATION};
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 use utoipa::ToSchema;
 
 #[derive(Debug, ToSchema, Serialize, Deserialize)]
 pub struct AuthOutput {
-    token: String,
+    /// short-lived access token
+    pub(crate) token: String,
+    /// exchange it for new tokens with `/api/refresh`, it could be used only once
+    pub(crate) refresh_token: String,
+    /// lifetime of the access token, in seconds
+    pub(crate) expires_in: u64,
+}
+
+impl AuthOutput {
+    /// Sign an access token of the session for the user
+    pub(crate) fn new(
+        state: &AppState,
+        user: User,
+        session_id: &SessionId,
+        refresh_token: String,
+    ) -> Result<Self, AppError> {
+        Ok(Self {
+            token: state.ek.sign(user, session_id)?,
+            refresh_token,
+            expires_in: JWT_DURATION,
+        })
+    }
 }
 
 /// Create a new user in the chat system with email, password workspace and full name.
 ///
 /// - If the email already exists, it will return 409.
-/// - Otherwise, it will return 201 with a token.
-/// - If the workspace doesn't exist, it will create one.
+/// - Otherwise, it will return 201 with a token, and a verification mail is sent.
+///   If verified emails are required to sign in, it will return 202 without a token.
+/// - If an invite is given, the user joins the workspace of the invite.
+/// - Otherwise, if the workspace doesn't exist, it will create one and the user owns it.
+///   Joining an existing workspace without an invite will return 403.
 #[utoipa::path(
     post,
     path = "/api/signup",
     responses(
-        (status = 201, description = "User created", body = AuthOutput)
+        (status = 201, description = "User created", body = AuthOutput),
+        (status = 202, description = "User created, email verification required"),
+        (status = 400, description = "Invalid invite", body = ErrorOutput),
+        (status = 403, description = "Invite required", body = ErrorOutput),
     )
 )]
 pub(crate) async fn signup_handler(
@@ -25,42 +57,210 @@ pub(crate) async fn signup_handler(
     Json(input): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.create_user(&input).await?;
-    let token = state.ek.sign(user)?;
-    let body = Json(AuthOutput { token });
-    Ok((StatusCode::CREATED, body))
+    if let Err(e) = state.send_verification_email(&user).await {
+        warn!("Failed to send verification mail to {}: {}", user.email, e);
+    }
+    if state.config.auth.require_verified_email {
+        return Ok(StatusCode::ACCEPTED.into_response());
+    }
+
+    let (session_id, refresh_token) = state.create_session(user.id as _).await?;
+    let body = Json(AuthOutput::new(&state, user, &session_id, refresh_token)?);
+    Ok((StatusCode::CREATED, body).into_response())
 }
 
 /// Sign in a user with email and password.
+///
+/// - If verified emails are required and the email of the user is not verified yet,
+///   it will return 403 and send a verification mail again.
+/// - If the user has enabled 2FA, it will return 202 with an MFA challenge instead of the
+///   tokens, complete it at `/api/signin/mfa`.
+/// - Too many failed signins lock the email out for the client ip for a while, longer on every
+///   further failure. It will return 429 with `Retry-After` until the lockout ends.
 #[utoipa::path(
     post,
     path = "/api/signin",
     responses(
-        (status = 200, description = "User signed in", body = AuthOutput)
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 202, description = "Second factor required", body = MfaChallenge),
+        (status = 403, description = "Invalid email or password, or email not verified", body = ErrorOutput),
+        (status = 429, description = "Too many failed signins or requests", body = ErrorOutput),
     )
 )]
 pub(crate) async fn signin_handler(
     State(state): State<AppState>,
+    client_ip: Option<Extension<ClientIp>>,
     Json(input): Json<SigninUser>,
 ) -> Result<impl IntoResponse, AppError> {
+    let ip = client_ip.map(|Extension(ClientIp(ip))| ip);
+    state.lockout.check(&input.email, ip)?;
     let user = state.verify_user(&input).await?;
 
     match user {
         Some(user) => {
-            let token = state.ek.sign(user)?;
-            Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+            state.lockout.succeed(&input.email, ip);
+            if state.config.auth.require_verified_email
+                && !state.is_email_verified(user.id as _).await?
+            {
+                state.send_verification_email(&user).await?;
+                return Err(AppError::EmailNotVerified(user.email));
+            }
+            if state.is_totp_enabled(user.id as _).await? {
+                let challenge = state.create_mfa_challenge(user.id as _).await?;
+                return Ok((StatusCode::ACCEPTED, Json(challenge)).into_response());
+            }
+            let (session_id, refresh_token) = state.create_session(user.id as _).await?;
+            let output = AuthOutput::new(&state, user, &session_id, refresh_token)?;
+            Ok((StatusCode::OK, Json(output)).into_response())
+        }
+        None => {
+            state.lockout.fail(&input.email, ip);
+            Ok((
+                StatusCode::FORBIDDEN,
+                Json(ErrorOutput::new("Invalid email or password")),
+            )
+                .into_response())
         }
-        None => Ok((
-            StatusCode::FORBIDDEN,
-            Json(ErrorOutput::new("Invalid email or password")),
-        )
-            .into_response()),
     }
 }
 
+/// Complete the signin with a TOTP code or a recovery code.
+///
+/// Wrong codes count towards the signin lockout, the challenge could be retried until then.
+#[utoipa::path(
+    post,
+    path = "/api/signin/mfa",
+    responses(
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 400, description = "Invalid or expired challenge", body = ErrorOutput),
+        (status = 401, description = "Invalid code", body = ErrorOutput),
+        (status = 429, description = "Too many failed signins", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn signin_mfa_handler(
+    State(state): State<AppState>,
+    Json(input): Json<SigninMfa>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.complete_mfa_challenge(&input).await?;
+    let (session_id, refresh_token) = state.create_session(user.id as _).await?;
+    let output = AuthOutput::new(&state, user, &session_id, refresh_token)?;
+    Ok(Json(output))
+}
+
+/// Exchange the refresh token for a new access token and refresh token.
+///
+/// - The refresh token rotates, the previous one couldn't be used any more.
+/// - Reusing a rotated refresh token revokes the whole session.
+#[utoipa::path(
+    post,
+    path = "/api/refresh",
+    responses(
+        (status = 200, description = "Tokens refreshed", body = AuthOutput),
+        (status = 401, description = "Invalid refresh token or session revoked", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn refresh_handler(
+    State(state): State<AppState>,
+    Json(input): Json<RefreshSession>,
+) -> Result<impl IntoResponse, AppError> {
+    let (user, session_id, refresh_token) = state.refresh_session(&input.refresh_token).await?;
+    let output = AuthOutput::new(&state, user, &session_id, refresh_token)?;
+    Ok(Json(output))
+}
+
+/// Mail a password reset link to the user.
+///
+/// It always returns 202, whether the email is registered or not.
+#[utoipa::path(
+    post,
+    path = "/api/password/forgot",
+    responses(
+        (status = 202, description = "Password reset mail sent if the user exists"),
+    )
+)]
+pub(crate) async fn forgot_password_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ForgotPassword>,
+) -> impl IntoResponse {
+    state.forgot_password(&input);
+    StatusCode::ACCEPTED
+}
+
+/// Set a new password with the token in the password reset mail.
+///
+/// The token could be used only once, and all sessions of the user are signed out.
+#[utoipa::path(
+    post,
+    path = "/api/password/reset",
+    responses(
+        (status = 204, description = "Password reset"),
+        (status = 400, description = "Invalid, used or expired token", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn reset_password_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ResetPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.reset_password(&input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Verify the email of the user with the token in the verification mail.
+#[utoipa::path(
+    post,
+    path = "/api/email/verify",
+    responses(
+        (status = 204, description = "Email verified"),
+        (status = 400, description = "Invalid, used or expired token", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn verify_email_handler(
+    State(state): State<AppState>,
+    Json(input): Json<VerifyEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_email(&input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Public keys to verify access tokens, looked up by the `kid` header of the token.
+/// It includes the previous keys, so tokens signed before a key rotation keep working.
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "JSON Web Key Set", body = Jwks),
+    )
+)]
+pub(crate) async fn jwks_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.dk.jwks())
+}
+
+/// Sign out the current session, its tokens are rejected from now on.
+#[utoipa::path(
+    post,
+    path = "/api/signout",
+    responses(
+        (status = 204, description = "Signed out"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn signout_handler(
+    Extension(user): Extension<User>,
+    Extension(session_id): Extension<SessionId>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_session(&session_id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use anyhow::Result;
+    use axum::http::header::RETRY_AFTER;
+    use chat_core::middlewares::TokenVerify;
     use http_body_util::BodyExt as _;
 
     #[tokio::test]
@@ -106,6 +306,20 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_signup_existing_workspace_without_invite_should_403() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = CreateUser::new("acme", "eve@acme.org", "Eve Chen", "hunter42");
+
+        let ret = signup_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn signin_should_work() -> Result<()> {
         let (_tdb, state) = AppState::try_new_for_test().await?;
@@ -114,7 +328,7 @@ mod tests {
         let password = "123456";
         let input = SigninUser::new(email, password);
 
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state), None, Json(input))
             .await?
             .into_response();
         assert_eq!(ret.status(), StatusCode::OK);
@@ -122,6 +336,114 @@ mod tests {
         let body = ret.into_body().collect().await?.to_bytes();
         let ret: AuthOutput = serde_json::from_slice(&body)?;
         assert_ne!(ret.token, "");
+        assert_ne!(ret.refresh_token, "");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_signins_should_lock_out() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let attacker = || Some(Extension(ClientIp("203.0.113.1".parse().unwrap())));
+
+        let max_failures = state.config.rate_limit.lockout.max_failures;
+        for _ in 0..max_failures {
+            let input = SigninUser::new("tchen@acme.org", "bad-password");
+            let ret = signin_handler(State(state.clone()), attacker(), Json(input))
+                .await?
+                .into_response();
+            assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        }
+
+        // even the right password is rejected during the lockout
+        let input = SigninUser::new("tchen@acme.org", "123456");
+        let ret = signin_handler(State(state.clone()), attacker(), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(ret.headers().contains_key(RETRY_AFTER));
+
+        // other clients could still sign in
+        let victim = Some(Extension(ClientIp("198.51.100.1".parse()?)));
+        let input = SigninUser::new("tchen@acme.org", "123456");
+        let ret = signin_handler(State(state), victim, Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_forgot_password_should_not_tell_registered_emails() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        for email in ["tchen@acme.org", "nobody@acme.org"] {
+            let input = ForgotPassword {
+                email: email.to_string(),
+            };
+            let ret = forgot_password_handler(State(state.clone()), Json(input))
+                .await
+                .into_response();
+            assert_eq!(ret.status(), StatusCode::ACCEPTED);
+            let body = ret.into_body().collect().await?.to_bytes();
+            assert!(body.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jwks_should_contain_signing_key() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let ret = jwks_handler(State(state.clone())).await.into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let body = ret.into_body().collect().await?.to_bytes();
+        let jwks: Jwks = serde_json::from_slice(&body)?;
+        assert_eq!(jwks.keys[0].kid, state.ek.kid());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_and_signout_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = SigninUser::new("tchen@acme.org", "123456");
+        let ret = signin_handler(State(state.clone()), None, Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let auth: AuthOutput = serde_json::from_slice(&body)?;
+
+        let input = RefreshSession {
+            refresh_token: auth.refresh_token.clone(),
+        };
+        let ret = refresh_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let refreshed: AuthOutput = serde_json::from_slice(&body)?;
+        assert_ne!(refreshed.refresh_token, auth.refresh_token);
+
+        let (user, session_id) = state.verify(&refreshed.token)?;
+        let ret = signout_handler(Extension(user), Extension(session_id), State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        // both the access token and the refresh token are rejected after signing out
+        assert!(state.verify(&refreshed.token).is_err());
+        let input = RefreshSession {
+            refresh_token: refreshed.refresh_token,
+        };
+        let ret = refresh_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
 
         Ok(())
     }
@@ -134,7 +456,7 @@ mod tests {
         let password = "hunter42";
         let input = SigninUser::new(email, password);
 
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state), None, Json(input))
             .await
             .into_response();
         assert_eq!(ret.status(), StatusCode::FORBIDDEN);
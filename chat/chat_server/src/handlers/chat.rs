@@ -1,18 +1,21 @@
-use crate::{AppError, AppState, CreateChat, ErrorOutput, UpdateChat};
+use crate::{
+    AppError, AppState, ChatSummary, CreateChat, ErrorOutput, MarkRead, Permission, UpdateChat,
+    UpdateChatRole,
+};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::IntoResponse,
     Extension, Json,
 };
-use chat_core::{Chat, User};
+use chat_core::{Chat, ChatReadState, User};
 
-/// List all chats in the workspace of the user.
+/// List all chats of the user in the workspace, with unread counts and the last message.
 #[utoipa::path(
     get,
     path = "/api/chats",
     responses(
-        (status = 200, description = "List of chats", body = Vec<Chat>)
+        (status = 200, description = "List of chats", body = Vec<ChatSummary>)
     ),
     security(
         ("token" = [])
@@ -22,8 +25,10 @@ pub(crate) async fn list_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.fetch_chats(user.id as _, user.ws_id as _).await?;
-    Ok((StatusCode::OK, Json(chat)))
+    let chats = state
+        .fetch_chat_summaries(user.id as _, user.ws_id as _)
+        .await?;
+    Ok((StatusCode::OK, Json(chats)))
 }
 
 /// Create a new chat in the workspace of the user.
@@ -74,7 +79,9 @@ pub(crate) async fn get_chat_handler(
     }
 }
 
-/// Update the chat info by id.
+/// Update the chat info by id, for workspace owner/admins and chat creator/admins.
+///
+/// - Removing other members from the chat needs the permission to remove members.
 #[utoipa::path(
     patch,
     path = "/api/chats/{id}",
@@ -83,6 +90,7 @@ pub(crate) async fn get_chat_handler(
     ),
     responses(
         (status = 200, description = "Chat updated", body = Chat),
+        (status = 403, description = "Permission denied", body = ErrorOutput),
         (status = 404, description = "Chat not found", body = ErrorOutput),
     ),
     security(
@@ -90,15 +98,30 @@ pub(crate) async fn get_chat_handler(
     )
 )]
 pub(crate) async fn update_chat_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<u64>,
     Json(input): Json<UpdateChat>,
 ) -> Result<impl IntoResponse, AppError> {
+    let chat = state
+        .get_chat_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Chat id {id}")))?;
+    if chat
+        .members
+        .iter()
+        .any(|m| *m != user.id && !input.members.contains(m))
+    {
+        state
+            .check_chat_permission(id, user.id as _, Permission::RemoveMembers)
+            .await?;
+    }
+
     let chat = state.update_chat_by_id(id, input).await?;
     Ok((StatusCode::OK, Json(chat)))
 }
 
-/// Delete the chat by id.
+/// Delete the chat by id, for workspace owner/admins and the chat creator.
 #[utoipa::path(
     delete,
     path = "/api/chats/{id}",
@@ -107,6 +130,7 @@ pub(crate) async fn update_chat_handler(
     ),
     responses(
         (status = 200, description = "Chat deleted", body = Chat),
+        (status = 403, description = "Permission denied", body = ErrorOutput),
         (status = 404, description = "Chat not found", body = ErrorOutput),
     ),
     security(
@@ -120,3 +144,77 @@ pub(crate) async fn delete_chat_handler(
     state.delete_chat_by_id(id).await?;
     Ok(StatusCode::OK)
 }
+
+/// Grant or revoke the chat admin role of a member, for workspace owner/admins and the chat creator.
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/members/{user_id}/role",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("user_id" = u64, Path, description = "Member id")
+    ),
+    responses(
+        (status = 200, description = "Role of the member", body = UpdateChatRole),
+        (status = 400, description = "Invalid role", body = ErrorOutput),
+        (status = 403, description = "Permission denied", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_chat_role_handler(
+    State(state): State<AppState>,
+    Path((id, user_id)): Path<(u64, u64)>,
+    Json(input): Json<UpdateChatRole>,
+) -> Result<impl IntoResponse, AppError> {
+    let role = state.update_chat_role(input, id, user_id).await?;
+    Ok(Json(UpdateChatRole { role }))
+}
+
+/// Mark the chat read up to a message.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/read",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Read state of the user", body = ChatReadState),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn mark_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+    let read = state.mark_chat_read(input, id, user.id as _).await?;
+    Ok((StatusCode::OK, Json(read)))
+}
+
+/// List the read states of the chat members.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/read",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Read states of the members", body = Vec<ChatReadState>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_read_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let states = state.list_read_states(id).await?;
+    Ok(Json(states))
+}
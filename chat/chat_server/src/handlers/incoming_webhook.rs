@@ -0,0 +1,154 @@
+use crate::{
+    AppError, AppState, CreateIncomingWebhook, CreatedIncomingWebhook, ErrorOutput,
+    IncomingWebhook, WebhookMessage,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{Message, User};
+
+/// List the incoming webhooks of the chat.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/webhooks",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "List of incoming webhooks", body = Vec<IncomingWebhook>),
+        (status = 403, description = "Not allowed to manage webhooks", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_incoming_webhook_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhooks = state.list_incoming_webhooks(id).await?;
+    Ok(Json(webhooks))
+}
+
+/// Create an incoming webhook for external systems to post into the chat.
+///
+/// - The url is shown only once, anyone with it could post into the chat.
+/// - Messages are posted as a bot user named after the webhook.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/webhooks",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 201, description = "Incoming webhook created", body = CreatedIncomingWebhook),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not allowed to manage webhooks", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_incoming_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateIncomingWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhook = state
+        .create_incoming_webhook(input, id, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+/// Revoke the incoming webhook, its url is rejected right away.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/webhooks/{webhook_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("webhook_id" = u64, Path, description = "Incoming webhook id")
+    ),
+    responses(
+        (status = 204, description = "Incoming webhook revoked"),
+        (status = 403, description = "Not allowed to manage webhooks", body = ErrorOutput),
+        (status = 404, description = "Incoming webhook not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_incoming_webhook_handler(
+    State(state): State<AppState>,
+    Path((id, webhook_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_incoming_webhook(id, webhook_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Post a message into the chat of the webhook, the token in the url is the credential.
+#[utoipa::path(
+    post,
+    path = "/api/hooks/{token}",
+    params(
+        ("token" = String, Path, description = "Token of the incoming webhook")
+    ),
+    responses(
+        (status = 201, description = "Message posted", body = Message),
+        (status = 400, description = "Invalid payload", body = ErrorOutput),
+        (status = 404, description = "Unknown or revoked webhook", body = ErrorOutput),
+        (status = 429, description = "Too many messages", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn post_webhook_message_handler(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Json(input): Json<WebhookMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let message = state.post_webhook_message(&token, input).await?;
+    Ok((StatusCode::CREATED, Json(message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{get_router, AppState};
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        extract::{ConnectInfo, Request},
+        http::StatusCode,
+    };
+    use chat_core::middlewares::RateLimit;
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_webhook_route_should_be_limited_per_ip() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test_with(|config| {
+            config.rate_limit.webhook_ip = RateLimit::new(2, 1);
+        })
+        .await?;
+        let app = get_router(state).await?;
+
+        let req = |ip: &str| {
+            Request::post("/api/hooks/unknown-token")
+                .header("content-type", "application/json")
+                .extension(ConnectInfo(SocketAddr::new(ip.parse().unwrap(), 443)))
+                .body(Body::from(r#"{"content": "hi"}"#))
+        };
+        for _ in 0..2 {
+            let resp = app.clone().oneshot(req("203.0.113.1")?).await?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+        let resp = app.clone().oneshot(req("203.0.113.1")?).await?;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let resp = app.oneshot(req("198.51.100.1")?).await?;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{AppError, AppState, CreateInvite, ErrorOutput, WorkspaceInvite};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+/// List all invites of the workspace, only for the workspace owner.
+#[utoipa::path(
+    get,
+    path = "/api/invites",
+    responses(
+        (status = 200, description = "List of invites", body = Vec<WorkspaceInvite>),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let invites = state.list_invites(user.ws_id as _, user.id as _).await?;
+    Ok(Json(invites))
+}
+
+/// Create an invite to the workspace, only for the workspace owner.
+///
+/// - If email is set, only the user signing up with the email could accept it.
+/// - The invite expires in 72 hours by default.
+#[utoipa::path(
+    post,
+    path = "/api/invites",
+    responses(
+        (status = 201, description = "Invite created", body = WorkspaceInvite),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    let invite = state
+        .create_invite(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+/// Revoke the invite, only for the workspace owner.
+#[utoipa::path(
+    delete,
+    path = "/api/invites/{id}",
+    params(
+        ("id" = u64, Path, description = "Invite id")
+    ),
+    responses(
+        (status = 200, description = "Invite revoked", body = WorkspaceInvite),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Invite not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let invite = state
+        .revoke_invite(user.ws_id as _, id, user.id as _)
+        .await?;
+    Ok(Json(invite))
+}
@@ -5,9 +5,12 @@ use axum::{
     Extension, Json,
 };
 use tokio::fs::{self};
-use tracing::{info, warn};
+use tracing::warn;
 
-use crate::{AppError, AppState, ChatFile, CreateMessage, ErrorOutput, ListMessages};
+use crate::{
+    AppError, AppState, ChatFile, CreateMessage, ErrorOutput, ListMessages, MessagePage,
+    UpdateMessage,
+};
 use chat_core::{Message, User};
 
 /// Send a new message in the chat.
@@ -20,6 +23,7 @@ use chat_core::{Message, User};
     responses(
         (status = 201, description = "Message send", body = Message),
         (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 429, description = "Too many messages"),
     ),
     security(
         ("token" = [])
@@ -44,7 +48,7 @@ pub(crate) async fn send_message_handler(
         ListMessages
     ),
     responses(
-        (status = 200, description = "List of messages", body = Vec<Message>),
+        (status = 200, description = "Page of messages", body = MessagePage),
         (status = 400, description = "Invalid input", body = ErrorOutput),
     ),
     security(
@@ -60,12 +64,98 @@ pub(crate) async fn list_message_handler(
     Ok(Json(msgs))
 }
 
+/// List the replies in the thread of the message.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{mid}/thread",
+    params(
+        ("id" = u64, Path, description = "Chat ID"),
+        ("mid" = u64, Path, description = "Message ID"),
+        ListMessages
+    ),
+    responses(
+        (status = 200, description = "Page of thread replies", body = MessagePage),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_thread_handler(
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let msgs = state.list_thread_messages(input, id, mid).await?;
+    Ok(Json(msgs))
+}
+
+/// Edit the content of the message, only the sender could edit it.
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/messages/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat ID"),
+        ("mid" = u64, Path, description = "Message ID")
+    ),
+    responses(
+        (status = 200, description = "Message updated", body = Message),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not the sender of the message", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+    Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let msg = state.update_message(input, id, mid, user.id as _).await?;
+    Ok((StatusCode::OK, Json(msg)))
+}
+
+/// Delete the message, only the sender could delete it.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat ID"),
+        ("mid" = u64, Path, description = "Message ID")
+    ),
+    responses(
+        (status = 200, description = "Message deleted"),
+        (status = 403, description = "Not the sender of the message", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_message(id, mid, user.id as _).await?;
+    Ok(StatusCode::OK)
+}
+
 pub(crate) async fn file_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path((ws_id, path)): Path<(i64, String)>,
 ) -> Result<impl IntoResponse, AppError> {
-    if user.ws_id != ws_id {
+    if user.ws_id != ws_id
+        || state
+            .get_workspace_role(ws_id as _, user.id as _)
+            .await?
+            .is_none()
+    {
         return Err(AppError::NotFound(
             "File not found or you don't have access".to_string(),
         ));
@@ -102,14 +192,7 @@ pub(crate) async fn upload_handler(
         };
 
         let file = ChatFile::new(ws_id, &filename, &data);
-        let path = file.path(base_dir);
-        if path.exists() {
-            info!("File {} already exists: {:?}", filename, path);
-        } else {
-            fs::create_dir_all(path.parent().expect("File path parent should exists")).await?;
-            fs::write(path, data).await?;
-        }
-
+        file.save(base_dir, &data).await?;
         files.push(file.url());
     }
 
@@ -0,0 +1,69 @@
+use crate::{AppError, AppState, ErrorOutput, RecoveryCodes, TotpCode, TotpSetup};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use chat_core::User;
+
+/// Generate a TOTP secret for the user, it takes effect once enabled with a code.
+#[utoipa::path(
+    post,
+    path = "/api/mfa/totp",
+    responses(
+        (status = 200, description = "TOTP secret generated", body = TotpSetup),
+        (status = 400, description = "TOTP already enabled", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn setup_totp_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let setup = state.setup_totp(&user).await?;
+    Ok(Json(setup))
+}
+
+/// Enable TOTP with a code from the authenticator, returns the recovery codes.
+///
+/// The recovery codes are shown only once, each could be used once instead of a TOTP code.
+#[utoipa::path(
+    post,
+    path = "/api/mfa/totp/enable",
+    responses(
+        (status = 200, description = "TOTP enabled", body = RecoveryCodes),
+        (status = 400, description = "TOTP not set up or already enabled", body = ErrorOutput),
+        (status = 401, description = "Invalid code", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn enable_totp_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TotpCode>,
+) -> Result<impl IntoResponse, AppError> {
+    let codes = state.enable_totp(&user, &input.code).await?;
+    Ok(Json(codes))
+}
+
+/// Disable TOTP with a TOTP code or a recovery code.
+#[utoipa::path(
+    post,
+    path = "/api/mfa/totp/disable",
+    responses(
+        (status = 204, description = "TOTP disabled"),
+        (status = 400, description = "TOTP not enabled", body = ErrorOutput),
+        (status = 401, description = "Invalid code", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn disable_totp_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TotpCode>,
+) -> Result<impl IntoResponse, AppError> {
+    state.disable_totp(&user, &input.code).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
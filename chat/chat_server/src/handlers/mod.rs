@@ -1,13 +1,31 @@
+mod agent;
+mod api_token;
 mod auth;
 mod chat;
+mod incoming_webhook;
+mod invite;
 mod messages;
+mod mfa;
+mod oidc;
+mod reaction;
+mod search;
+mod subscription;
 mod workspace;
 
 use axum::response::IntoResponse;
 
+pub(crate) use agent::*;
+pub(crate) use api_token::*;
 pub(crate) use auth::*;
 pub(crate) use chat::*;
+pub(crate) use incoming_webhook::*;
+pub(crate) use invite::*;
 pub(crate) use messages::*;
+pub(crate) use mfa::*;
+pub(crate) use oidc::*;
+pub(crate) use reaction::*;
+pub(crate) use search::*;
+pub(crate) use subscription::*;
 pub(crate) use workspace::*;
 
 pub(crate) async fn index_handler() -> impl IntoResponse {
@@ -0,0 +1,138 @@
+use crate::{
+    handlers::AuthOutput, AppError, AppState, ErrorOutput, MfaChallenge, OidcAuthorize,
+    OidcAuthorizeOutput, OidcCallback, OidcProvider,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+/// List the OpenID Connect providers users could sign in with.
+#[utoipa::path(
+    get,
+    path = "/api/oidc/providers",
+    responses(
+        (status = 200, description = "List of providers", body = Vec<OidcProvider>),
+    )
+)]
+pub(crate) async fn list_oidc_provider_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.list_oidc_providers())
+}
+
+/// Start signing in with the provider, the web app should redirect the user to the url.
+///
+/// The provider redirects back to the callback page of the web app with a code and a state,
+/// which should be posted to `/api/oidc/{provider}/callback` within 10 minutes.
+#[utoipa::path(
+    get,
+    path = "/api/oidc/{provider}/authorize",
+    params(
+        ("provider" = String, Path, description = "Provider name"),
+        OidcAuthorize
+    ),
+    responses(
+        (status = 200, description = "Authorization url", body = OidcAuthorizeOutput),
+        (status = 404, description = "Provider not found", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn oidc_authorize_handler(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(input): Query<OidcAuthorize>,
+) -> Result<impl IntoResponse, AppError> {
+    let output = state.oidc_authorize(&provider, &input).await?;
+    Ok(Json(output))
+}
+
+/// Finish signing in with the provider.
+///
+/// - The identity is linked to the user of the same email, or a new user if there is none.
+/// - The user joins the workspace mapped from the tenant or the email domain, and it becomes
+///   the active workspace. It will return 403 if nothing is mapped.
+/// - Users with TOTP enabled get a challenge to complete at `/api/signin/mfa`, the same as
+///   signing in with a password.
+#[utoipa::path(
+    post,
+    path = "/api/oidc/{provider}/callback",
+    params(
+        ("provider" = String, Path, description = "Provider name")
+    ),
+    responses(
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 202, description = "Second factor required", body = MfaChallenge),
+        (status = 401, description = "Invalid state, code or ID token", body = ErrorOutput),
+        (status = 403, description = "No workspace for the identity", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn oidc_callback_handler(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Json(input): Json<OidcCallback>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.oidc_signin(&provider, &input).await?;
+    if state.is_totp_enabled(user.id as _).await? {
+        let challenge = state.create_mfa_challenge(user.id as _).await?;
+        return Ok((StatusCode::ACCEPTED, Json(challenge)).into_response());
+    }
+    let (session_id, refresh_token) = state.create_session(user.id as _).await?;
+    let output = AuthOutput::new(&state, user, &session_id, refresh_token)?;
+    Ok((StatusCode::OK, Json(output)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oidc::mock::MockIdp;
+    use anyhow::Result;
+    use http_body_util::BodyExt as _;
+
+    async fn callback(
+        state: &AppState,
+        idp: &MockIdp,
+        email: &str,
+    ) -> Result<(StatusCode, Vec<u8>)> {
+        let input = OidcAuthorize {
+            login_hint: Some(email.to_string()),
+        };
+        let output = state.oidc_authorize("corp", &input).await?;
+        let (code, oidc_state) = idp.authorize(&output.url).await?;
+        let input = OidcCallback {
+            code,
+            state: oidc_state,
+        };
+        let ret =
+            oidc_callback_handler(State(state.clone()), Path("corp".to_string()), Json(input))
+                .await?
+                .into_response();
+        let status = ret.status();
+        let body = ret.into_body().collect().await?.to_bytes();
+        Ok((status, body.to_vec()))
+    }
+
+    #[tokio::test]
+    async fn test_oidc_callback_should_require_totp() -> Result<()> {
+        let idp = MockIdp::start().await?;
+        let (_tdb, state) = AppState::try_new_for_test_with(|config| {
+            config.oidc = vec![idp.provider("corp", "foo")];
+        })
+        .await?;
+
+        let (status, body) = callback(&state, &idp, "tchen@acme.org").await?;
+        assert_eq!(status, StatusCode::OK);
+        let output: AuthOutput = serde_json::from_slice(&body)?;
+        assert_ne!(output.token, "");
+
+        // the identity is linked to user 1, who has TOTP enabled now
+        sqlx::query("UPDATE users SET totp_enabled_at = NOW() WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let (status, body) = callback(&state, &idp, "tchen@acme.org").await?;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let challenge: MfaChallenge = serde_json::from_slice(&body)?;
+        assert_ne!(challenge.mfa_token, "");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{AppError, AppState, ErrorOutput, ReactionInput};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{ReactionCount, User};
+
+/// Add a reaction to the message.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/messages/{mid}/reactions",
+    params(
+        ("id" = u64, Path, description = "Chat ID"),
+        ("mid" = u64, Path, description = "Message ID")
+    ),
+    responses(
+        (status = 200, description = "Reactions of the message", body = Vec<ReactionCount>),
+        (status = 400, description = "Invalid emoji", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+    Json(input): Json<ReactionInput>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state.add_reaction(input, id, mid, user.id as _).await?;
+    Ok((StatusCode::OK, Json(reactions)))
+}
+
+/// Remove a reaction from the message.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}/reactions",
+    params(
+        ("id" = u64, Path, description = "Chat ID"),
+        ("mid" = u64, Path, description = "Message ID"),
+        ReactionInput
+    ),
+    responses(
+        (status = 200, description = "Reactions of the message", body = Vec<ReactionCount>),
+        (status = 404, description = "Message or reaction not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+    Query(input): Query<ReactionInput>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state.remove_reaction(input, id, mid, user.id as _).await?;
+    Ok((StatusCode::OK, Json(reactions)))
+}
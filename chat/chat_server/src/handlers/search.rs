@@ -0,0 +1,33 @@
+use crate::{AppError, AppState, ErrorOutput, SearchMessages, SearchResult};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+/// Search messages in the chats of the user.
+#[utoipa::path(
+    get,
+    path = "/api/search/messages",
+    params(
+        SearchMessages
+    ),
+    responses(
+        (status = 200, description = "Matched messages", body = SearchResult),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn search_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<SearchMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let ret = state
+        .search_messages(input, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(ret))
+}
@@ -0,0 +1,198 @@
+use crate::{
+    AppError, AppState, CreateSubscription, CreatedSubscription, ErrorOutput, EventDelivery,
+    EventDeliveryDetail, EventSubscription, ListDeliveries, UpdateSubscription,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+/// List the event subscriptions of the workspace, only for workspace owner/admins.
+#[utoipa::path(
+    get,
+    path = "/api/subscriptions",
+    responses(
+        (status = 200, description = "List of event subscriptions", body = Vec<EventSubscription>),
+        (status = 403, description = "Not a workspace owner/admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_subscription_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let subscriptions = state
+        .list_subscriptions(user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(subscriptions))
+}
+
+/// Subscribe an http(s) endpoint to the events of the workspace.
+///
+/// - Each event is posted as JSON, the same as the event sent to SSE clients.
+/// - Events of a chat are delivered only if it is a public channel or the creator of the subscription is a member of it.
+/// - The body is signed in the `X-Chat-Signature` header as `t=<timestamp>,v1=<hex HMAC-SHA256 of "<timestamp>.<body>">`, with the secret shown only once.
+/// - Failed deliveries are retried with exponential backoff.
+#[utoipa::path(
+    post,
+    path = "/api/subscriptions",
+    responses(
+        (status = 201, description = "Event subscription created", body = CreatedSubscription),
+        (status = 400, description = "Invalid url or events", body = ErrorOutput),
+        (status = 403, description = "Not a workspace owner/admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_subscription_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateSubscription>,
+) -> Result<impl IntoResponse, AppError> {
+    let subscription = state
+        .create_subscription(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(subscription)))
+}
+
+/// Pause or resume the subscription, or change its events.
+#[utoipa::path(
+    patch,
+    path = "/api/subscriptions/{id}",
+    params(
+        ("id" = u64, Path, description = "Subscription id")
+    ),
+    responses(
+        (status = 200, description = "Event subscription updated", body = EventSubscription),
+        (status = 400, description = "Invalid events", body = ErrorOutput),
+        (status = 404, description = "Subscription not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_subscription_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<UpdateSubscription>,
+) -> Result<impl IntoResponse, AppError> {
+    let subscription = state
+        .update_subscription(input, id, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(subscription))
+}
+
+/// Delete the subscription, its pending deliveries are dropped.
+#[utoipa::path(
+    delete,
+    path = "/api/subscriptions/{id}",
+    params(
+        ("id" = u64, Path, description = "Subscription id")
+    ),
+    responses(
+        (status = 204, description = "Event subscription deleted"),
+        (status = 404, description = "Subscription not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_subscription_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .delete_subscription(id, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List the deliveries of the subscription, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/subscriptions/{id}/deliveries",
+    params(
+        ("id" = u64, Path, description = "Subscription id"),
+        ListDeliveries
+    ),
+    responses(
+        (status = 200, description = "List of deliveries", body = Vec<EventDelivery>),
+        (status = 404, description = "Subscription not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_delivery_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Query(input): Query<ListDeliveries>,
+) -> Result<impl IntoResponse, AppError> {
+    let deliveries = state
+        .list_deliveries(id, input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(deliveries))
+}
+
+/// Get the delivery with the log of its attempts.
+#[utoipa::path(
+    get,
+    path = "/api/subscriptions/{id}/deliveries/{delivery_id}",
+    params(
+        ("id" = u64, Path, description = "Subscription id"),
+        ("delivery_id" = u64, Path, description = "Delivery id")
+    ),
+    responses(
+        (status = 200, description = "Delivery found", body = EventDeliveryDetail),
+        (status = 404, description = "Delivery not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_delivery_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, delivery_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let delivery = state
+        .get_delivery(id, delivery_id, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(delivery))
+}
+
+/// Send the delivery again right away, with a fresh set of retries.
+#[utoipa::path(
+    post,
+    path = "/api/subscriptions/{id}/deliveries/{delivery_id}/replay",
+    params(
+        ("id" = u64, Path, description = "Subscription id"),
+        ("delivery_id" = u64, Path, description = "Delivery id")
+    ),
+    responses(
+        (status = 200, description = "Delivery queued", body = EventDelivery),
+        (status = 404, description = "Delivery not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn replay_delivery_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, delivery_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let delivery = state
+        .replay_delivery(id, delivery_id, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(delivery))
+}
@@ -1,6 +1,15 @@
-use crate::{AppError, AppState};
-use axum::{extract::State, response::IntoResponse, Extension, Json};
-use chat_core::{ChatUser, User};
+use super::AuthOutput;
+use crate::{
+    AppError, AppState, ErrorOutput, JoinWorkspace, TransferWorkspace, UpdateMember,
+    UpdateWorkspace, UserWorkspace, WorkspaceMember,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{ChatUser, SessionId, User, Workspace};
 
 /// List all users in the workspace.
 #[utoipa::path(
@@ -20,3 +29,222 @@ pub(crate) async fn list_chat_users_handler(
     let users = state.fetch_chat_users(user.ws_id as _).await?;
     Ok(Json(users))
 }
+
+/// List all workspaces the user belongs to, with the role of the user.
+#[utoipa::path(
+    get,
+    path = "/api/workspaces",
+    responses(
+        (status = 200, description = "List of workspaces", body = Vec<UserWorkspace>)
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let workspaces = state.list_user_workspaces(user.id as _).await?;
+    Ok(Json(workspaces))
+}
+
+/// Join another workspace with an invite, switch to it to use it.
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/join",
+    responses(
+        (status = 201, description = "Workspace joined", body = UserWorkspace),
+        (status = 400, description = "Invalid invite", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn join_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<JoinWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.join_workspace(input, &user).await?;
+    Ok((StatusCode::CREATED, Json(ws)))
+}
+
+/// Switch to another workspace of the user, returns a token scoped to the workspace.
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/{id}/switch",
+    params(
+        ("id" = u64, Path, description = "Workspace id")
+    ),
+    responses(
+        (status = 200, description = "Workspace switched", body = AuthOutput),
+        (status = 404, description = "Workspace not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn switch_workspace_handler(
+    Extension(user): Extension<User>,
+    Extension(session_id): Extension<SessionId>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.switch_workspace(id, user.id as _).await?;
+    let refresh_token = state.rotate_session(&session_id).await?;
+    Ok(Json(AuthOutput::new(
+        &state,
+        user,
+        &session_id,
+        refresh_token,
+    )?))
+}
+
+/// Get the active workspace of the user.
+#[utoipa::path(
+    get,
+    path = "/api/workspace",
+    responses(
+        (status = 200, description = "The workspace", body = Workspace),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .find_workspace_by_id(user.ws_id as _)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Workspace id {}", user.ws_id)))?;
+    Ok(Json(ws))
+}
+
+/// Rename the workspace, for workspace owner/admins.
+#[utoipa::path(
+    patch,
+    path = "/api/workspace",
+    responses(
+        (status = 200, description = "Workspace updated", body = Workspace),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Permission denied", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .update_workspace(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(ws))
+}
+
+/// Transfer the workspace to another member, for the owner only.
+#[utoipa::path(
+    post,
+    path = "/api/workspace/transfer",
+    responses(
+        (status = 200, description = "Workspace transferred", body = Workspace),
+        (status = 403, description = "Permission denied", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn transfer_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TransferWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .transfer_workspace(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(ws))
+}
+
+/// List all members of the workspace with their roles, for workspace owner/admins.
+#[utoipa::path(
+    get,
+    path = "/api/workspace/members",
+    responses(
+        (status = 200, description = "List of members", body = Vec<WorkspaceMember>),
+        (status = 403, description = "Permission denied", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = state
+        .list_workspace_members(user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(members))
+}
+
+/// Change the role of the member, or deactivate/activate the member.
+#[utoipa::path(
+    patch,
+    path = "/api/workspace/members/{id}",
+    params(
+        ("id" = u64, Path, description = "Member id")
+    ),
+    responses(
+        (status = 200, description = "Member updated", body = WorkspaceMember),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Permission denied", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<UpdateMember>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state
+        .update_workspace_member(input, user.ws_id as _, id, user.id as _)
+        .await?;
+    Ok(Json(member))
+}
+
+/// Remove the member from the workspace and all its chats.
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/members/{id}",
+    params(
+        ("id" = u64, Path, description = "Member id")
+    ),
+    responses(
+        (status = 200, description = "Member removed"),
+        (status = 403, description = "Permission denied", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn remove_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .remove_workspace_member(user.ws_id as _, id, user.id as _)
+        .await?;
+    Ok(StatusCode::OK)
+}
@@ -1,32 +1,44 @@
+mod agent;
 mod config;
 mod error;
 mod handlers;
+mod lockout;
+mod mailer;
 mod middlewares;
 mod models;
+mod oidc;
 mod openapi;
 
 use anyhow::Context;
 use axum::{
+    handler::Handler,
     http::Method,
-    middleware::from_fn_with_state,
-    routing::{get, post},
+    middleware::{from_fn, from_fn_with_state},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use chat_core::{
-    middlewares::{set_layer, verify_token, TokenVerify},
-    DecodingKey, EncodingKey, User,
+    middlewares::{
+        rate_limit_ip, rate_limit_user, set_layer, verify_token, RateLimiter, TokenVerify,
+    },
+    DecodingKey, EncodingKey, RevokedSessions, SessionId, User,
 };
 use handlers::*;
-use middlewares::verify_chat;
+use lockout::SigninLockout;
+use mailer::MailerAdapter;
+use middlewares::{verify_api_scope, verify_chat, verify_chat_permission, verify_workspace};
+use oidc::OidcClient;
 use openapi::OpenApiRouter;
 use sqlx::PgPool;
-use std::{fmt, ops::Deref, sync::Arc};
+use std::{fmt, iter, ops::Deref, sync::Arc};
 use tokio::fs;
 use tower_http::cors::{self, CorsLayer};
 
 pub use config::AppConfig;
 pub use error::{AppError, ErrorOutput};
 pub use models::*;
+#[cfg(feature = "test-util")]
+pub use oidc::mock::MockIdp;
 
 #[derive(Debug, Clone)]
 pub struct AppState {
@@ -39,18 +51,75 @@ pub struct AppStateInner {
     pub(crate) ek: EncodingKey,
     pub(crate) dk: DecodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) revoked: RevokedSessions,
+    pub(crate) mailer: MailerAdapter,
+    pub(crate) lockout: SigninLockout,
+    pub(crate) oidc: OidcClient,
+    pub(crate) webhook_limit: RateLimiter,
 }
 
 pub async fn get_router(state: AppState) -> Result<Router, AppError> {
+    state.watch_revoked_sessions().await?;
+
+    let auth_limit = from_fn_with_state(
+        RateLimiter::new(state.config.rate_limit.auth)
+            .with_trusted_proxies(state.config.rate_limit.trusted_proxies.clone()),
+        rate_limit_ip,
+    );
+    let webhook_ip_limit = from_fn_with_state(
+        RateLimiter::new(state.config.rate_limit.webhook_ip)
+            .with_trusted_proxies(state.config.rate_limit.trusted_proxies.clone()),
+        rate_limit_ip,
+    );
+    let message_limit = from_fn_with_state(
+        RateLimiter::new(state.config.rate_limit.message),
+        rate_limit_user,
+    );
+    let permit =
+        |permission| from_fn_with_state((state.clone(), permission), verify_chat_permission);
+
     let chat = Router::new()
         .route(
             "/:id",
             get(get_chat_handler)
-                .patch(update_chat_handler)
-                .delete(delete_chat_handler)
-                .post(send_message_handler),
+                .patch(update_chat_handler.layer(permit(Permission::UpdateChat)))
+                .delete(delete_chat_handler.layer(permit(Permission::DeleteChat)))
+                .post(send_message_handler.layer(message_limit)),
+        )
+        .route(
+            "/:id/members/:user_id/role",
+            put(update_chat_role_handler.layer(permit(Permission::ManageRoles))),
         )
         .route("/:id/messages", get(list_message_handler))
+        .route("/:id/read", get(list_read_handler).post(mark_read_handler))
+        .route(
+            "/:id/messages/:mid",
+            patch(update_message_handler).delete(delete_message_handler),
+        )
+        .route("/:id/messages/:mid/thread", get(list_thread_handler))
+        .route(
+            "/:id/messages/:mid/reactions",
+            post(add_reaction_handler).delete(remove_reaction_handler),
+        )
+        .route(
+            "/:id/agents",
+            get(list_agent_handler)
+                .post(create_agent_handler.layer(permit(Permission::ManageAgents))),
+        )
+        .route(
+            "/:id/agents/:agent_id",
+            patch(update_agent_handler.layer(permit(Permission::ManageAgents)))
+                .delete(delete_agent_handler.layer(permit(Permission::ManageAgents))),
+        )
+        .route(
+            "/:id/webhooks",
+            get(list_incoming_webhook_handler.layer(permit(Permission::ManageWebhooks)))
+                .post(create_incoming_webhook_handler.layer(permit(Permission::ManageWebhooks))),
+        )
+        .route(
+            "/:id/webhooks/:webhook_id",
+            delete(revoke_incoming_webhook_handler.layer(permit(Permission::ManageWebhooks))),
+        )
         .layer(from_fn_with_state(state.clone(), verify_chat))
         .route("/", get(list_chat_handler).post(create_chat_handler));
 
@@ -65,20 +134,86 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
         ])
         .allow_origin(cors::Any)
         .allow_headers(cors::Any);
+    // unauthenticated, so they are limited per client ip
+    let auth = Router::new()
+        .route("/signin", post(signin_handler))
+        .route("/signin/mfa", post(signin_mfa_handler))
+        .route("/signup", post(signup_handler))
+        .route("/refresh", post(refresh_handler))
+        .route("/password/forgot", post(forgot_password_handler))
+        .route("/password/reset", post(reset_password_handler))
+        .route("/email/verify", post(verify_email_handler))
+        .route("/oidc/providers", get(list_oidc_provider_handler))
+        .route("/oidc/:provider/authorize", get(oidc_authorize_handler))
+        .route("/oidc/:provider/callback", post(oidc_callback_handler))
+        .route_layer(auth_limit)
+        // limited per client ip and then per webhook, the token in the url is the credential
+        .route(
+            "/hooks/:token",
+            post(post_webhook_message_handler.layer(webhook_ip_limit)),
+        );
     let api = Router::new()
         .route("/users", get(list_chat_users_handler))
+        .route(
+            "/workspace",
+            get(get_workspace_handler).patch(update_workspace_handler),
+        )
+        .route("/workspace/transfer", post(transfer_workspace_handler))
+        .route("/workspace/members", get(list_member_handler))
+        .route(
+            "/workspace/members/:id",
+            patch(update_member_handler).delete(remove_member_handler),
+        )
+        .route(
+            "/invites",
+            get(list_invite_handler).post(create_invite_handler),
+        )
+        .route("/invites/:id", delete(revoke_invite_handler))
+        .route(
+            "/tokens",
+            get(list_api_token_handler).post(create_api_token_handler),
+        )
+        .route("/tokens/:id", delete(revoke_api_token_handler))
+        .route(
+            "/subscriptions",
+            get(list_subscription_handler).post(create_subscription_handler),
+        )
+        .route(
+            "/subscriptions/:id",
+            patch(update_subscription_handler).delete(delete_subscription_handler),
+        )
+        .route("/subscriptions/:id/deliveries", get(list_delivery_handler))
+        .route(
+            "/subscriptions/:id/deliveries/:delivery_id",
+            get(get_delivery_handler),
+        )
+        .route(
+            "/subscriptions/:id/deliveries/:delivery_id/replay",
+            post(replay_delivery_handler),
+        )
         .nest("/chats", chat)
+        .route("/search/messages", get(search_messages_handler))
         .route("/upload", post(upload_handler))
         .route("/files/:ws_id/*path", get(file_handler))
+        .layer(from_fn_with_state(state.clone(), verify_workspace))
+        // routes to find another workspace, even if the user is out of the current one
+        .route("/workspaces", get(list_workspace_handler))
+        .route("/workspaces/join", post(join_workspace_handler))
+        .route("/workspaces/:id/switch", post(switch_workspace_handler))
+        .route("/mfa/totp", post(setup_totp_handler))
+        .route("/mfa/totp/enable", post(enable_totp_handler))
+        .route("/mfa/totp/disable", post(disable_totp_handler))
+        .route("/signout", post(signout_handler))
+        .layer(from_fn(verify_api_scope))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         // routes doesn't need token verification
-        .route("/signin", post(signin_handler))
-        .route("/signup", post(signup_handler))
+        .merge(auth)
         .layer(cors);
 
     let app = Router::new()
         .openapi()
         .route("/", get(index_handler))
+        .route("/.well-known/jwks.json", get(jwks_handler))
         .nest("/api", api)
         .with_state(state);
 
@@ -97,8 +232,16 @@ impl Deref for AppState {
 impl TokenVerify for AppState {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    fn verify(&self, token: &str) -> Result<(User, SessionId), Self::Error> {
+        let (user, session_id) = self.dk.verify(token)?;
+        if self.revoked.is_revoked(&session_id) {
+            return Err(AppError::SessionError("session revoked".to_string()));
+        }
+        Ok((user, session_id))
+    }
+
+    async fn verify_api_token(&self, token: &str) -> Result<User, Self::Error> {
+        self.find_user_by_api_token(token).await
     }
 }
 
@@ -108,16 +251,25 @@ impl AppState {
             .await
             .context("Create base url failed")?;
         let ek = EncodingKey::load(&config.auth.sk).context("Failed to load private key")?;
-        let dk = DecodingKey::load(&config.auth.pk).context("Failed to load public key")?;
+        let pks = iter::once(&config.auth.pk).chain(&config.auth.prev_pks);
+        let dk = DecodingKey::load_all(pks).context("Failed to load public keys")?;
         let pool = PgPool::connect(&config.server.db_url)
             .await
             .context("Failed to connect to database")?;
+        let mailer = MailerAdapter::try_new(&config.mail)?;
+        let lockout = SigninLockout::new(config.rate_limit.lockout.clone());
+        let webhook_limit = RateLimiter::new(config.rate_limit.webhook);
         Ok(Self {
             inner: Arc::new(AppStateInner {
                 config,
                 ek,
                 dk,
                 pool,
+                revoked: RevokedSessions::default(),
+                mailer,
+                lockout,
+                oidc: OidcClient::default(),
+                webhook_limit,
             }),
         })
     }
@@ -134,25 +286,47 @@ impl fmt::Debug for AppStateInner {
 #[cfg(feature = "test-util")]
 mod test_util {
     use super::*;
+    use crate::mailer::FileMailer;
     use sqlx::{Executor, PgPool};
     use sqlx_db_tester::TestPg;
-    use std::path::Path;
+    use std::{env, path::Path};
 
     impl AppState {
         pub async fn try_new_for_test() -> Result<(sqlx_db_tester::TestPg, Self), AppError> {
-            let config = AppConfig::try_load()?;
+            Self::try_new_for_test_with(|_| {}).await
+        }
+
+        /// Same as `try_new_for_test`, with the config updated for the test
+        pub async fn try_new_for_test_with(
+            update: impl FnOnce(&mut AppConfig),
+        ) -> Result<(sqlx_db_tester::TestPg, Self), AppError> {
+            let mut config = AppConfig::try_load()?;
+            update(&mut config);
             let ek = EncodingKey::load(&config.auth.sk).context("Failed to load private key")?;
-            let dk = DecodingKey::load(&config.auth.pk).context("Failed to load public key")?;
+            let pks = iter::once(&config.auth.pk).chain(&config.auth.prev_pks);
+            let dk = DecodingKey::load_all(pks).context("Failed to load public keys")?;
             // let post = config.server.db_url.rfind('/').expect("Invalid db_url");
             // let server_url = &config.server.db_url[..post];
             // println!("server_url: {}", server_url);
             let (tdb, pool) = get_test_pool(Some(config.server.db_url.as_ref())).await;
+            // mails are written to a file of the test, so that tests could read them back
+            let mail_file = env::temp_dir()
+                .join("chat_server_mails")
+                .join(format!("{}.jsonl", tdb.dbname));
+            let mailer = MailerAdapter::File(FileMailer::new(Some(mail_file)));
+            let lockout = SigninLockout::new(config.rate_limit.lockout.clone());
+            let webhook_limit = RateLimiter::new(config.rate_limit.webhook);
             let state = Self {
                 inner: Arc::new(AppStateInner {
                     config,
                     ek,
                     dk,
                     pool,
+                    revoked: RevokedSessions::default(),
+                    mailer,
+                    lockout,
+                    oidc: OidcClient::default(),
+                    webhook_limit,
                 }),
             };
             Ok((tdb, state))
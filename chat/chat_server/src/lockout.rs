@@ -0,0 +1,157 @@
+use crate::{config::LockoutConfig, AppError};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Forget stale failures once there are more keys than this
+const MAX_ENTRIES: usize = 10_000;
+
+/// Progressive lockout of failed signins, keyed by email and client ip, so that failures from
+/// one client don't lock the others out of the account. Failures without a client ip, e.g. wrong
+/// codes of an MFA challenge which is issued after the password check, are keyed by email only.
+#[derive(Debug)]
+pub struct SigninLockout {
+    config: LockoutConfig,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    failed_at: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl SigninLockout {
+    pub fn new(config: LockoutConfig) -> Self {
+        Self {
+            config,
+            failures: Default::default(),
+        }
+    }
+
+    /// Reject the signin if the email is locked out for the client
+    pub fn check(&self, email: &str, ip: Option<IpAddr>) -> Result<(), AppError> {
+        self.check_at(email, ip, Instant::now())
+    }
+
+    /// Record a failed signin, which locks the email out for the client after too many of them
+    pub fn fail(&self, email: &str, ip: Option<IpAddr>) {
+        self.fail_at(email, ip, Instant::now())
+    }
+
+    /// Forget the failures of the client after a successful signin
+    pub fn succeed(&self, email: &str, ip: Option<IpAddr>) {
+        self.failures.lock().unwrap().remove(&key(email, ip));
+    }
+
+    fn check_at(&self, email: &str, ip: Option<IpAddr>, now: Instant) -> Result<(), AppError> {
+        let failures = self.failures.lock().unwrap();
+        match failures.get(&key(email, ip)).and_then(|f| f.locked_until) {
+            Some(until) if until > now => {
+                let secs = (until - now).as_secs_f64().ceil().max(1.0) as u64;
+                Err(AppError::TooManyRequests(secs))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn fail_at(&self, email: &str, ip: Option<IpAddr>, now: Instant) {
+        let forget_after = Duration::from_secs(self.config.max_secs);
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= MAX_ENTRIES {
+            failures.retain(|_, f| now - f.failed_at < forget_after);
+        }
+
+        let entry = failures.entry(key(email, ip)).or_insert(Failures {
+            count: 0,
+            failed_at: now,
+            locked_until: None,
+        });
+        if now - entry.failed_at >= forget_after {
+            entry.count = 0;
+        }
+        entry.count += 1;
+        entry.failed_at = now;
+
+        if entry.count >= self.config.max_failures {
+            // 1, 2, 4... times the base lockout
+            let exp = (entry.count - self.config.max_failures).min(16);
+            let secs = self
+                .config
+                .base_secs
+                .saturating_mul(1 << exp)
+                .min(self.config.max_secs);
+            entry.locked_until = Some(now + Duration::from_secs(secs));
+        }
+    }
+}
+
+fn key(email: &str, ip: Option<IpAddr>) -> String {
+    let email = email.trim().to_lowercase();
+    match ip {
+        Some(ip) => format!("{email}|{ip}"),
+        None => email,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signin_lockout_should_grow() {
+        let lockout = SigninLockout::new(LockoutConfig {
+            max_failures: 2,
+            base_secs: 30,
+            max_secs: 100,
+        });
+        let now = Instant::now();
+
+        lockout.fail_at("tchen@acme.org", None, now);
+        assert!(lockout.check_at("tchen@acme.org", None, now).is_ok());
+        lockout.fail_at("tchen@acme.org", None, now);
+        let ret = lockout.check_at("TChen@acme.org", None, now);
+        assert!(matches!(ret, Err(AppError::TooManyRequests(30))));
+        // other emails are not affected
+        assert!(lockout.check_at("alice@acme.org", None, now).is_ok());
+
+        let now = now + Duration::from_secs(30);
+        assert!(lockout.check_at("tchen@acme.org", None, now).is_ok());
+        lockout.fail_at("tchen@acme.org", None, now);
+        let ret = lockout.check_at("tchen@acme.org", None, now);
+        assert!(matches!(ret, Err(AppError::TooManyRequests(60))));
+
+        let now = now + Duration::from_secs(60);
+        lockout.fail_at("tchen@acme.org", None, now);
+        let ret = lockout.check_at("tchen@acme.org", None, now);
+        assert!(matches!(ret, Err(AppError::TooManyRequests(100))));
+
+        lockout.succeed("tchen@acme.org", None);
+        assert!(lockout.check_at("tchen@acme.org", None, now).is_ok());
+    }
+
+    #[test]
+    fn signin_lockout_should_be_per_client() {
+        let lockout = SigninLockout::new(LockoutConfig {
+            max_failures: 1,
+            base_secs: 30,
+            max_secs: 100,
+        });
+        let now = Instant::now();
+        let attacker = Some("203.0.113.1".parse().unwrap());
+        let victim = Some("198.51.100.1".parse().unwrap());
+
+        lockout.fail_at("tchen@acme.org", attacker, now);
+        assert!(lockout.check_at("tchen@acme.org", attacker, now).is_err());
+        // the owner of the account signs in from elsewhere
+        assert!(lockout.check_at("tchen@acme.org", victim, now).is_ok());
+        assert!(lockout.check_at("tchen@acme.org", None, now).is_ok());
+
+        lockout.succeed("tchen@acme.org", victim);
+        assert!(lockout.check_at("tchen@acme.org", attacker, now).is_err());
+    }
+}
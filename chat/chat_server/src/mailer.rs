@@ -0,0 +1,150 @@
+use crate::{
+    config::{MailConfig, SmtpConfig},
+    AppError,
+};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use std::{future::Future, path::PathBuf};
+use tokio::{fs, io::AsyncWriteExt};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    /// plain text body
+    pub body: String,
+}
+
+pub trait Mailer {
+    fn send(&self, mail: &Mail) -> impl Future<Output = Result<(), AppError>> + Send;
+}
+
+pub enum MailerAdapter {
+    Smtp(Box<SmtpMailer>),
+    File(FileMailer),
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+/// Append mails to a file as json lines, or print them to stdout if no file is set.
+/// For development and tests, which could read the mails back.
+pub struct FileMailer {
+    path: Option<PathBuf>,
+}
+
+impl Mail {
+    pub fn new(to: impl Into<String>, subject: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            to: to.into(),
+            subject: subject.into(),
+            body: body.into(),
+        }
+    }
+}
+
+impl MailerAdapter {
+    pub fn try_new(config: &MailConfig) -> Result<Self, AppError> {
+        let mailer = match &config.smtp {
+            Some(smtp) => Self::Smtp(Box::new(SmtpMailer::try_new(smtp, &config.from)?)),
+            None => Self::File(FileMailer::new(config.file.clone())),
+        };
+        Ok(mailer)
+    }
+}
+
+impl Mailer for MailerAdapter {
+    async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+        match self {
+            MailerAdapter::Smtp(mailer) => mailer.send(mail).await,
+            MailerAdapter::File(mailer) => mailer.send(mail).await,
+        }
+    }
+}
+
+impl SmtpMailer {
+    pub fn try_new(config: &SmtpConfig, from: &str) -> Result<Self, AppError> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+            .map_err(mail_error)?
+            .port(config.port)
+            .credentials(Credentials::new(
+                config.username.clone(),
+                config.password.clone(),
+            ))
+            .build();
+        let from = from.parse().map_err(mail_error)?;
+        Ok(Self { transport, from })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail.to.parse().map_err(mail_error)?)
+            .subject(&mail.subject)
+            .body(mail.body.clone())
+            .map_err(mail_error)?;
+        self.transport.send(message).await.map_err(mail_error)?;
+        Ok(())
+    }
+}
+
+impl FileMailer {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self { path }
+    }
+
+    /// Read back all mails sent, empty if mails are printed to stdout
+    #[allow(dead_code)]
+    pub async fn read_mails(&self) -> Result<Vec<Mail>, AppError> {
+        let Some(path) = &self.path else {
+            return Ok(vec![]);
+        };
+        if !fs::try_exists(path).await? {
+            return Ok(vec![]);
+        }
+
+        let content = fs::read_to_string(path).await?;
+        content
+            .lines()
+            .map(|line| serde_json::from_str(line).map_err(mail_error))
+            .collect()
+    }
+}
+
+impl Mailer for FileMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+        let Some(path) = &self.path else {
+            println!(
+                "To: {}\nSubject: {}\n\n{}",
+                mail.to, mail.subject, mail.body
+            );
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut line = serde_json::to_string(mail).map_err(mail_error)?;
+        line.push('\n');
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        // tokio files write in the background, flush so the mail could be read back right away
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+fn mail_error(e: impl ToString) -> AppError {
+    AppError::MailError(e.to_string())
+}
@@ -1,7 +1,8 @@
 use anyhow::Result;
 use chat_server::{get_router, AppConfig, AppState};
+use std::net::SocketAddr;
 use tokio::net::TcpListener;
-use tracing::{info, level_filters::LevelFilter};
+use tracing::{info, level_filters::LevelFilter, warn};
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
 
 #[tokio::main]
@@ -13,11 +14,22 @@ async fn main() -> Result<()> {
     let addr = format!("0.0.0.0:{}", config.server.port);
 
     let state = AppState::try_new(config).await?;
+    for agent in state.check_agent_args().await? {
+        warn!(
+            "agent {} ({}) has invalid args: {}",
+            agent.name, agent.id, agent.reason
+        );
+    }
     let app = get_router(state).await?;
     let listener = TcpListener::bind(&addr).await?;
     info!("Listening on: {}", addr);
 
-    axum::serve(listener, app.into_make_service()).await?;
+    // client ips are needed by the rate limiting
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
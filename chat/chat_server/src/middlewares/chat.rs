@@ -5,19 +5,28 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use chat_core::User;
+use serde::Deserialize;
+
+// nested routes (e.g. /:id/agents/:agent_id) carry more params, only the chat id matters here
+#[derive(Debug, Deserialize)]
+struct ChatPath {
+    id: u64,
+}
 
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
-    let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
-        .await
-        .unwrap();
+    let chat_id = match Path::<ChatPath>::from_request_parts(&mut parts, &state).await {
+        Ok(Path(path)) => path.id,
+        Err(e) => return e.into_response(),
+    };
 
+    // the chat should be in the active workspace of the user
     let user = parts.extensions.get::<User>().unwrap();
-    if !state
-        .is_chat_member(chat_id, user.id as _)
-        .await
-        .unwrap_or_default()
-    {
+    let is_member = match state.get_chat_by_id(chat_id).await {
+        Ok(Some(chat)) => chat.ws_id == user.ws_id && chat.members.contains(&user.id),
+        _ => false,
+    };
+    if !is_member {
         let err = AppError::CreateMessageError(format!(
             "User {} is not a member of chat {}",
             user.id, chat_id
@@ -49,10 +58,12 @@ mod tests {
         let (_tdb, state) = AppState::try_new_for_test().await?;
 
         let user = state.find_user_by_id(1).await?.expect("user should exists");
-        let token = state.ek.sign(user)?;
+        let (session_id, _) = state.create_session(1).await?;
+        let token = state.ek.sign(user, &session_id)?;
 
         let app = Router::new()
             .route("/chats/:id/messages", get(handler))
+            .route("/chats/:id/agents/:agent_id", get(handler))
             .layer(from_fn_with_state(state.clone(), verify_chat))
             .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
             .with_state(state);
@@ -65,6 +76,14 @@ mod tests {
         let resp = app.clone().oneshot(req).await?;
         assert_eq!(resp.status(), StatusCode::OK);
 
+        // nested route with more path params
+        let req = Request::builder()
+            .uri("/chats/1/agents/1")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let resp = app.clone().oneshot(req).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
         // user not in chat
         let req = Request::builder()
             .uri("/chats/5/messages")
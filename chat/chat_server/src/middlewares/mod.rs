@@ -1,3 +1,9 @@
 mod chat;
+mod permission;
+mod scope;
+mod workspace;
 
 pub use chat::verify_chat;
+pub use permission::verify_chat_permission;
+pub use scope::verify_api_scope;
+pub use workspace::verify_workspace;
@@ -0,0 +1,95 @@
+use crate::{AppState, Permission};
+use axum::{
+    extract::{FromRequestParts, Path, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::User;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct ChatPath {
+    id: u64,
+}
+
+/// Check the user has the permission on the chat, should be layered after `verify_chat`.
+/// Use it like `from_fn_with_state((state, Permission::UpdateChat), verify_chat_permission)`.
+pub async fn verify_chat_permission(
+    State((state, permission)): State<(AppState, Permission)>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let chat_id = match Path::<ChatPath>::from_request_parts(&mut parts, &state).await {
+        Ok(Path(path)) => path.id,
+        Err(e) => return e.into_response(),
+    };
+
+    let user = parts.extensions.get::<User>().unwrap();
+    if let Err(e) = state
+        .check_chat_permission(chat_id, user.id as _, permission)
+        .await
+    {
+        return e.into_response();
+    }
+
+    let req = Request::from_parts(parts, body);
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{
+        body::Body, http::StatusCode, middleware::from_fn_with_state, routing::delete, Router,
+    };
+    use chat_core::middlewares::verify_token;
+    use tower::ServiceExt;
+
+    async fn handler(_req: Request) -> impl IntoResponse {
+        (StatusCode::OK, "OK")
+    }
+
+    #[tokio::test]
+    async fn test_chat_permission_middleware_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let owner = state.find_user_by_id(1).await?.expect("user should exists");
+        let (owner_session, _) = state.create_session(1).await?;
+        let owner_token = state.ek.sign(owner, &owner_session)?;
+        let member = state.find_user_by_id(2).await?.expect("user should exists");
+        let (member_session, _) = state.create_session(2).await?;
+        let member_token = state.ek.sign(member, &member_session)?;
+
+        let app = Router::new()
+            .route("/chats/:id", delete(handler))
+            .layer(from_fn_with_state(
+                (state.clone(), Permission::DeleteChat),
+                verify_chat_permission,
+            ))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state);
+
+        // workspace owner
+        let req = Request::builder()
+            .method("DELETE")
+            .uri("/chats/1")
+            .header("Authorization", format!("Bearer {}", owner_token))
+            .body(Body::empty())?;
+        let resp = app.clone().oneshot(req).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // plain member
+        let req = Request::builder()
+            .method("DELETE")
+            .uri("/chats/1")
+            .header("Authorization", format!("Bearer {}", member_token))
+            .body(Body::empty())?;
+        let resp = app.oneshot(req).await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+}
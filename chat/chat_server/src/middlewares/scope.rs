@@ -0,0 +1,117 @@
+use crate::AppError;
+use axum::{
+    extract::{MatchedPath, Request},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::{ApiScope, User};
+
+/// API tokens could only use the routes of their scopes, the other routes, e.g. managing the
+/// workspace or the tokens, are for users signed in themselves.
+pub async fn verify_api_scope(req: Request, next: Next) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+    if user.scopes.is_none() {
+        return next.run(req).await;
+    }
+
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str())
+        .unwrap_or_default();
+    match required_scope(req.method(), path) {
+        Some(scope) if user.has_scope(scope) => next.run(req).await,
+        Some(scope) => {
+            AppError::PermissionDenied(format!("API token without scope {scope:?}")).into_response()
+        }
+        None => AppError::PermissionDenied(format!("{path} is not available to API tokens"))
+            .into_response(),
+    }
+}
+
+fn required_scope(method: &Method, path: &str) -> Option<ApiScope> {
+    match (method, path) {
+        (
+            &Method::GET,
+            "/api/users"
+            | "/api/chats"
+            | "/api/chats/:id"
+            | "/api/chats/:id/messages"
+            | "/api/chats/:id/messages/:mid/thread"
+            | "/api/search/messages"
+            | "/api/files/:ws_id/*path",
+        ) => Some(ApiScope::ReadChats),
+        (&Method::POST, "/api/chats/:id") => Some(ApiScope::SendMessages),
+        (&Method::POST, "/api/upload") => Some(ApiScope::UploadFiles),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppState, CreateApiToken};
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state},
+        routing::get,
+        Router,
+    };
+    use chat_core::middlewares::verify_token;
+    use tower::ServiceExt;
+
+    async fn handler(_req: Request) -> impl IntoResponse {
+        (StatusCode::OK, "OK")
+    }
+
+    #[tokio::test]
+    async fn test_api_scope_middleware_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let user = state.find_user_by_id(1).await?.expect("user should exists");
+        let input = CreateApiToken {
+            name: "ci".to_string(),
+            scopes: vec![ApiScope::ReadChats],
+            expires_in_days: Some(30),
+        };
+        let token = state.create_api_token(&user, input).await?.token;
+
+        let chat = Router::new().route("/:id", get(handler).post(handler));
+        let api = Router::new()
+            .nest("/chats", chat)
+            .route("/workspace", get(handler))
+            .layer(from_fn(verify_api_scope))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>));
+        let app = Router::new().nest("/api", api).with_state(state);
+
+        let send = |method: Method, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+        };
+
+        let resp = app
+            .clone()
+            .oneshot(send(Method::GET, "/api/chats/1")?)
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // out of the scopes of the token
+        let resp = app
+            .clone()
+            .oneshot(send(Method::POST, "/api/chats/1")?)
+            .await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // not available to API tokens at all
+        let resp = app.oneshot(send(Method::GET, "/api/workspace")?).await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+}
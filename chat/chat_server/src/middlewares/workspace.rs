@@ -0,0 +1,78 @@
+use crate::{AppError, AppState};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::User;
+
+/// The user should be an active member of the workspace in the token, so that removed or
+/// deactivated members couldn't keep using their tokens.
+pub async fn verify_workspace(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+    match state
+        .get_workspace_role(user.ws_id as _, user.id as _)
+        .await
+    {
+        Ok(Some(_)) => next.run(req).await,
+        Ok(None) => AppError::PermissionDenied(format!(
+            "User {} is not an active member of workspace {}",
+            user.id, user.ws_id
+        ))
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UpdateMember;
+    use anyhow::Result;
+    use axum::{
+        body::Body, http::StatusCode, middleware::from_fn_with_state, routing::get, Router,
+    };
+    use chat_core::middlewares::verify_token;
+    use tower::ServiceExt;
+
+    async fn handler(_req: Request) -> impl IntoResponse {
+        (StatusCode::OK, "OK")
+    }
+
+    #[tokio::test]
+    async fn test_workspace_middleware_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let user = state.find_user_by_id(2).await?.expect("user should exists");
+        let (session_id, _) = state.create_session(2).await?;
+        let token = state.ek.sign(user, &session_id)?;
+
+        let app = Router::new()
+            .route("/users", get(handler))
+            .layer(from_fn_with_state(state.clone(), verify_workspace))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state.clone());
+
+        let req = Request::builder()
+            .uri("/users")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let resp = app.clone().oneshot(req).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // deactivated member
+        let input = UpdateMember {
+            active: Some(false),
+            ..Default::default()
+        };
+        state.update_workspace_member(input, 1, 2, 1).await?;
+        let req = Request::builder()
+            .uri("/users")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let resp = app.oneshot(req).await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use super::user::hash_password;
+use crate::{
+    mailer::{Mail, Mailer},
+    AppError, AppState,
+};
+use chat_core::User;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+use tracing::warn;
+use utoipa::ToSchema;
+
+const PASSWORD_RESET: &str = "password_reset";
+const EMAIL_VERIFY: &str = "email_verify";
+/// Lifetime of a password reset token, in seconds
+const PASSWORD_RESET_SECS: u64 = 60 * 60;
+/// Lifetime of an email verification token, in seconds
+const EMAIL_VERIFY_SECS: u64 = 60 * 60 * 24 * 3;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ForgotPassword {
+    pub email: String,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ResetPassword {
+    /// the token from the password reset mail
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct VerifyEmail {
+    /// the token from the verification mail
+    pub token: String,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Mail a password reset link to the user in background, so that neither the response nor
+    /// its timing tells whether an email is registered. Failures are only logged.
+    pub fn forgot_password(&self, input: &ForgotPassword) {
+        let state = self.clone();
+        let email = input.email.clone();
+        tokio::spawn(async move {
+            if let Err(e) = state.send_password_reset(&email).await {
+                warn!("Failed to send password reset mail: {}", e);
+            }
+        });
+    }
+
+    /// Mail a password reset link to the user, unknown emails are ignored silently
+    pub(crate) async fn send_password_reset(&self, email: &str) -> Result<(), AppError> {
+        let user: Option<User> = sqlx::query_as(
+            "SELECT id, ws_id, fullname, email, created_at FROM users WHERE email = $1 AND NOT is_bot",
+        )
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(user) = user else {
+            return Ok(());
+        };
+
+        let token = self
+            .issue_user_token(user.id, PASSWORD_RESET, PASSWORD_RESET_SECS)
+            .await?;
+        let link = format!(
+            "{}/reset-password?token={}",
+            self.config.mail.app_url, token
+        );
+        let body = format!(
+            "Hi {},\n\nReset your password with the link below within an hour:\n\n{}\n\n\
+            Ignore this mail if you didn't ask for it.\n",
+            user.fullname, link
+        );
+        self.mailer
+            .send(&Mail::new(&user.email, "Reset your password", body))
+            .await
+    }
+
+    /// Set a new password with the token, all sessions of the user are revoked
+    pub async fn reset_password(&self, input: &ResetPassword) -> Result<(), AppError> {
+        let password_hash = hash_password(&input.password)?;
+
+        let mut tx = self.pool.begin().await?;
+        let user_id = self
+            .use_user_token(&mut tx, &input.token, PASSWORD_RESET)
+            .await?;
+        // the reset link was mailed to the user, so the email is verified as well
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET password_hash = $1, email_verified_at = COALESCE(email_verified_at, NOW())
+            WHERE id = $2
+            "#,
+        )
+        .bind(password_hash)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        // the old password might be leaked, sign out everywhere
+        self.revoke_user_sessions(user_id as _).await
+    }
+
+    /// Mail a verification link to the user
+    pub async fn send_verification_email(&self, user: &User) -> Result<(), AppError> {
+        let token = self
+            .issue_user_token(user.id, EMAIL_VERIFY, EMAIL_VERIFY_SECS)
+            .await?;
+        let link = format!("{}/verify-email?token={}", self.config.mail.app_url, token);
+        let body = format!(
+            "Hi {},\n\nVerify your email with the link below:\n\n{}\n",
+            user.fullname, link
+        );
+        self.mailer
+            .send(&Mail::new(&user.email, "Verify your email", body))
+            .await
+    }
+
+    pub async fn verify_email(&self, input: &VerifyEmail) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let user_id = self
+            .use_user_token(&mut tx, &input.token, EMAIL_VERIFY)
+            .await?;
+        sqlx::query(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn is_email_verified(&self, user_id: u64) -> Result<bool, AppError> {
+        let verified: Option<(bool,)> =
+            sqlx::query_as("SELECT email_verified_at IS NOT NULL FROM users WHERE id = $1")
+                .bind(user_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(verified.is_some_and(|v| v.0))
+    }
+
+    /// Sign a one-time token for the purpose, previous tokens of the purpose are discarded
+    pub(crate) async fn issue_user_token(
+        &self,
+        user_id: i64,
+        purpose: &str,
+        duration: u64,
+    ) -> Result<String, AppError> {
+        let id = uuid::Uuid::now_v7().to_string();
+        let expires_at = Utc::now() + Duration::seconds(duration as i64);
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            UPDATE user_tokens
+            SET used_at = NOW()
+            WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(purpose)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO user_tokens (id, user_id, purpose, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(&id)
+        .bind(user_id)
+        .bind(purpose)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(self.ek.sign_once(user_id, purpose, &id, duration)?)
+    }
+
+    /// Check the signature of the token and mark it used, returns the user id
+    pub(crate) async fn use_user_token(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        token: &str,
+        purpose: &str,
+    ) -> Result<i64, AppError> {
+        let (user_id, id) = self
+            .dk
+            .verify_once(token, purpose)
+            .map_err(|e| AppError::UserTokenError(e.to_string()))?;
+
+        let ret = sqlx::query(
+            r#"
+            UPDATE user_tokens
+            SET used_at = NOW()
+            WHERE id = $1 AND user_id = $2 AND purpose = $3
+              AND used_at IS NULL AND expires_at > NOW()
+            "#,
+        )
+        .bind(&id)
+        .bind(user_id)
+        .bind(purpose)
+        .execute(&mut **tx)
+        .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::UserTokenError(
+                "token already used or expired".to_string(),
+            ));
+        }
+        Ok(user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mailer::MailerAdapter, models::SigninUser, CreateUser};
+    use anyhow::Result;
+
+    /// Get the token from the link in the latest mail
+    async fn latest_mail_token(state: &AppState) -> Result<String> {
+        let MailerAdapter::File(mailer) = &state.mailer else {
+            panic!("tests should use file mailer");
+        };
+        let mail = mailer.read_mails().await?.pop().expect("mail should exist");
+        let token = mail
+            .body
+            .split("token=")
+            .nth(1)
+            .and_then(|s| s.split_whitespace().next())
+            .expect("token should exist");
+        Ok(token.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        // unknown emails are ignored
+        state.send_password_reset("nobody@acme.org").await?;
+
+        state.send_password_reset("tchen@acme.org").await?;
+        let token = latest_mail_token(&state).await?;
+        let (session_id, _) = state.create_session(1).await?;
+
+        let input = ResetPassword {
+            token: token.clone(),
+            password: "hunter42".to_string(),
+        };
+        state.reset_password(&input).await?;
+        assert!(state.revoked.is_revoked(&session_id));
+
+        let user = state
+            .verify_user(&SigninUser::new("tchen@acme.org", "hunter42"))
+            .await?;
+        assert!(user.is_some());
+
+        // the token couldn't be used again
+        let ret = state.reset_password(&input).await;
+        assert!(matches!(ret, Err(AppError::UserTokenError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_email_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = CreateUser::new("new ws", "eve@acme.org", "Eve Chen", "hunter42");
+        let user = state.create_user(&input).await?;
+        assert!(!state.is_email_verified(user.id as _).await?);
+
+        state.send_verification_email(&user).await?;
+        let token = latest_mail_token(&state).await?;
+
+        // tokens are only accepted for their purpose
+        let input = ResetPassword {
+            token: token.clone(),
+            password: "hunter42".to_string(),
+        };
+        let ret = state.reset_password(&input).await;
+        assert!(matches!(ret, Err(AppError::UserTokenError(_))));
+
+        state.verify_email(&VerifyEmail { token }).await?;
+        assert!(state.is_email_verified(user.id as _).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,528 @@
+use crate::{AppError, AppState};
+use chat_core::{AgentArgs, AgentType, ChatAgent};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{types::Json, FromRow};
+use tracing::warn;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateAgent {
+    pub name: String,
+    pub r#type: AgentType,
+    pub prompt: String,
+    #[serde(default)]
+    pub args: AgentArgs,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateAgent {
+    pub prompt: String,
+    #[serde(default)]
+    pub args: AgentArgs,
+}
+
+/// An agent whose stored args could not be parsed as `AgentArgs`
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidAgentArgs {
+    pub id: i64,
+    pub name: String,
+    pub reason: String,
+}
+
+#[derive(Debug, FromRow)]
+struct RawAgentArgs {
+    id: i64,
+    name: String,
+    args: Value,
+}
+
+/// A stored agent with its args not parsed yet, so that one malformed row doesn't fail the query
+#[derive(Debug, FromRow)]
+struct RawChatAgent {
+    id: i64,
+    chat_id: i64,
+    user_id: i64,
+    name: String,
+    r#type: AgentType,
+    prompt: String,
+    args: Value,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Create a new agent in the chat and attach it to `chats.agents`
+    pub async fn create_agent(
+        &self,
+        input: CreateAgent,
+        chat_id: u64,
+    ) -> Result<ChatAgent, AppError> {
+        if input.name.trim().is_empty() {
+            return Err(AppError::CreateAgentError(
+                "Agent name cannot be empty".to_string(),
+            ));
+        }
+        validate_agent(&input.prompt, &input.args).map_err(AppError::CreateAgentError)?;
+
+        if self.agent_name_exists(&input.name).await? {
+            return Err(AppError::CreateAgentError(format!(
+                "Agent {} already exists",
+                input.name
+            )));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        // the agent id is allocated first so that its bot user gets a stable email
+        let (id,): (i64,) =
+            sqlx::query_as("SELECT nextval(pg_get_serial_sequence('chat_agents', 'id'))")
+                .fetch_one(&mut *tx)
+                .await?;
+
+        let (user_id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (ws_id, fullname, email, password_hash, is_bot)
+            SELECT ws_id, $1, $2, '', TRUE
+            FROM chats
+            WHERE id = $3
+            RETURNING id
+            "#,
+        )
+        .bind(&input.name)
+        .bind(bot_email(id))
+        .bind(chat_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO workspace_members (ws_id, user_id)
+            SELECT ws_id, $1
+            FROM users
+            WHERE id = $1
+            "#,
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let agent: ChatAgent = sqlx::query_as(
+            r#"
+            INSERT INTO chat_agents (id, chat_id, user_id, name, type, prompt, args)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, chat_id, user_id, name, type, prompt, args, created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .bind(chat_id as i64)
+        .bind(user_id)
+        .bind(&input.name)
+        .bind(input.r#type)
+        .bind(&input.prompt)
+        .bind(Json(&input.args))
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            // another agent with the name was created after the check above
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                AppError::CreateAgentError(format!("Agent {} already exists", input.name))
+            }
+            e => e.into(),
+        })?;
+
+        sqlx::query(
+            r#"
+            UPDATE chats
+            SET agents = array_append(agents, $1)
+            WHERE id = $2
+            "#,
+        )
+        .bind(agent.id)
+        .bind(chat_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(agent)
+    }
+
+    pub async fn agent_name_exists(&self, name: &str) -> Result<bool, AppError> {
+        let exists = sqlx::query(
+            r#"
+            SELECT 1
+            FROM chat_agents
+            WHERE name = $1
+            "#,
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(exists.is_some())
+    }
+
+    /// Agents with malformed args are listed with `invalid_args` set
+    pub async fn list_agents(&self, chat_id: u64) -> Result<Vec<ChatAgent>, AppError> {
+        let agents: Vec<RawChatAgent> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, user_id, name, type, prompt, args, created_at, updated_at
+            FROM chat_agents
+            WHERE chat_id = $1
+            ORDER BY id ASC
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(agents.into_iter().map(RawChatAgent::parse).collect())
+    }
+
+    pub async fn get_agent_by_id(
+        &self,
+        chat_id: u64,
+        agent_id: u64,
+    ) -> Result<Option<ChatAgent>, AppError> {
+        let agent: Option<RawChatAgent> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, user_id, name, type, prompt, args, created_at, updated_at
+            FROM chat_agents
+            WHERE chat_id = $1 AND id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(agent_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(agent.map(RawChatAgent::parse))
+    }
+
+    pub async fn update_agent(
+        &self,
+        input: UpdateAgent,
+        chat_id: u64,
+        agent_id: u64,
+    ) -> Result<ChatAgent, AppError> {
+        validate_agent(&input.prompt, &input.args).map_err(AppError::UpdateAgentError)?;
+
+        let agent = sqlx::query_as(
+            r#"
+            UPDATE chat_agents
+            SET prompt = $1, args = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE chat_id = $3 AND id = $4
+            RETURNING id, chat_id, user_id, name, type, prompt, args, created_at, updated_at
+            "#,
+        )
+        .bind(&input.prompt)
+        .bind(Json(&input.args))
+        .bind(chat_id as i64)
+        .bind(agent_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        agent.ok_or_else(|| AppError::NotFound(format!("Agent id {agent_id} in chat {chat_id}")))
+    }
+
+    /// Delete the agent and detach it from `chats.agents`, its bot user is kept as the author of
+    /// the messages it posted
+    pub async fn delete_agent(&self, chat_id: u64, agent_id: u64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let ret = sqlx::query(
+            r#"
+            DELETE FROM chat_agents
+            WHERE chat_id = $1 AND id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(agent_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "Agent id {agent_id} in chat {chat_id}"
+            )));
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE chats
+            SET agents = array_remove(agents, $1)
+            WHERE id = $2
+            "#,
+        )
+        .bind(agent_id as i64)
+        .bind(chat_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Parse the args of all stored agents and report the ones that are not valid `AgentArgs`
+    pub async fn check_agent_args(&self) -> Result<Vec<InvalidAgentArgs>, AppError> {
+        let rows: Vec<RawAgentArgs> = sqlx::query_as(
+            r#"
+            SELECT id, name, args
+            FROM chat_agents
+            ORDER BY id ASC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let invalid = rows
+            .into_iter()
+            .filter_map(|row| {
+                let reason = match serde_json::from_value::<AgentArgs>(row.args) {
+                    Ok(args) => args.validate().err()?,
+                    Err(e) => e.to_string(),
+                };
+                Some(InvalidAgentArgs {
+                    id: row.id,
+                    name: row.name,
+                    reason,
+                })
+            })
+            .collect();
+
+        Ok(invalid)
+    }
+}
+
+impl RawChatAgent {
+    /// Malformed args are replaced by the default ones, and the agent is marked invalid
+    fn parse(self) -> ChatAgent {
+        let (args, invalid_args) = match serde_json::from_value::<AgentArgs>(self.args) {
+            Ok(args) => (args, None),
+            Err(e) => {
+                warn!("Agent {} has malformed args: {}", self.id, e);
+                (AgentArgs::default(), Some(e.to_string()))
+            }
+        };
+        ChatAgent {
+            id: self.id,
+            chat_id: self.chat_id,
+            user_id: self.user_id,
+            name: self.name,
+            r#type: self.r#type,
+            prompt: self.prompt,
+            args,
+            invalid_args,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+}
+
+/// Email of the bot user of the agent, bot users can't sign in so it is only used as a unique key
+fn bot_email(agent_id: i64) -> String {
+    format!("agent-{}@bot.none", agent_id)
+}
+
+fn validate_agent(prompt: &str, args: &AgentArgs) -> Result<(), String> {
+    if prompt.trim().is_empty() {
+        return Err("Agent prompt cannot be empty".to_string());
+    }
+    args.validate()
+}
+
+#[cfg(test)]
+impl CreateAgent {
+    pub fn new(name: &str, r#type: AgentType, prompt: &str, args: AgentArgs) -> Self {
+        Self {
+            name: name.to_string(),
+            r#type,
+            prompt: prompt.to_string(),
+            args,
+        }
+    }
+}
+
+#[cfg(test)]
+impl UpdateAgent {
+    pub fn new(prompt: &str, args: AgentArgs) -> Self {
+        Self {
+            prompt: prompt.to_string(),
+            args,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chat_core::ModelArgs;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_create_and_list_agents_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = CreateAgent::new(
+            "code guru",
+            AgentType::Reply,
+            "You are a code guru",
+            AgentArgs::default(),
+        );
+        let agent = state
+            .create_agent(input, 1)
+            .await
+            .expect("create agent failed");
+        assert_eq!(agent.name, "code guru");
+        assert_eq!(agent.r#type, AgentType::Reply);
+
+        let bots = state.fetch_chat_users_by_ids(&[agent.user_id]).await?;
+        assert_eq!(bots.len(), 1);
+        assert_eq!(bots[0].fullname, "code guru");
+        assert!(bots[0].is_bot);
+
+        let agents = state.list_agents(1).await?;
+        assert_eq!(agents.len(), 2);
+        assert_eq!(agents[1].id, agent.id);
+
+        let chat = state.get_chat_by_id(1).await?.expect("chat should exists");
+        assert!(chat.agents.contains(&agent.id));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_invalid_agent_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        // duplicate name
+        let input = CreateAgent::new(
+            "translation",
+            AgentType::Proxy,
+            "translate",
+            AgentArgs::default(),
+        );
+        assert!(state.create_agent(input, 1).await.is_err());
+
+        // empty prompt
+        let input = CreateAgent::new("empty", AgentType::Proxy, " ", AgentArgs::default());
+        assert!(state.create_agent(input, 1).await.is_err());
+
+        // temperature out of range
+        let args = AgentArgs::Openai(ModelArgs {
+            temperature: Some(2.5),
+            ..Default::default()
+        });
+        let input = CreateAgent::new("bad args", AgentType::Tap, "tap", args);
+        assert!(matches!(
+            state.create_agent(input, 1).await,
+            Err(AppError::CreateAgentError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_agents_with_same_name_concurrently_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let input = || CreateAgent::new("twin", AgentType::Reply, "reply", AgentArgs::default());
+        let (a, b) = tokio::join!(
+            state.create_agent(input(), 1),
+            state.create_agent(input(), 1)
+        );
+        let (created, failed) = if a.is_ok() { (a, b) } else { (b, a) };
+        assert!(created.is_ok());
+        assert!(matches!(failed, Err(AppError::CreateAgentError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_agent_args_should_reject_base_url() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = json!({
+            "name": "exfil",
+            "type": "proxy",
+            "prompt": "translate",
+            "args": {"adapter": "openai", "baseUrl": "http://169.254.169.254/v1"}
+        });
+        assert!(serde_json::from_value::<CreateAgent>(input).is_err());
+        let input = json!({
+            "prompt": "translate",
+            "args": {"adapter": "ollama", "baseUrl": "http://127.0.0.1:11434"}
+        });
+        assert!(serde_json::from_value::<UpdateAgent>(input).is_err());
+
+        // rows written before the field was removed are not trusted either
+        sqlx::query(
+            r#"UPDATE chat_agents SET args = '{"adapter":"openai","baseUrl":"http://127.0.0.1"}' WHERE id = 1"#,
+        )
+        .execute(&state.pool)
+        .await?;
+        let invalid = state.check_agent_args().await?;
+        assert_eq!(invalid.len(), 1);
+        assert!(invalid[0].reason.contains("baseUrl"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_agent_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let args = AgentArgs::Ollama(ModelArgs {
+            temperature: Some(0.2),
+            ..Default::default()
+        });
+        let input = UpdateAgent::new("Reply in French", args.clone());
+        let agent = state.update_agent(input, 1, 1).await?;
+        assert_eq!(agent.prompt, "Reply in French");
+        assert_eq!(agent.args, args);
+        assert!(agent.updated_at >= agent.created_at);
+
+        // agent 1 doesn't belong to chat 2
+        let input = UpdateAgent::new("Reply in French", AgentArgs::default());
+        assert!(matches!(
+            state.update_agent(input, 2, 1).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_agent_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        state.delete_agent(1, 1).await?;
+        assert!(state.get_agent_by_id(1, 1).await?.is_none());
+
+        let chat = state.get_chat_by_id(1).await?.expect("chat should exists");
+        assert!(chat.agents.is_empty());
+
+        assert!(state.delete_agent(1, 1).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_agent_args_should_report_invalid_rows() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        assert!(state.check_agent_args().await?.is_empty());
+
+        // rows written before args were typed
+        sqlx::query("UPDATE chat_agents SET args = $1 WHERE id = 1")
+            .bind(json!({ "adapter": "openai", "temperature": "hot" }))
+            .execute(&state.pool)
+            .await?;
+
+        let invalid = state.check_agent_args().await?;
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].id, 1);
+        assert_eq!(invalid[0].name, "translation");
+
+        Ok(())
+    }
+}
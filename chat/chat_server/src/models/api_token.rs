@@ -0,0 +1,247 @@
+use crate::{AppError, AppState};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::{middlewares::API_TOKEN_PREFIX, ApiScope, User};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// Random bytes in an API token, hex encoded after the prefix
+const API_TOKEN_BYTES: usize = 32;
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateApiToken {
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    /// the token never expires if not set
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub id: i64,
+    pub ws_id: i64,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedApiToken {
+    /// shown only once, use it as the bearer token
+    pub token: String,
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+}
+
+#[derive(Debug, FromRow)]
+struct ApiTokenUser {
+    #[sqlx(flatten)]
+    user: User,
+    scopes: Vec<ApiScope>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Create an API token in the current workspace of the user. API tokens couldn't create
+    /// tokens, so that a leaked token couldn't be used to gain more scopes.
+    pub async fn create_api_token(
+        &self,
+        user: &User,
+        input: CreateApiToken,
+    ) -> Result<CreatedApiToken, AppError> {
+        if user.scopes.is_some() {
+            return Err(AppError::PermissionDenied(
+                "API tokens couldn't create API tokens".to_string(),
+            ));
+        }
+        let name = input.name.trim();
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(AppError::ApiTokenError(format!(
+                "name should be 1 to {MAX_NAME_LEN} characters"
+            )));
+        }
+        let mut scopes = Vec::with_capacity(input.scopes.len());
+        for scope in input.scopes {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        if scopes.is_empty() {
+            return Err(AppError::ApiTokenError(
+                "at least one scope is required".to_string(),
+            ));
+        }
+
+        let token = generate_api_token();
+        let expires_at = input
+            .expires_in_days
+            .map(|days| Utc::now() + Duration::days(days as i64));
+        let api_token = sqlx::query_as(
+            r#"
+            INSERT INTO api_tokens (user_id, ws_id, name, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, ws_id, name, scopes, expires_at, last_used_at, created_at
+            "#,
+        )
+        .bind(user.id)
+        .bind(user.ws_id)
+        .bind(name)
+        .bind(hash_api_token(&token))
+        .bind(&scopes)
+        .bind(expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(CreatedApiToken { token, api_token })
+    }
+
+    /// Tokens of the user which are not revoked, expired ones included
+    pub async fn list_api_tokens(&self, user_id: u64) -> Result<Vec<ApiToken>, AppError> {
+        let tokens = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, name, scopes, expires_at, last_used_at, created_at
+            FROM api_tokens
+            WHERE user_id = $1 AND revoked_at IS NULL
+            ORDER BY id
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    pub async fn revoke_api_token(&self, id: u64, user_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE api_tokens SET revoked_at = NOW()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("API token id {id}")));
+        }
+        Ok(())
+    }
+
+    /// Returns the user of the token in the workspace of the token, with the scopes of it.
+    /// Revoked or expired tokens are rejected, the use of the token is recorded.
+    pub async fn find_user_by_api_token(&self, token: &str) -> Result<User, AppError> {
+        let row: Option<ApiTokenUser> = sqlx::query_as(
+            r#"
+            WITH t AS (
+              UPDATE api_tokens SET last_used_at = NOW()
+              WHERE token_hash = $1 AND revoked_at IS NULL
+                AND (expires_at IS NULL OR expires_at > NOW())
+              RETURNING user_id, ws_id, scopes
+            )
+            SELECT u.id, t.ws_id, w.name AS ws_name, u.fullname, u.email, u.created_at, t.scopes
+            FROM t
+            JOIN users u ON u.id = t.user_id
+            JOIN workspaces w ON w.id = t.ws_id
+            "#,
+        )
+        .bind(hash_api_token(token))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let row = row.ok_or_else(|| AppError::ApiTokenError("invalid API token".to_string()))?;
+        Ok(User {
+            scopes: Some(row.scopes),
+            ..row.user
+        })
+    }
+}
+
+fn generate_api_token() -> String {
+    let mut buf = [0u8; API_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut buf);
+    format!("{API_TOKEN_PREFIX}{}", hex::encode(buf))
+}
+
+/// API tokens are random enough to skip salting
+fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    impl CreateApiToken {
+        pub fn new(name: &str, scopes: &[ApiScope]) -> Self {
+            Self {
+                name: name.to_string(),
+                scopes: scopes.to_vec(),
+                expires_in_days: None,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_token_should_verify_until_revoked() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+
+        let input = CreateApiToken::new("ci", &[ApiScope::ReadChats, ApiScope::SendMessages]);
+        let created = state.create_api_token(&user, input).await?;
+        assert!(created.token.starts_with(API_TOKEN_PREFIX));
+
+        let token_user = state.find_user_by_api_token(&created.token).await?;
+        assert_eq!(token_user.id, 1);
+        assert_eq!(token_user.ws_name, "acme");
+        assert!(token_user.has_scope(ApiScope::SendMessages));
+        assert!(!token_user.has_scope(ApiScope::UploadFiles));
+
+        let tokens = state.list_api_tokens(1).await?;
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0].last_used_at.is_some());
+
+        // API tokens couldn't mint tokens
+        let input = CreateApiToken::new("more", &[ApiScope::UploadFiles]);
+        let ret = state.create_api_token(&token_user, input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // tokens of others couldn't be revoked
+        let ret = state.revoke_api_token(created.api_token.id as _, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        state.revoke_api_token(created.api_token.id as _, 1).await?;
+        let ret = state.find_user_by_api_token(&created.token).await;
+        assert!(matches!(ret, Err(AppError::ApiTokenError(_))));
+        assert!(state.list_api_tokens(1).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_api_token_should_validate_input() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+
+        let ret = state
+            .create_api_token(&user, CreateApiToken::new("ci", &[]))
+            .await;
+        assert!(matches!(ret, Err(AppError::ApiTokenError(_))));
+
+        let ret = state
+            .create_api_token(&user, CreateApiToken::new(" ", &[ApiScope::ReadChats]))
+            .await;
+        assert!(matches!(ret, Err(AppError::ApiTokenError(_))));
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{AppError, AppState};
-use chat_core::{Chat, ChatType};
+use chat_core::{Chat, ChatRole, ChatType};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
@@ -58,6 +58,16 @@ impl AppState {
                 "Some of the members do not exist".to_string(),
             ));
         }
+        if users.iter().any(|u| u.is_bot) {
+            return Err(AppError::CreateChatError(
+                "Bot users cannot be chat members".to_string(),
+            ));
+        }
+        if !self.all_workspace_members(ws_id, &input.members).await? {
+            return Err(AppError::CreateChatError(
+                "Some of the members are not in the workspace".to_string(),
+            ));
+        }
 
         let chat_type = match (&input.name, len) {
             (None, 2) => ChatType::Single,
@@ -71,7 +81,8 @@ impl AppState {
             }
         };
 
-        let chat = sqlx::query_as(
+        let mut tx = self.pool.begin().await?;
+        let chat: Chat = sqlx::query_as(
             r#"
             INSERT INTO chats (ws_id, name, type, members)
             VALUES ($1, $2, $3, $4)
@@ -82,9 +93,17 @@ impl AppState {
         .bind(input.name)
         .bind(chat_type)
         .bind(input.members)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
+        sqlx::query("INSERT INTO chat_roles (chat_id, user_id, role) VALUES ($1, $2, $3)")
+            .bind(chat.id)
+            .bind(user_id as i64)
+            .bind(ChatRole::Creator)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
         Ok(chat)
     }
 
@@ -163,7 +182,25 @@ impl AppState {
                 "Some of the members do not exist".to_string(),
             ));
         }
+        if users.iter().any(|u| u.is_bot) {
+            return Err(AppError::UpdateChatError(
+                "Bot users cannot be chat members".to_string(),
+            ));
+        }
+        let chat = self
+            .get_chat_by_id(id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Chat id {id}")))?;
+        if !self
+            .all_workspace_members(chat.ws_id as _, &input.members)
+            .await?
+        {
+            return Err(AppError::UpdateChatError(
+                "Some of the members are not in the workspace".to_string(),
+            ));
+        }
 
+        let mut tx = self.pool.begin().await?;
         let chat = sqlx::query_as(
             r#"
             UPDATE chats
@@ -174,11 +211,19 @@ impl AppState {
         )
         .bind(input.r#type)
         .bind(input.name)
-        .bind(input.members)
+        .bind(&input.members)
         .bind(id as i64)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
+        // removed members lose their roles in the chat
+        sqlx::query("DELETE FROM chat_roles WHERE chat_id = $1 AND NOT user_id = ANY($2)")
+            .bind(id as i64)
+            .bind(&input.members)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
         Ok(chat)
     }
 
@@ -195,6 +240,19 @@ impl AppState {
 
         Ok(())
     }
+
+    /// Check if all the users are members of the workspace
+    async fn all_workspace_members(&self, ws_id: u64, user_ids: &[i64]) -> Result<bool, AppError> {
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM workspace_members WHERE ws_id = $1 AND user_id = ANY($2)",
+        )
+        .bind(ws_id as i64)
+        .bind(user_ids)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count as usize == user_ids.len())
+    }
 }
 
 #[cfg(test)]
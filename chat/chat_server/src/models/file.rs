@@ -5,14 +5,27 @@ use std::{
 
 use crate::{AppError, ChatFile};
 use sha1::{Digest, Sha1};
+use tokio::fs;
+use tracing::info;
+
+/// Extension of files whose names don't have a valid one
+const DEFAULT_EXT: &str = "bin";
+const MAX_EXT_LEN: usize = 10;
 
 impl ChatFile {
+    /// The extension is taken from the filename, which is given by the client, so anything but
+    /// a short alphanumeric extension is replaced to keep the path under the base dir
     pub fn new(ws_id: u64, filename: &str, data: &[u8]) -> Self {
         let hash = Sha1::digest(data);
+        let ext = filename
+            .rsplit_once('.')
+            .map(|(_, ext)| ext)
+            .filter(|ext| is_valid_ext(ext))
+            .unwrap_or(DEFAULT_EXT);
 
         Self {
             ws_id,
-            ext: filename.split(".").last().unwrap_or("txt").to_string(),
+            ext: ext.to_string(),
             hash: hex::encode(hash),
         }
     }
@@ -25,6 +38,18 @@ impl ChatFile {
         base_dir.join(self.hash_to_path())
     }
 
+    /// Write the data under the base dir, files are named by their hash so existing ones are kept
+    pub async fn save(&self, base_dir: &Path, data: &[u8]) -> Result<(), AppError> {
+        let path = self.path(base_dir);
+        if path.exists() {
+            info!("File already exists: {:?}", path);
+        } else {
+            fs::create_dir_all(path.parent().expect("File path parent should exists")).await?;
+            fs::write(path, data).await?;
+        }
+        Ok(())
+    }
+
     // split hash into 3 parts, first 2 with 3 chars
     fn hash_to_path(&self) -> String {
         let (part1, part2) = self.hash.split_at(3);
@@ -55,7 +80,10 @@ impl FromStr for ChatFile {
             )));
         };
 
-        let Some((part3, ext)) = parts[3].split_once('.') else {
+        let Some((part3, ext)) = parts[3]
+            .split_once('.')
+            .filter(|(_, ext)| is_valid_ext(ext))
+        else {
             return Err(AppError::ChatFileError(format!(
                 "Invalid file name: {}",
                 parts[3]
@@ -71,6 +99,10 @@ impl FromStr for ChatFile {
     }
 }
 
+fn is_valid_ext(ext: &str) -> bool {
+    (1..=MAX_EXT_LEN).contains(&ext.len()) && ext.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,4 +117,27 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_chat_file_should_sanitize_ext() -> Result<()> {
+        for name in [
+            "x./../../../etc/foo",
+            "x.",
+            "README",
+            "a.verylongextension",
+            "a.p%6eg",
+        ] {
+            let file = ChatFile::new(1, name, b"hello world");
+            assert_eq!(file.ext, "bin");
+            let path = file.path(Path::new("/tmp/chat"));
+            assert!(path.starts_with("/tmp/chat/1"));
+            assert!(!path.to_string_lossy().contains(".."));
+        }
+        assert_eq!(ChatFile::new(1, "a.tar.gz", b"").ext, "gz");
+
+        let ret = "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.a%2fb".parse::<ChatFile>();
+        assert!(ret.is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,421 @@
+use crate::{
+    config::OidcProviderConfig,
+    oidc::{AuthRequest, Identity},
+    AppError, AppState,
+};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::User;
+use chrono::{Duration, Utc};
+use jwt_simple::reexports::ct_codecs::{Base64UrlSafeNoPadding, Encoder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
+
+/// Lifetime of a pending authorization request, in minutes
+const OIDC_REQUEST_MINUTES: i64 = 10;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcProvider {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, IntoParams, Serialize, Deserialize)]
+pub struct OidcAuthorize {
+    /// email of the user, passed on to the provider to skip the account selection
+    #[serde(default)]
+    pub login_hint: Option<String>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcAuthorizeOutput {
+    /// url of the provider to redirect the user to
+    pub url: String,
+}
+
+/// Query params of the redirect from the provider
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct OidcCallback {
+    pub code: String,
+    pub state: String,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub fn list_oidc_providers(&self) -> Vec<OidcProvider> {
+        self.config
+            .oidc
+            .iter()
+            .map(|p| OidcProvider {
+                name: p.name.clone(),
+            })
+            .collect()
+    }
+
+    /// Start an authorization code flow with PKCE, the request is kept until its callback
+    pub async fn oidc_authorize(
+        &self,
+        provider: &str,
+        input: &OidcAuthorize,
+    ) -> Result<OidcAuthorizeOutput, AppError> {
+        let provider = self.find_oidc_provider(provider)?;
+        let state = random_string(16);
+        let nonce = random_string(16);
+        let code_verifier = random_string(32);
+        let code_challenge =
+            Base64UrlSafeNoPadding::encode_to_string(Sha256::digest(code_verifier.as_bytes()))
+                .map_err(|e| AppError::OidcError(e.to_string()))?;
+
+        let req = AuthRequest {
+            state: &state,
+            nonce: &nonce,
+            code_challenge: &code_challenge,
+            login_hint: input.login_hint.as_deref(),
+        };
+        let url = self.oidc.authorize_url(provider, &req).await?;
+
+        sqlx::query("DELETE FROM oidc_requests WHERE expires_at < NOW()")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO oidc_requests (state, provider, nonce, code_verifier, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(&state)
+        .bind(&provider.name)
+        .bind(&nonce)
+        .bind(&code_verifier)
+        .bind(Utc::now() + Duration::minutes(OIDC_REQUEST_MINUTES))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(OidcAuthorizeOutput { url })
+    }
+
+    /// Finish the authorization code flow, returns the user of the identity scoped to the
+    /// workspace mapped from the tenant or the verified email domain.
+    ///
+    /// - A known identity signs in as its user.
+    /// - Otherwise the identity is linked to the user of the same email, or a new user
+    ///   without password, as long as the provider has verified the email.
+    /// - The user joins the workspace unless an admin has removed or deactivated the user.
+    pub async fn oidc_signin(
+        &self,
+        provider: &str,
+        input: &OidcCallback,
+    ) -> Result<User, AppError> {
+        let provider = self.find_oidc_provider(provider)?;
+        let request: Option<(String, String)> = sqlx::query_as(
+            r#"
+            DELETE FROM oidc_requests
+            WHERE state = $1 AND provider = $2 AND expires_at > NOW()
+            RETURNING nonce, code_verifier
+            "#,
+        )
+        .bind(&input.state)
+        .bind(&provider.name)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((nonce, code_verifier)) = request else {
+            return Err(AppError::OidcError("unknown or expired state".to_string()));
+        };
+
+        let identity = self
+            .oidc
+            .exchange(provider, &input.code, &code_verifier, &nonce)
+            .await?;
+        let ws_name = identity_workspace(provider, &identity).ok_or_else(|| {
+            AppError::PermissionDenied(format!(
+                "No workspace for the identity of provider {}",
+                provider.name
+            ))
+        })?;
+        let ws = self
+            .find_workspace_by_name(ws_name)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Workspace {ws_name}")))?;
+
+        let user_id = self.link_identity(&provider.name, &identity, ws.id).await?;
+        // users join the workspace of their identity, and it becomes the active one
+        sqlx::query(
+            r#"
+            INSERT INTO workspace_members (ws_id, user_id)
+            SELECT $1, $2
+            WHERE NOT EXISTS (
+              SELECT 1 FROM workspace_removals WHERE ws_id = $1 AND user_id = $2
+            )
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(ws.id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        if self
+            .get_workspace_role(ws.id as _, user_id as _)
+            .await?
+            .is_none()
+        {
+            return Err(AppError::PermissionDenied(format!(
+                "Not a member of workspace {}",
+                ws.name
+            )));
+        }
+
+        self.switch_workspace(ws.id as _, user_id as _).await
+    }
+
+    /// Find the user of the identity, or link it to a user by email. Returns the user id.
+    async fn link_identity(
+        &self,
+        provider: &str,
+        identity: &Identity,
+        ws_id: i64,
+    ) -> Result<i64, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let linked: Option<(i64,)> = sqlx::query_as(
+            "SELECT user_id FROM user_identities WHERE provider = $1 AND subject = $2",
+        )
+        .bind(provider)
+        .bind(&identity.subject)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some((user_id,)) = linked {
+            return Ok(user_id);
+        }
+
+        let email = identity
+            .email
+            .as_deref()
+            .ok_or_else(|| AppError::OidcError("ID token without email".to_string()))?;
+        // otherwise anyone could take over the account of the email
+        if !identity.email_verified {
+            return Err(AppError::OidcError(format!(
+                "email {email} is not verified by the provider"
+            )));
+        }
+
+        let user: Option<(i64,)> =
+            sqlx::query_as("SELECT id FROM users WHERE email = $1 AND NOT is_bot")
+                .bind(email)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let user_id = match user {
+            Some((user_id,)) => {
+                sqlx::query(
+                    "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+                )
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+                user_id
+            }
+            None => {
+                let fullname = identity
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| email.split('@').next().unwrap_or(email).to_string());
+                let (user_id,): (i64,) = sqlx::query_as(
+                    r#"
+                    INSERT INTO users (ws_id, email, fullname, email_verified_at)
+                    VALUES ($1, $2, $3, NOW())
+                    RETURNING id
+                    "#,
+                )
+                .bind(ws_id)
+                .bind(email)
+                .bind(fullname)
+                .fetch_one(&mut *tx)
+                .await?;
+                user_id
+            }
+        };
+
+        sqlx::query("INSERT INTO user_identities (provider, subject, user_id) VALUES ($1, $2, $3)")
+            .bind(provider)
+            .bind(&identity.subject)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(user_id)
+    }
+
+    fn find_oidc_provider(&self, name: &str) -> Result<&OidcProviderConfig, AppError> {
+        self.config
+            .oidc
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| AppError::NotFound(format!("OIDC provider {name}")))
+    }
+}
+
+/// The tenant is matched first, then the email domain if the provider has verified the email
+fn identity_workspace<'a>(
+    provider: &'a OidcProviderConfig,
+    identity: &Identity,
+) -> Option<&'a String> {
+    let domain = identity
+        .email
+        .as_deref()
+        .filter(|_| identity.email_verified)
+        .and_then(|email| email.rsplit_once('@'))
+        .map(|(_, domain)| domain.to_lowercase());
+    identity
+        .tenant
+        .as_ref()
+        .and_then(|tenant| provider.workspaces.get(tenant))
+        .or_else(|| domain.and_then(|domain| provider.workspaces.get(&domain)))
+}
+
+/// Random bytes, base64url encoded
+fn random_string(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buf);
+    Base64UrlSafeNoPadding::encode_to_string(buf).expect("encode random bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        oidc::mock::{MockIdp, MOCK_TENANT},
+        UpdateMember,
+    };
+    use anyhow::Result;
+
+    async fn signin(state: &AppState, idp: &MockIdp, email: &str) -> Result<User, AppError> {
+        let input = OidcAuthorize {
+            login_hint: Some(email.to_string()),
+        };
+        let output = state.oidc_authorize("corp", &input).await?;
+        let (code, oidc_state) = idp.authorize(&output.url).await?;
+        let input = OidcCallback {
+            code,
+            state: oidc_state,
+        };
+        state.oidc_signin("corp", &input).await
+    }
+
+    #[tokio::test]
+    async fn test_oidc_signin_should_link_identities() -> Result<()> {
+        let idp = MockIdp::start().await?;
+        let (_tdb, state) = AppState::try_new_for_test_with(|config| {
+            config.oidc = vec![idp.provider("corp", "foo")];
+        })
+        .await?;
+
+        // a new user joins the workspace mapped from the tenant
+        let user = signin(&state, &idp, "eve@corp.org").await?;
+        assert_eq!(user.ws_name, "foo");
+        assert!(state.is_email_verified(user.id as _).await?);
+        let user2 = signin(&state, &idp, "eve@corp.org").await?;
+        assert_eq!(user.id, user2.id);
+
+        // an existing user is linked by email, and joins the workspace as well
+        let user = signin(&state, &idp, "tchen@acme.org").await?;
+        assert_eq!(user.id, 1);
+        assert_eq!(user.ws_name, "foo");
+        assert_eq!(state.list_user_workspaces(1).await?.len(), 2);
+
+        // the workspace without owner stays so
+        let ws = state.find_workspace_by_name("foo").await?.unwrap();
+        assert_eq!(ws.owner_id, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oidc_signin_should_not_add_back_removed_members() -> Result<()> {
+        let idp = MockIdp::start().await?;
+        let (_tdb, state) = AppState::try_new_for_test_with(|config| {
+            config.oidc = vec![idp.provider("corp", "acme")];
+        })
+        .await?;
+
+        // alice is member 2 of acme, deactivated by the owner
+        signin(&state, &idp, "alice@acme.org").await?;
+        let input = UpdateMember {
+            active: Some(false),
+            ..Default::default()
+        };
+        state.update_workspace_member(input, 1, 2, 1).await?;
+        let ret = signin(&state, &idp, "alice@acme.org").await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // bob is removed
+        signin(&state, &idp, "bob@acme.org").await?;
+        state.remove_workspace_member(1, 3, 1).await?;
+        let ret = signin(&state, &idp, "bob@acme.org").await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        assert!(state.get_workspace_member(1, 3).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oidc_signin_should_reject_unverified_email() -> Result<()> {
+        let idp = MockIdp::start().await?;
+        let (_tdb, state) = AppState::try_new_for_test_with(|config| {
+            config.oidc = vec![idp.provider("corp", "foo")];
+        })
+        .await?;
+
+        let ret = signin(&state, &idp, "unverified@acme.org").await;
+        assert!(matches!(ret, Err(AppError::OidcError(_))));
+
+        // the state could be used only once
+        let input = OidcAuthorize {
+            login_hint: Some("eve@corp.org".to_string()),
+        };
+        let output = state.oidc_authorize("corp", &input).await?;
+        let (code, oidc_state) = idp.authorize(&output.url).await?;
+        let input = OidcCallback {
+            code,
+            state: oidc_state,
+        };
+        state.oidc_signin("corp", &input).await?;
+        let ret = state.oidc_signin("corp", &input).await;
+        assert!(matches!(ret, Err(AppError::OidcError(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn identity_workspace_should_match_tenant_then_domain() {
+        let mut provider = OidcProviderConfig {
+            name: "corp".to_string(),
+            issuer: "http://localhost".to_string(),
+            client_id: "client".to_string(),
+            client_secret: None,
+            redirect_url: "http://localhost/callback".to_string(),
+            scopes: vec![],
+            tenant_claim: Some("tid".to_string()),
+            workspaces: [(MOCK_TENANT.to_string(), "foo".to_string())].into(),
+        };
+        provider
+            .workspaces
+            .insert("acme.org".to_string(), "acme".to_string());
+        let mut identity = Identity {
+            subject: "1".to_string(),
+            email: Some("eve@Acme.org".to_string()),
+            email_verified: true,
+            name: None,
+            tenant: Some(MOCK_TENANT.to_string()),
+        };
+
+        assert_eq!(identity_workspace(&provider, &identity).unwrap(), "foo");
+        identity.tenant = None;
+        assert_eq!(identity_workspace(&provider, &identity).unwrap(), "acme");
+        // anyone could claim an unverified email
+        identity.email_verified = false;
+        assert!(identity_workspace(&provider, &identity).is_none());
+        identity.email_verified = true;
+        identity.email = Some("eve@other.org".to_string());
+        assert!(identity_workspace(&provider, &identity).is_none());
+    }
+}
@@ -0,0 +1,432 @@
+use crate::{AppError, AppState, ChatFile, CreateMessage};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::{Message, SenderType};
+use chrono::{DateTime, Utc};
+use jwt_simple::reexports::ct_codecs::{Base64, Decoder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use tokio::fs;
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Random bytes in a webhook token, hex encoded
+const WEBHOOK_TOKEN_BYTES: usize = 32;
+const MAX_NAME_LEN: usize = 64;
+const MAX_ATTACHMENTS: usize = 10;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateIncomingWebhook {
+    /// shown as the sender of the messages
+    pub name: String,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingWebhook {
+    pub id: i64,
+    pub chat_id: i64,
+    /// the bot user the messages are posted as
+    pub user_id: i64,
+    pub name: String,
+    pub created_by: i64,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedIncomingWebhook {
+    /// shown only once, post messages to it without any other credential
+    pub url: String,
+    #[serde(flatten)]
+    pub webhook: IncomingWebhook,
+}
+
+/// Payload of an incoming webhook, it is posted as a `CreateMessage`
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct WebhookMessage {
+    pub content: String,
+    /// files saved to the workspace and attached to the message
+    #[serde(default)]
+    pub attachments: Vec<WebhookAttachment>,
+    /// reply in the thread of the message
+    #[serde(default)]
+    pub parent_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct WebhookAttachment {
+    pub filename: String,
+    /// base64 encoded content of the file
+    pub data: String,
+}
+
+#[derive(Debug, FromRow)]
+struct WebhookTarget {
+    id: i64,
+    chat_id: i64,
+    user_id: i64,
+    ws_id: i64,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Create an incoming webhook for the chat, with a bot user posting its messages
+    pub async fn create_incoming_webhook(
+        &self,
+        input: CreateIncomingWebhook,
+        chat_id: u64,
+        created_by: u64,
+    ) -> Result<CreatedIncomingWebhook, AppError> {
+        let name = input.name.trim();
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(AppError::WebhookError(format!(
+                "name should be 1 to {MAX_NAME_LEN} characters"
+            )));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        // the webhook id is allocated first so that its bot user gets a stable email
+        let (id,): (i64,) =
+            sqlx::query_as("SELECT nextval(pg_get_serial_sequence('incoming_webhooks', 'id'))")
+                .fetch_one(&mut *tx)
+                .await?;
+
+        let (user_id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (ws_id, fullname, email, password_hash, is_bot)
+            SELECT ws_id, $1, $2, '', TRUE
+            FROM chats
+            WHERE id = $3
+            RETURNING id
+            "#,
+        )
+        .bind(name)
+        .bind(bot_email(id))
+        .bind(chat_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO workspace_members (ws_id, user_id)
+            SELECT ws_id, $1
+            FROM users
+            WHERE id = $1
+            "#,
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let token = generate_webhook_token();
+        let webhook = sqlx::query_as(
+            r#"
+            INSERT INTO incoming_webhooks (id, chat_id, user_id, name, token_hash, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, chat_id, user_id, name, created_by, last_used_at, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(chat_id as i64)
+        .bind(user_id)
+        .bind(name)
+        .bind(hash_webhook_token(&token))
+        .bind(created_by as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(CreatedIncomingWebhook {
+            url: format!("/api/hooks/{token}"),
+            webhook,
+        })
+    }
+
+    pub async fn list_incoming_webhooks(
+        &self,
+        chat_id: u64,
+    ) -> Result<Vec<IncomingWebhook>, AppError> {
+        let webhooks = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, user_id, name, created_by, last_used_at, created_at
+            FROM incoming_webhooks
+            WHERE chat_id = $1 AND revoked_at IS NULL
+            ORDER BY id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(webhooks)
+    }
+
+    /// Revoke the webhook, its bot user is kept as the author of the messages it posted
+    pub async fn revoke_incoming_webhook(&self, chat_id: u64, id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE incoming_webhooks SET revoked_at = NOW()
+            WHERE chat_id = $1 AND id = $2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "Webhook id {id} in chat {chat_id}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Post the payload into the chat of the webhook, as its bot user. The attachments are
+    /// saved to the workspace of the chat like uploaded files, and removed again if the message
+    /// couldn't be posted.
+    pub async fn post_webhook_message(
+        &self,
+        token: &str,
+        input: WebhookMessage,
+    ) -> Result<Message, AppError> {
+        let target: Option<WebhookTarget> = sqlx::query_as(
+            r#"
+            SELECT h.id, h.chat_id, h.user_id, c.ws_id
+            FROM incoming_webhooks h
+            JOIN chats c ON c.id = h.chat_id
+            WHERE h.token_hash = $1 AND h.revoked_at IS NULL
+            "#,
+        )
+        .bind(hash_webhook_token(token))
+        .fetch_optional(&self.pool)
+        .await?;
+        let target = target.ok_or_else(|| AppError::NotFound("Webhook".to_string()))?;
+
+        if let Err(retry_after) = self.webhook_limit.check(&format!("webhook:{}", target.id)) {
+            let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            return Err(AppError::TooManyRequests(secs));
+        }
+
+        if input.attachments.len() > MAX_ATTACHMENTS {
+            return Err(AppError::WebhookError(format!(
+                "at most {MAX_ATTACHMENTS} attachments are allowed"
+            )));
+        }
+        // decode all attachments before saving any of them
+        let attachments = input
+            .attachments
+            .iter()
+            .map(|a| {
+                let data = Base64::decode_to_vec(&a.data, None).map_err(|_| {
+                    AppError::WebhookError(format!("attachment {} is not base64", a.filename))
+                })?;
+                Ok((ChatFile::new(target.ws_id as _, &a.filename, &data), data))
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+
+        // files are named by their hash, only the ones saved here are removed on errors
+        let base_dir = &self.config.server.base_dir;
+        let mut saved = vec![];
+        let ret = async {
+            let mut files = Vec::with_capacity(attachments.len());
+            for (file, data) in &attachments {
+                let path = file.path(base_dir);
+                if !path.exists() {
+                    file.save(base_dir, data).await?;
+                    saved.push(path);
+                }
+                files.push(file.url());
+            }
+
+            let input = CreateMessage {
+                content: input.content,
+                files,
+                parent_id: input.parent_id,
+            };
+            self.create_message_as(
+                input,
+                target.chat_id as _,
+                target.user_id as _,
+                SenderType::Integration,
+            )
+            .await
+        }
+        .await;
+
+        let message = match ret {
+            Ok(message) => message,
+            Err(e) => {
+                for path in saved {
+                    if let Err(e) = fs::remove_file(&path).await {
+                        warn!("Failed to remove attachment {:?}: {}", path, e);
+                    }
+                }
+                return Err(e);
+            }
+        };
+
+        sqlx::query("UPDATE incoming_webhooks SET last_used_at = NOW() WHERE id = $1")
+            .bind(target.id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(message)
+    }
+}
+
+/// Email of the bot user of the webhook, bot users can't sign in so it is only used as a unique key
+fn bot_email(webhook_id: i64) -> String {
+    format!("webhook-{}@bot.none", webhook_id)
+}
+
+fn generate_webhook_token() -> String {
+    let mut buf = [0u8; WEBHOOK_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+/// Webhook tokens are random enough to skip salting
+fn hash_webhook_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use jwt_simple::reexports::ct_codecs::Encoder;
+
+    impl WebhookMessage {
+        pub fn new(content: &str) -> Self {
+            Self {
+                content: content.to_string(),
+                attachments: vec![],
+                parent_id: None,
+            }
+        }
+    }
+
+    fn token_of(created: &CreatedIncomingWebhook) -> &str {
+        created
+            .url
+            .rsplit('/')
+            .next()
+            .expect("url should have token")
+    }
+
+    #[tokio::test]
+    async fn test_webhook_should_post_as_integration() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let input = CreateIncomingWebhook {
+            name: "CI".to_string(),
+        };
+        let created = state.create_incoming_webhook(input, 1, 1).await?;
+        assert_eq!(state.list_incoming_webhooks(1).await?.len(), 1);
+
+        let mut input = WebhookMessage::new("Deploy finished");
+        input.attachments.push(WebhookAttachment {
+            filename: "report.txt".to_string(),
+            data: Base64::encode_to_string(b"all green")?,
+        });
+        let message = state
+            .post_webhook_message(token_of(&created), input)
+            .await?;
+        assert_eq!(message.chat_id, 1);
+        assert_eq!(message.sender_id, created.webhook.user_id);
+        assert_eq!(message.sender_type, SenderType::Integration);
+        assert_eq!(message.files.len(), 1);
+
+        let file = message.files[0].parse::<ChatFile>()?;
+        assert_eq!(file.ws_id, 1);
+        assert!(file.path(&state.config.server.base_dir).exists());
+
+        let sender = state.find_user_by_id(created.webhook.user_id).await?;
+        assert_eq!(sender.expect("bot user should exist").fullname, "CI");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_webhook_post_should_leave_no_trace() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let input = CreateIncomingWebhook {
+            name: "CI".to_string(),
+        };
+        let created = state.create_incoming_webhook(input, 1, 1).await?;
+
+        // the message is rejected after the attachment is saved
+        let mut input = WebhookMessage::new("");
+        input.attachments.push(WebhookAttachment {
+            filename: "report.txt".to_string(),
+            data: Base64::encode_to_string(b"never posted")?,
+        });
+        let ret = state.post_webhook_message(token_of(&created), input).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        let file = ChatFile::new(1, "report.txt", b"never posted");
+        assert!(!file.path(&state.config.server.base_dir).exists());
+        let webhooks = state.list_incoming_webhooks(1).await?;
+        assert_eq!(webhooks[0].last_used_at, None);
+
+        state
+            .post_webhook_message(token_of(&created), WebhookMessage::new("hi"))
+            .await?;
+        let webhooks = state.list_incoming_webhooks(1).await?;
+        assert!(webhooks[0].last_used_at.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_webhook_attachment_should_stay_in_base_dir() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let input = CreateIncomingWebhook {
+            name: "CI".to_string(),
+        };
+        let created = state.create_incoming_webhook(input, 1, 1).await?;
+
+        let mut input = WebhookMessage::new("Deploy finished");
+        input.attachments.push(WebhookAttachment {
+            filename: "x./../../../../etc/foo".to_string(),
+            data: Base64::encode_to_string(b"escaped?")?,
+        });
+        let message = state
+            .post_webhook_message(token_of(&created), input)
+            .await?;
+
+        let file = message.files[0].parse::<ChatFile>()?;
+        assert_eq!(file.ext, "bin");
+        let base_dir = &state.config.server.base_dir;
+        let path = file.path(base_dir);
+        assert!(path.starts_with(base_dir) && path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_revoked_webhook_should_not_post() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let input = CreateIncomingWebhook {
+            name: "CI".to_string(),
+        };
+        let created = state.create_incoming_webhook(input, 1, 1).await?;
+
+        let mut input = WebhookMessage::new("Deploy finished");
+        input.attachments.push(WebhookAttachment {
+            filename: "report.txt".to_string(),
+            data: "not base64!".to_string(),
+        });
+        let ret = state.post_webhook_message(token_of(&created), input).await;
+        assert!(matches!(ret, Err(AppError::WebhookError(_))));
+
+        state
+            .revoke_incoming_webhook(1, created.webhook.id as _)
+            .await?;
+        assert!(state.list_incoming_webhooks(1).await?.is_empty());
+        let ret = state
+            .post_webhook_message(token_of(&created), WebhookMessage::new("hi"))
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+}
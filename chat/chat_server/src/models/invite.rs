@@ -0,0 +1,365 @@
+use crate::{AppError, AppState};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::Workspace;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Postgres, Transaction};
+use utoipa::ToSchema;
+
+/// Default lifetime of an invite, in hours
+const DEFAULT_INVITE_HOURS: u32 = 72;
+/// Max lifetime of an invite, in hours
+const MAX_INVITE_HOURS: u32 = 24 * 30;
+/// Random bytes in an invite token, hex encoded
+const INVITE_TOKEN_BYTES: usize = 24;
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct CreateInvite {
+    /// if set, only the user signing up with this email could accept the invite
+    #[serde(default)]
+    pub email: Option<String>,
+    /// hours before the invite expires, 72 if not set
+    #[serde(default)]
+    pub expires_in_hours: Option<u32>,
+    /// times the invite could be accepted, unlimited until it expires if not set
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceInvite {
+    pub id: i64,
+    pub ws_id: i64,
+    pub token: String,
+    pub email: Option<String>,
+    pub created_by: i64,
+    pub expires_at: DateTime<Utc>,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Create an invite to the workspace, only the workspace owner could invite others
+    pub async fn create_invite(
+        &self,
+        input: CreateInvite,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<WorkspaceInvite, AppError> {
+        self.check_workspace_owner(ws_id, user_id).await?;
+
+        let hours = input.expires_in_hours.unwrap_or(DEFAULT_INVITE_HOURS);
+        if hours == 0 || hours > MAX_INVITE_HOURS {
+            return Err(AppError::InviteError(format!(
+                "expires_in_hours must be between 1 and {MAX_INVITE_HOURS}"
+            )));
+        }
+        if input.max_uses == Some(0) {
+            return Err(AppError::InviteError(
+                "max_uses must be greater than 0".to_string(),
+            ));
+        }
+        let email = match input.email.as_deref().map(str::trim) {
+            Some("") | None => None,
+            Some(email) if !email.contains('@') => {
+                return Err(AppError::InviteError(format!("Invalid email: {email}")));
+            }
+            Some(email) => Some(email.to_string()),
+        };
+
+        let invite = sqlx::query_as(
+            r#"
+            INSERT INTO workspace_invites (ws_id, token, email, created_by, expires_at, max_uses)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, ws_id, token, email, created_by, expires_at, max_uses, use_count, revoked_at, created_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(generate_invite_token())
+        .bind(email)
+        .bind(user_id as i64)
+        .bind(Utc::now() + Duration::hours(hours as _))
+        .bind(input.max_uses.map(|n| n as i32))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(invite)
+    }
+
+    /// List all invites of the workspace, latest first
+    pub async fn list_invites(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<WorkspaceInvite>, AppError> {
+        self.check_workspace_owner(ws_id, user_id).await?;
+
+        let invites = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, token, email, created_by, expires_at, max_uses, use_count, revoked_at, created_at
+            FROM workspace_invites
+            WHERE ws_id = $1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(invites)
+    }
+
+    /// Revoke the invite so that it couldn't be accepted anymore, revoking twice is a no-op
+    pub async fn revoke_invite(
+        &self,
+        ws_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<WorkspaceInvite, AppError> {
+        self.check_workspace_owner(ws_id, user_id).await?;
+
+        let invite = sqlx::query_as(
+            r#"
+            UPDATE workspace_invites
+            SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND ws_id = $2
+            RETURNING id, ws_id, token, email, created_by, expires_at, max_uses, use_count, revoked_at, created_at
+            "#,
+        )
+        .bind(id as i64)
+        .bind(ws_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        invite.ok_or_else(|| AppError::NotFound(format!("Invite id {id}")))
+    }
+
+    /// Accept the invite for the email within the signup transaction.
+    /// Returns the workspace the user is invited to.
+    pub(crate) async fn accept_invite(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        token: &str,
+        email: &str,
+    ) -> Result<Workspace, AppError> {
+        // lock the invite so that concurrent signups couldn't exceed max_uses
+        let invite: WorkspaceInvite = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, token, email, created_by, expires_at, max_uses, use_count, revoked_at, created_at
+            FROM workspace_invites
+            WHERE token = $1
+            FOR UPDATE
+            "#,
+        )
+        .bind(token)
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or_else(|| AppError::InviteError("invite not found".to_string()))?;
+
+        if invite.revoked_at.is_some() {
+            return Err(AppError::InviteError("invite is revoked".to_string()));
+        }
+        if invite.expires_at <= Utc::now() {
+            return Err(AppError::InviteError("invite is expired".to_string()));
+        }
+        if invite.max_uses.is_some_and(|n| invite.use_count >= n) {
+            return Err(AppError::InviteError("invite is used up".to_string()));
+        }
+        if invite
+            .email
+            .as_deref()
+            .is_some_and(|e| !e.eq_ignore_ascii_case(email))
+        {
+            return Err(AppError::InviteError(format!("invite is not for {email}")));
+        }
+
+        sqlx::query("UPDATE workspace_invites SET use_count = use_count + 1 WHERE id = $1")
+            .bind(invite.id)
+            .execute(&mut **tx)
+            .await?;
+
+        let ws = sqlx::query_as(
+            r#"
+            SELECT id, name, owner_id, created_at
+            FROM workspaces
+            WHERE id = $1
+            "#,
+        )
+        .bind(invite.ws_id)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        Ok(ws)
+    }
+
+    /// Get the workspace if the user owns it
+    async fn check_workspace_owner(&self, ws_id: u64, user_id: u64) -> Result<Workspace, AppError> {
+        let ws = self
+            .find_workspace_by_id(ws_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Workspace id {ws_id}")))?;
+
+        if ws.owner_id != user_id as i64 {
+            return Err(AppError::PermissionDenied(
+                "Only the workspace owner could manage invites".to_string(),
+            ));
+        }
+        Ok(ws)
+    }
+}
+
+fn generate_invite_token() -> String {
+    let mut buf = [0u8; INVITE_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateUser;
+    use anyhow::Result;
+
+    fn signup(email: &str, invite: &WorkspaceInvite) -> CreateUser {
+        let mut input = CreateUser::new("", email, "Invited User", "hunter42");
+        input.invite = Some(invite.token.clone());
+        input
+    }
+
+    #[tokio::test]
+    async fn test_create_and_list_invites_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = CreateInvite {
+            email: Some("eve@acme.org".to_string()),
+            ..Default::default()
+        };
+        let invite = state.create_invite(input, 1, 1).await?;
+        assert_eq!(invite.ws_id, 1);
+        assert_eq!(invite.token.len(), INVITE_TOKEN_BYTES * 2);
+        assert_eq!(invite.email.as_deref(), Some("eve@acme.org"));
+        assert!(invite.expires_at > Utc::now() + Duration::hours(71));
+
+        let input = CreateInvite {
+            max_uses: Some(2),
+            ..Default::default()
+        };
+        let invite2 = state.create_invite(input, 1, 1).await?;
+        assert_ne!(invite.token, invite2.token);
+
+        let invites = state.list_invites(1, 1).await?;
+        assert_eq!(invites.len(), 2);
+        assert_eq!(invites[0].id, invite2.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invites_should_be_managed_by_owner_only() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let ret = state.create_invite(CreateInvite::default(), 1, 2).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let invite = state.create_invite(CreateInvite::default(), 1, 1).await?;
+        let ret = state.list_invites(1, 2).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = state.revoke_invite(1, invite.id as _, 2).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_invite_with_invalid_input_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = CreateInvite {
+            expires_in_hours: Some(0),
+            ..Default::default()
+        };
+        let ret = state.create_invite(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::InviteError(_))));
+
+        let input = CreateInvite {
+            max_uses: Some(0),
+            ..Default::default()
+        };
+        let ret = state.create_invite(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::InviteError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_signup_with_invite_should_join_workspace() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = CreateInvite {
+            max_uses: Some(1),
+            ..Default::default()
+        };
+        let invite = state.create_invite(input, 1, 1).await?;
+
+        let user = state.create_user(&signup("eve@acme.org", &invite)).await?;
+        assert_eq!(user.ws_id, 1);
+        assert_eq!(user.ws_name, "acme");
+
+        let invites = state.list_invites(1, 1).await?;
+        assert_eq!(invites[0].use_count, 1);
+
+        // the invite is used up
+        let ret = state.create_user(&signup("frank@acme.org", &invite)).await;
+        assert!(matches!(ret, Err(AppError::InviteError(_))));
+        assert!(state.find_user_by_email("frank@acme.org").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_signup_with_email_bound_invite_should_check_email() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = CreateInvite {
+            email: Some("eve@acme.org".to_string()),
+            ..Default::default()
+        };
+        let invite = state.create_invite(input, 1, 1).await?;
+
+        let ret = state.create_user(&signup("frank@acme.org", &invite)).await;
+        assert!(matches!(ret, Err(AppError::InviteError(_))));
+
+        let user = state.create_user(&signup("Eve@acme.org", &invite)).await?;
+        assert_eq!(user.ws_id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_signup_with_revoked_or_expired_invite_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let invite = state.create_invite(CreateInvite::default(), 1, 1).await?;
+        let revoked = state.revoke_invite(1, invite.id as _, 1).await?;
+        assert!(revoked.revoked_at.is_some());
+        let ret = state.create_user(&signup("eve@acme.org", &invite)).await;
+        assert!(matches!(ret, Err(AppError::InviteError(_))));
+
+        let invite = state.create_invite(CreateInvite::default(), 1, 1).await?;
+        sqlx::query("UPDATE workspace_invites SET expires_at = NOW() WHERE id = $1")
+            .bind(invite.id)
+            .execute(&state.pool)
+            .await?;
+        let ret = state.create_user(&signup("eve@acme.org", &invite)).await;
+        assert!(matches!(ret, Err(AppError::InviteError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_revoke_nonexistent_invite_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let ret = state.revoke_invite(1, 100, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+use crate::{AppError, AppState};
+use chat_core::WorkspaceRole;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// A user in the workspace, as seen by workspace admins
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceMember {
+    pub id: i64,
+    pub fullname: String,
+    pub email: String,
+    pub is_bot: bool,
+    pub role: WorkspaceRole,
+    /// deactivated members couldn't access the workspace until activated again
+    pub deactivated_at: Option<DateTime<Utc>>,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateMember {
+    /// the new role, use transfer to change the owner
+    #[serde(default)]
+    pub role: Option<WorkspaceRole>,
+    /// activate or deactivate the member
+    #[serde(default)]
+    pub active: Option<bool>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// List all members of the workspace including deactivated ones, for workspace owner/admins
+    pub async fn list_workspace_members(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<WorkspaceMember>, AppError> {
+        self.check_workspace_admin(ws_id, user_id).await?;
+
+        let members = sqlx::query_as(
+            r#"
+            SELECT u.id, u.fullname, u.email, u.is_bot, m.role, m.deactivated_at, m.created_at AS joined_at
+            FROM workspace_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.ws_id = $1
+            ORDER BY u.id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(members)
+    }
+
+    pub async fn get_workspace_member(
+        &self,
+        ws_id: u64,
+        id: u64,
+    ) -> Result<Option<WorkspaceMember>, AppError> {
+        let member = sqlx::query_as(
+            r#"
+            SELECT u.id, u.fullname, u.email, u.is_bot, m.role, m.deactivated_at, m.created_at AS joined_at
+            FROM workspace_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.ws_id = $1 AND m.user_id = $2
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(member)
+    }
+
+    /// Change the role of the member or (de)activate the member
+    pub async fn update_workspace_member(
+        &self,
+        input: UpdateMember,
+        ws_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<WorkspaceMember, AppError> {
+        let role = self.check_workspace_admin(ws_id, user_id).await?;
+        let member = self.get_manageable_member(role, ws_id, id, user_id).await?;
+
+        match input.role {
+            Some(WorkspaceRole::Owner) => {
+                return Err(AppError::UpdateWorkspaceError(
+                    "Transfer the workspace to change the owner".to_string(),
+                ));
+            }
+            Some(WorkspaceRole::Admin) if role != WorkspaceRole::Owner => {
+                return Err(AppError::PermissionDenied(
+                    "Only the workspace owner could grant admins".to_string(),
+                ));
+            }
+            _ => {}
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE workspace_members
+            SET role = COALESCE($1, role),
+                deactivated_at = CASE
+                    WHEN $2 IS NULL THEN deactivated_at
+                    WHEN $2 THEN NULL
+                    ELSE COALESCE(deactivated_at, NOW())
+                END
+            WHERE ws_id = $3 AND user_id = $4
+            "#,
+        )
+        .bind(input.role)
+        .bind(input.active)
+        .bind(ws_id as i64)
+        .bind(member.id)
+        .execute(&self.pool)
+        .await?;
+
+        self.get_workspace_member(ws_id, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Member id {id}")))
+    }
+
+    /// Remove the member from the workspace and all its chats. The member could only join again
+    /// with an invite.
+    pub async fn remove_workspace_member(
+        &self,
+        ws_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let role = self.check_workspace_admin(ws_id, user_id).await?;
+        let member = self.get_manageable_member(role, ws_id, id, user_id).await?;
+
+        let mut tx = self.pool.begin().await?;
+        // the chat trigger notifies the chat members, including the removed one
+        sqlx::query(
+            r#"
+            UPDATE chats
+            SET members = array_remove(members, $1)
+            WHERE ws_id = $2 AND $1 = ANY(members)
+            "#,
+        )
+        .bind(member.id)
+        .bind(ws_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM chat_roles
+            WHERE user_id = $1 AND chat_id IN (SELECT id FROM chats WHERE ws_id = $2)
+            "#,
+        )
+        .bind(member.id)
+        .bind(ws_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("DELETE FROM workspace_members WHERE ws_id = $1 AND user_id = $2")
+            .bind(ws_id as i64)
+            .bind(member.id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO workspace_removals (ws_id, user_id)
+            VALUES ($1, $2)
+            ON CONFLICT (ws_id, user_id) DO UPDATE SET removed_at = NOW()
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(member.id)
+        .execute(&mut *tx)
+        .await?;
+
+        // if it is the active workspace of the user, fall back to another one
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET ws_id = COALESCE(
+                (SELECT ws_id FROM workspace_members WHERE user_id = $1 ORDER BY created_at LIMIT 1),
+                0
+            )
+            WHERE id = $1 AND ws_id = $2
+            "#,
+        )
+        .bind(member.id)
+        .bind(ws_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Get the role of the user if the user is a workspace owner/admin
+    pub(crate) async fn check_workspace_admin(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<WorkspaceRole, AppError> {
+        match self.get_workspace_role(ws_id, user_id).await? {
+            Some(role @ (WorkspaceRole::Owner | WorkspaceRole::Admin)) => Ok(role),
+            _ => Err(AppError::PermissionDenied(
+                "Only workspace owner/admins could manage the workspace".to_string(),
+            )),
+        }
+    }
+
+    /// Get the member if the user with the role could manage it
+    async fn get_manageable_member(
+        &self,
+        role: WorkspaceRole,
+        ws_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<WorkspaceMember, AppError> {
+        let member = self
+            .get_workspace_member(ws_id, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Member id {id}")))?;
+
+        if id == user_id {
+            return Err(AppError::UpdateWorkspaceError(
+                "Couldn't manage yourself".to_string(),
+            ));
+        }
+        if member.is_bot {
+            return Err(AppError::UpdateWorkspaceError(
+                "Bot users are managed with their agents".to_string(),
+            ));
+        }
+        if !can_manage(role, member.role) {
+            return Err(AppError::PermissionDenied(format!(
+                "{role:?} couldn't manage {:?}",
+                member.role
+            )));
+        }
+        Ok(member)
+    }
+}
+
+/// The owner manages everyone else, admins manage members and guests
+fn can_manage(role: WorkspaceRole, target: WorkspaceRole) -> bool {
+    match role {
+        WorkspaceRole::Owner => target != WorkspaceRole::Owner,
+        WorkspaceRole::Admin => matches!(target, WorkspaceRole::Member | WorkspaceRole::Guest),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_can_manage_should_follow_roles() {
+        use WorkspaceRole::*;
+        assert!(can_manage(Owner, Admin));
+        assert!(!can_manage(Owner, Owner));
+        assert!(can_manage(Admin, Guest));
+        assert!(!can_manage(Admin, Admin));
+        assert!(!can_manage(Member, Guest));
+    }
+
+    #[tokio::test]
+    async fn test_list_and_update_members_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let members = state.list_workspace_members(1, 1).await?;
+        assert_eq!(members.len(), 6);
+        assert_eq!(members[0].role, WorkspaceRole::Owner);
+
+        // plain members couldn't manage the workspace
+        let ret = state.list_workspace_members(1, 2).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let input = UpdateMember {
+            role: Some(WorkspaceRole::Admin),
+            ..Default::default()
+        };
+        let member = state.update_workspace_member(input, 1, 2, 1).await?;
+        assert_eq!(member.role, WorkspaceRole::Admin);
+
+        // admins couldn't grant admins or manage other admins
+        let input = UpdateMember {
+            role: Some(WorkspaceRole::Admin),
+            ..Default::default()
+        };
+        let ret = state.update_workspace_member(input, 1, 3, 2).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = state
+            .update_workspace_member(UpdateMember::default(), 1, 1, 2)
+            .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let input = UpdateMember {
+            active: Some(false),
+            ..Default::default()
+        };
+        let member = state.update_workspace_member(input, 1, 3, 2).await?;
+        assert!(member.deactivated_at.is_some());
+        assert_eq!(state.get_workspace_role(1, 3).await?, None);
+
+        let input = UpdateMember {
+            active: Some(true),
+            ..Default::default()
+        };
+        let member = state.update_workspace_member(input, 1, 3, 2).await?;
+        assert!(member.deactivated_at.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_member_should_strip_from_chats() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        state.remove_workspace_member(1, 3, 1).await?;
+        assert!(state.get_workspace_member(1, 3).await?.is_none());
+
+        let chat = state.get_chat_by_id(1).await?.unwrap();
+        assert_eq!(chat.members, [1, 2, 4, 5]);
+        let chat = state.get_chat_by_id(4).await?.unwrap();
+        assert_eq!(chat.members, [1, 4]);
+
+        let user = state.find_user_by_id(3).await?.unwrap();
+        assert_eq!(user.ws_id, 0);
+
+        // the owner couldn't be removed, bots are managed with agents
+        let ret = state.remove_workspace_member(1, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::UpdateWorkspaceError(_))));
+        let ret = state.remove_workspace_member(1, 6, 1).await;
+        assert!(matches!(ret, Err(AppError::UpdateWorkspaceError(_))));
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{AppError, AppState, ChatFile};
-use chat_core::Message;
+use chat_core::{Message, SenderType};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use utoipa::{IntoParams, ToSchema};
@@ -9,16 +10,58 @@ pub struct CreateMessage {
     pub content: String,
     #[serde(default)]
     pub files: Vec<String>,
+    /// reply in the thread of the message
+    #[serde(default)]
+    pub parent_id: Option<u64>,
 }
 
-#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateMessage {
+    pub content: String,
+}
+
+/// Paging of messages, at most one of the cursors could be set. Without a cursor the latest
+/// messages are returned.
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
 pub struct ListMessages {
+    /// messages older than this id
     #[serde(default)]
     pub last_id: Option<u64>,
+    /// messages newer than this id
+    #[serde(default)]
+    pub after_id: Option<u64>,
+    /// the message with this id and the messages around it, half of the limit on each side
+    #[serde(default)]
+    pub around_id: Option<u64>,
+    /// messages created at or after this time
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
     #[serde(default)]
     pub limit: u64,
 }
 
+/// A page of messages, newest first
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessagePage {
+    pub messages: Vec<Message>,
+    /// more messages in the direction of the cursor
+    pub has_more: bool,
+    /// pass as `last_id` to load older messages, none if there are no older messages
+    pub prev_cursor: Option<i64>,
+    /// pass as `after_id` to load newer messages, none if there are no newer messages
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Cursor {
+    Latest,
+    Before(i64),
+    After(i64),
+    Around(i64),
+    Since(DateTime<Utc>),
+}
+
 #[allow(dead_code)]
 impl AppState {
     pub async fn create_message(
@@ -26,6 +69,18 @@ impl AppState {
         input: CreateMessage,
         chat_id: u64,
         user_id: u64,
+    ) -> Result<Message, AppError> {
+        self.create_message_as(input, chat_id, user_id, SenderType::User)
+            .await
+    }
+
+    /// Same as `create_message`, for senders other than users, e.g. integrations
+    pub(crate) async fn create_message_as(
+        &self,
+        input: CreateMessage,
+        chat_id: u64,
+        sender_id: u64,
+        sender_type: SenderType,
     ) -> Result<Message, AppError> {
         let base_dir = &self.config.server.base_dir;
         // verify content - not empty
@@ -46,21 +101,206 @@ impl AppState {
             }
         }
 
-        // create message
+        // threads are one level deep, replies go to the thread of the top-level message
+        if let Some(parent_id) = input.parent_id {
+            let parent = self
+                .get_message_by_id(chat_id, parent_id)
+                .await?
+                .filter(|m| m.deleted_at.is_none())
+                .ok_or_else(|| {
+                    AppError::CreateMessageError(format!("Parent message {} not found", parent_id))
+                })?;
+            if parent.parent_id.is_some() {
+                return Err(AppError::CreateMessageError(
+                    "Cannot reply to a thread reply".to_string(),
+                ));
+            }
+        }
+
+        let (proxies, followups) = self.load_agents(chat_id).await?;
+
+        // let proxy agents rewrite the message before it is saved and delivered
+        let mut draft = Message {
+            files: input.files,
+            parent_id: input.parent_id.map(|id| id as _),
+            ..Message::draft(chat_id as _, sender_id as _, sender_type, input.content)
+        };
+        self.run_proxy_agents(&proxies, &mut draft).await?;
+
+        let message = self.insert_message(&draft).await?;
+
+        self.spawn_followup_agents(followups, message.clone());
+
+        Ok(message)
+    }
+
+    /// Save the draft message, thread replies also bump the reply count of their parent
+    pub(crate) async fn insert_message(&self, draft: &Message) -> Result<Message, AppError> {
+        let mut tx = self.pool.begin().await?;
         let message: Message = sqlx::query_as(
             r#"
-            INSERT INTO messages (chat_id, sender_id, content, files)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, chat_id, sender_id, content, modified_content, files, created_at
+            INSERT INTO messages (chat_id, sender_id, sender_type, content, modified_content, files, parent_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, chat_id, sender_id, sender_type, content, modified_content, files, parent_id, reply_count, last_reply_at, created_at, edited_at, deleted_at
+            "#,
+        )
+        .bind(draft.chat_id)
+        .bind(draft.sender_id)
+        .bind(draft.sender_type)
+        .bind(&draft.content)
+        .bind(draft.modified_content.as_deref())
+        .bind(&draft.files)
+        .bind(draft.parent_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(parent_id) = message.parent_id {
+            sqlx::query(
+                r#"
+                UPDATE messages
+                SET reply_count = reply_count + 1, last_reply_at = $1
+                WHERE id = $2
+                "#,
+            )
+            .bind(message.created_at)
+            .bind(parent_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
+    pub async fn get_message_by_id(
+        &self,
+        chat_id: u64,
+        id: u64,
+    ) -> Result<Option<Message>, AppError> {
+        let message = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, sender_type, content, modified_content, files, parent_id, reply_count, last_reply_at, created_at, edited_at, deleted_at
+            FROM messages
+            WHERE chat_id = $1 AND id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(message)
+    }
+
+    /// Edit the content of the message, only the sender could edit it. The new content goes
+    /// through the proxy agents of the chat again.
+    pub async fn update_message(
+        &self,
+        input: UpdateMessage,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        if input.content.is_empty() {
+            return Err(AppError::UpdateMessageError(
+                "Content cannot be empty".to_string(),
+            ));
+        }
+
+        let mut draft = self.get_sender_message(chat_id, id, user_id).await?;
+        draft.content = input.content;
+        draft.modified_content = None;
+
+        let (proxies, _) = self.load_agents(chat_id).await?;
+        self.run_proxy_agents(&proxies, &mut draft).await?;
+
+        let message = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET content = $1, modified_content = $2, edited_at = CURRENT_TIMESTAMP
+            WHERE chat_id = $3 AND id = $4 AND deleted_at IS NULL
+            RETURNING id, chat_id, sender_id, sender_type, content, modified_content, files, parent_id, reply_count, last_reply_at, created_at, edited_at, deleted_at
+            "#,
+        )
+        .bind(&draft.content)
+        .bind(draft.modified_content.as_deref())
+        .bind(chat_id as i64)
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        message.ok_or_else(|| AppError::NotFound(format!("Message id {id} in chat {chat_id}")))
+    }
+
+    /// Delete the message, only the sender could delete it. The row is kept as a tombstone
+    /// with the content, files and reactions cleared.
+    pub async fn delete_message(
+        &self,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        self.get_sender_message(chat_id, id, user_id).await?;
+
+        let mut tx = self.pool.begin().await?;
+        let deleted: Option<(Option<i64>,)> = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET content = '', modified_content = NULL, files = '{}', deleted_at = CURRENT_TIMESTAMP
+            WHERE chat_id = $1 AND id = $2 AND deleted_at IS NULL
+            RETURNING parent_id
             "#,
         )
         .bind(chat_id as i64)
-        .bind(user_id as i64)
-        .bind(input.content)
-        .bind(&input.files)
-        .fetch_one(&self.pool)
+        .bind(id as i64)
+        .fetch_optional(&mut *tx)
         .await?;
 
+        // the thread counts the remaining replies only
+        if let Some((Some(parent_id),)) = deleted {
+            sqlx::query(
+                r#"
+                UPDATE messages
+                SET reply_count = GREATEST(reply_count - 1, 0),
+                    last_reply_at = (
+                      SELECT MAX(created_at) FROM messages
+                      WHERE parent_id = $1 AND deleted_at IS NULL
+                    )
+                WHERE id = $1
+                "#,
+            )
+            .bind(parent_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM message_reactions WHERE message_id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Get a message that is not deleted and was sent by the user
+    async fn get_sender_message(
+        &self,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        let message = self
+            .get_message_by_id(chat_id, id)
+            .await?
+            .filter(|m| m.deleted_at.is_none())
+            .ok_or_else(|| AppError::NotFound(format!("Message id {id} in chat {chat_id}")))?;
+
+        if message.sender_id != user_id as i64 {
+            return Err(AppError::PermissionDenied(
+                "Only the sender could change the message".to_string(),
+            ));
+        }
         Ok(message)
     }
 
@@ -68,31 +308,183 @@ impl AppState {
         &self,
         input: ListMessages,
         chat_id: u64,
-    ) -> Result<Vec<Message>, AppError> {
-        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+    ) -> Result<MessagePage, AppError> {
+        self.list_message_page(input, chat_id, None).await
+    }
+
+    /// List the replies in the thread of the message, paged like `list_messages`
+    pub async fn list_thread_messages(
+        &self,
+        input: ListMessages,
+        chat_id: u64,
+        parent_id: u64,
+    ) -> Result<MessagePage, AppError> {
+        if self.get_message_by_id(chat_id, parent_id).await?.is_none() {
+            return Err(AppError::NotFound(format!(
+                "Message id {parent_id} in chat {chat_id}"
+            )));
+        }
+        self.list_message_page(input, chat_id, Some(parent_id as _))
+            .await
+    }
+
+    /// Page through the chat timeline, or the thread if `parent_id` is set
+    async fn list_message_page(
+        &self,
+        input: ListMessages,
+        chat_id: u64,
+        parent_id: Option<i64>,
+    ) -> Result<MessagePage, AppError> {
+        let cursor = input.cursor()?;
         let limit = match input.limit {
             0 => i64::MAX,
             1..=100 => input.limit as _,
             _ => 100,
         };
+        let chat_id = chat_id as i64;
+
+        let mut messages = match cursor {
+            Cursor::Latest => {
+                self.older_messages(chat_id, parent_id, i64::MAX, limit)
+                    .await?
+            }
+            Cursor::Before(id) => self.older_messages(chat_id, parent_id, id, limit).await?,
+            Cursor::After(id) => {
+                self.newer_messages(chat_id, parent_id, id, None, limit)
+                    .await?
+            }
+            Cursor::Since(since) => {
+                self.newer_messages(chat_id, parent_id, 0, Some(since), limit)
+                    .await?
+            }
+            Cursor::Around(id) => {
+                match self.get_message_by_id(chat_id as _, id as _).await? {
+                    Some(m) if m.parent_id == parent_id => {}
+                    _ => {
+                        return Err(AppError::NotFound(format!(
+                            "Message id {id} in chat {chat_id}"
+                        )))
+                    }
+                }
+                let after = limit / 2;
+                let mut messages = self
+                    .newer_messages(chat_id, parent_id, id, None, after)
+                    .await?;
+                // the message itself is in the older half
+                let older = self
+                    .older_messages(chat_id, parent_id, id + 1, limit - after)
+                    .await?;
+                messages.extend(older);
+                messages
+            }
+        };
+        self.load_reactions(&mut messages).await?;
 
-        let messages: Vec<Message> = sqlx::query_as(
+        let (prev_cursor, next_cursor) = match (messages.last(), messages.first()) {
+            (Some(oldest), Some(newest)) => {
+                let (oldest, newest) = (oldest.id, newest.id);
+                let has_older = !self
+                    .older_messages(chat_id, parent_id, oldest, 1)
+                    .await?
+                    .is_empty();
+                let has_newer = !self
+                    .newer_messages(chat_id, parent_id, newest, None, 1)
+                    .await?
+                    .is_empty();
+                (has_older.then_some(oldest), has_newer.then_some(newest))
+            }
+            _ => (None, None),
+        };
+        let has_more = match cursor {
+            Cursor::Latest | Cursor::Before(_) => prev_cursor.is_some(),
+            Cursor::After(_) | Cursor::Since(_) => next_cursor.is_some(),
+            Cursor::Around(_) => prev_cursor.is_some() || next_cursor.is_some(),
+        };
+
+        Ok(MessagePage {
+            messages,
+            has_more,
+            prev_cursor,
+            next_cursor,
+        })
+    }
+
+    /// Messages older than `before_id`, newest first
+    async fn older_messages(
+        &self,
+        chat_id: i64,
+        parent_id: Option<i64>,
+        before_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Message>, AppError> {
+        let messages = sqlx::query_as(
             r#"
-            SELECT id, chat_id, sender_id, content, modified_content, files, created_at
+            SELECT id, chat_id, sender_id, sender_type, content, modified_content, files, parent_id, reply_count, last_reply_at, created_at, edited_at, deleted_at
             FROM messages
-            WHERE chat_id = $1 AND id < $2
+            WHERE chat_id = $1 AND parent_id IS NOT DISTINCT FROM $2 AND id < $3
             ORDER BY id DESC
-            LIMIT $3
+            LIMIT $4
             "#,
         )
-        .bind(chat_id as i64)
-        .bind(last_id as i64)
+        .bind(chat_id)
+        .bind(parent_id)
+        .bind(before_id)
         .bind(limit)
         .fetch_all(&self.pool)
         .await?;
 
         Ok(messages)
     }
+
+    /// Messages newer than `after_id` and created since the given time, newest first
+    async fn newer_messages(
+        &self,
+        chat_id: i64,
+        parent_id: Option<i64>,
+        after_id: i64,
+        since: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<Message>, AppError> {
+        let mut messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, sender_type, content, modified_content, files, parent_id, reply_count, last_reply_at, created_at, edited_at, deleted_at
+            FROM messages
+            WHERE chat_id = $1 AND parent_id IS NOT DISTINCT FROM $2 AND id > $3
+              AND ($4::timestamptz IS NULL OR created_at >= $4)
+            ORDER BY id ASC
+            LIMIT $5
+            "#,
+        )
+        .bind(chat_id)
+        .bind(parent_id)
+        .bind(after_id)
+        .bind(since)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        messages.reverse();
+
+        Ok(messages)
+    }
+}
+
+impl ListMessages {
+    fn cursor(&self) -> Result<Cursor, AppError> {
+        let cursors = [
+            self.last_id.map(|id| Cursor::Before(id as _)),
+            self.after_id.map(|id| Cursor::After(id as _)),
+            self.around_id.map(|id| Cursor::Around(id as _)),
+            self.since.map(Cursor::Since),
+        ];
+        let mut cursors = cursors.into_iter().flatten();
+        match (cursors.next(), cursors.next()) {
+            (None, _) => Ok(Cursor::Latest),
+            (Some(cursor), None) => Ok(cursor),
+            _ => Err(AppError::ListMessagesError(
+                "Only one of last_id, after_id, around_id and since could be set".to_string(),
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -107,6 +499,7 @@ mod tests {
         let input = CreateMessage {
             content: "Hello World".to_string(),
             files: vec![],
+            parent_id: None,
         };
 
         let message = state
@@ -119,6 +512,7 @@ mod tests {
         let input = CreateMessage {
             content: "Hello World".to_string(),
             files: vec!["invalid_file".to_string()],
+            parent_id: None,
         };
         assert!(state.create_message(input, 1, 1).await.is_err());
 
@@ -127,6 +521,7 @@ mod tests {
         let input = CreateMessage {
             content: "Hello World".to_string(),
             files: vec![url],
+            parent_id: None,
         };
         let message = state
             .create_message(input, 1, 1)
@@ -145,20 +540,232 @@ mod tests {
         let input = ListMessages {
             last_id: None,
             limit: 6,
+            ..Default::default()
         };
 
-        let messages = state.list_messages(input, 1).await?;
+        let page = state.list_messages(input, 1).await?;
+        let messages = page.messages;
         assert_eq!(messages.len(), 6);
+        assert!(page.has_more);
+        assert_eq!(page.next_cursor, None);
 
         let last_id = messages.last().expect("last message should exists").id;
+        assert_eq!(page.prev_cursor, Some(last_id));
 
         let input = ListMessages {
             last_id: Some(last_id as _),
             limit: 6,
+            ..Default::default()
+        };
+
+        let page = state.list_messages(input, 1).await?;
+        assert_eq!(page.messages.len(), 4);
+        assert!(!page.has_more);
+        assert_eq!(page.prev_cursor, None);
+        assert_eq!(page.next_cursor, Some(4));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_messages_with_cursors_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        // newer messages, still newest first
+        let input = ListMessages {
+            after_id: Some(3),
+            limit: 4,
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        let ids: Vec<_> = page.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![7, 6, 5, 4]);
+        assert!(page.has_more);
+        assert_eq!(page.next_cursor, Some(7));
+        assert_eq!(page.prev_cursor, Some(4));
+
+        // the message and the messages around it
+        let input = ListMessages {
+            around_id: Some(5),
+            limit: 4,
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        let ids: Vec<_> = page.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![7, 6, 5, 4]);
+        assert!(page.has_more);
+
+        let input = ListMessages {
+            around_id: Some(100),
+            ..Default::default()
+        };
+        assert!(matches!(
+            state.list_messages(input, 1).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        // all fixture messages are created at the time of the test
+        let input = ListMessages {
+            since: Some(Utc::now() - chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        assert_eq!(page.messages.len(), 10);
+        assert!(!page.has_more);
+
+        let input = ListMessages {
+            last_id: Some(5),
+            after_id: Some(1),
+            ..Default::default()
+        };
+        assert!(matches!(
+            state.list_messages(input, 1).await,
+            Err(AppError::ListMessagesError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        // message 1 in chat 1 is sent by user 1, chat 1 has a proxy agent that is not
+        // configured in tests, so it makes no decision
+        let input = UpdateMessage {
+            content: "Hello, Rust!".to_string(),
+        };
+        let message = state.update_message(input, 1, 1, 1).await?;
+        assert_eq!(message.content, "Hello, Rust!");
+        assert!(message.edited_at.is_some());
+
+        // only the sender could edit
+        let input = UpdateMessage {
+            content: "hacked".to_string(),
+        };
+        assert!(matches!(
+            state.update_message(input, 1, 1, 2).await,
+            Err(AppError::PermissionDenied(_))
+        ));
+
+        let input = UpdateMessage {
+            content: "".to_string(),
         };
+        assert!(matches!(
+            state.update_message(input, 1, 1, 1).await,
+            Err(AppError::UpdateMessageError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_message_should_leave_tombstone() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        assert!(matches!(
+            state.delete_message(1, 1, 2).await,
+            Err(AppError::PermissionDenied(_))
+        ));
+
+        state.delete_message(1, 1, 1).await?;
+        let message = state
+            .get_message_by_id(1, 1)
+            .await?
+            .expect("message should exist");
+        assert!(message.deleted_at.is_some());
+        assert!(message.content.is_empty());
+
+        // deleted messages could not be changed again
+        assert!(matches!(
+            state.delete_message(1, 1, 1).await,
+            Err(AppError::NotFound(_))
+        ));
+        let input = UpdateMessage {
+            content: "back".to_string(),
+        };
+        assert!(matches!(
+            state.update_message(input, 1, 1, 1).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_thread_replies_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        for content in ["first", "second"] {
+            let input = CreateMessage {
+                content: content.to_string(),
+                files: vec![],
+                parent_id: Some(1),
+            };
+            let reply = state.create_message(input, 1, 2).await?;
+            assert_eq!(reply.parent_id, Some(1));
+        }
+
+        let parent = state
+            .get_message_by_id(1, 1)
+            .await?
+            .expect("message should exist");
+        assert_eq!(parent.reply_count, 2);
+        assert!(parent.last_reply_at.is_some());
+
+        let input = ListMessages {
+            limit: 10,
+            ..Default::default()
+        };
+        let replies = state
+            .list_thread_messages(input.clone(), 1, 1)
+            .await?
+            .messages;
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].content, "second");
+
+        // replies are not in the chat timeline
+        let messages = state.list_messages(input, 1).await?.messages;
+        assert_eq!(messages.len(), 10);
+        assert!(messages.iter().all(|m| m.parent_id.is_none()));
+
+        // threads are one level deep
+        let input = CreateMessage {
+            content: "nested".to_string(),
+            files: vec![],
+            parent_id: Some(replies[0].id as _),
+        };
+        assert!(matches!(
+            state.create_message(input, 1, 1).await,
+            Err(AppError::CreateMessageError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_thread_reply_should_update_parent() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let mut replies = vec![];
+        for content in ["first", "second"] {
+            let input = CreateMessage {
+                content: content.to_string(),
+                files: vec![],
+                parent_id: Some(1),
+            };
+            replies.push(state.create_message(input, 1, 2).await?);
+        }
+
+        state.delete_message(1, replies[1].id as _, 2).await?;
+        let parent = state.get_message_by_id(1, 1).await?.unwrap();
+        assert_eq!(parent.reply_count, 1);
+        assert_eq!(parent.last_reply_at, Some(replies[0].created_at));
 
-        let messages = state.list_messages(input, 1).await?;
-        assert_eq!(messages.len(), 4);
+        state.delete_message(1, replies[0].id as _, 2).await?;
+        let parent = state.get_message_by_id(1, 1).await?.unwrap();
+        assert_eq!(parent.reply_count, 0);
+        assert_eq!(parent.last_reply_at, None);
 
         Ok(())
     }
@@ -0,0 +1,402 @@
+use crate::{AppError, AppState};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::User;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, Postgres, Transaction};
+use std::time::{SystemTime, UNIX_EPOCH};
+use totp_rs::{Algorithm, Secret, TOTP};
+use utoipa::ToSchema;
+
+const MFA: &str = "mfa";
+/// Lifetime of an MFA challenge, in seconds
+const MFA_CHALLENGE_SECS: u64 = 60 * 5;
+const TOTP_ISSUER: &str = "Chat";
+/// Seconds of a TOTP time step, codes of the previous and the next step are accepted as well
+const TOTP_STEP: u64 = 30;
+const TOTP_DIGITS: usize = 6;
+const RECOVERY_CODES: usize = 10;
+/// Random bytes in a recovery code, hex encoded
+const RECOVERY_CODE_BYTES: usize = 5;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpSetup {
+    /// base32 encoded, for entering the secret manually
+    pub secret: String,
+    /// `otpauth://` uri, usually shown as a QR code
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodes {
+    /// shown only once, each could be used once instead of a TOTP code
+    pub codes: Vec<String>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+/// Returned by signin instead of the tokens when the user has enabled 2FA
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct MfaChallenge {
+    /// complete the signin with it at `/api/signin/mfa`
+    pub mfa_token: String,
+    /// lifetime of the challenge, in seconds
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct SigninMfa {
+    pub mfa_token: String,
+    /// a TOTP code, or a recovery code
+    pub code: String,
+}
+
+#[derive(Debug, FromRow)]
+struct TotpRow {
+    totp_secret: Option<String>,
+    totp_enabled_at: Option<DateTime<Utc>>,
+    totp_last_step: Option<i64>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Generate a new TOTP secret for the user, it is enabled once a code is verified
+    pub async fn setup_totp(&self, user: &User) -> Result<TotpSetup, AppError> {
+        if self.is_totp_enabled(user.id as _).await? {
+            return Err(AppError::MfaError("TOTP is already enabled".to_string()));
+        }
+
+        let secret = Secret::generate_secret()
+            .to_bytes()
+            .map_err(|e| AppError::MfaError(e.to_string()))?;
+        let totp = new_totp(secret, &user.email)?;
+        sqlx::query("UPDATE users SET totp_secret = $1, totp_last_step = NULL WHERE id = $2")
+            .bind(totp.get_secret_base32())
+            .bind(user.id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(TotpSetup {
+            secret: totp.get_secret_base32(),
+            uri: totp.get_url(),
+        })
+    }
+
+    /// Enable TOTP with a code of the secret set up, returns new recovery codes
+    pub async fn enable_totp(&self, user: &User, code: &str) -> Result<RecoveryCodes, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let row = fetch_totp(&mut tx, user.id).await?;
+        if row.totp_enabled_at.is_some() {
+            return Err(AppError::MfaError("TOTP is already enabled".to_string()));
+        }
+        let Some(secret) = &row.totp_secret else {
+            return Err(AppError::MfaError("TOTP is not set up".to_string()));
+        };
+
+        let step = check_totp(secret, &user.email, code, None)?
+            .ok_or_else(|| AppError::InvalidMfaCode("invalid TOTP code".to_string()))?;
+        sqlx::query("UPDATE users SET totp_enabled_at = NOW(), totp_last_step = $1 WHERE id = $2")
+            .bind(step as i64)
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        let codes = replace_recovery_codes(&mut tx, user.id).await?;
+        tx.commit().await?;
+
+        Ok(codes)
+    }
+
+    /// Disable TOTP with a TOTP code or a recovery code, the recovery codes are discarded
+    pub async fn disable_totp(&self, user: &User, code: &str) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        if !verify_second_factor(&mut tx, user, code).await? {
+            return Err(AppError::InvalidMfaCode("invalid code".to_string()));
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+            WHERE id = $1
+            "#,
+        )
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn is_totp_enabled(&self, user_id: u64) -> Result<bool, AppError> {
+        let enabled: Option<(bool,)> =
+            sqlx::query_as("SELECT totp_enabled_at IS NOT NULL FROM users WHERE id = $1")
+                .bind(user_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(enabled.is_some_and(|v| v.0))
+    }
+
+    /// Issue a challenge for the user who has passed the password check
+    pub async fn create_mfa_challenge(&self, user_id: u64) -> Result<MfaChallenge, AppError> {
+        let mfa_token = self
+            .issue_user_token(user_id as _, MFA, MFA_CHALLENGE_SECS)
+            .await?;
+        Ok(MfaChallenge {
+            mfa_token,
+            expires_in: MFA_CHALLENGE_SECS,
+        })
+    }
+
+    /// Complete the challenge with the second factor, returns the user with its active
+    /// workspace. Failed codes count towards the signin lockout of the user, whichever client
+    /// they come from.
+    pub async fn complete_mfa_challenge(&self, input: &SigninMfa) -> Result<User, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let user_id = self.use_user_token(&mut tx, &input.mfa_token, MFA).await?;
+        let mut user = self
+            .find_user_by_id(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("User id {user_id}")))?;
+
+        self.lockout.check(&user.email, None)?;
+        // the challenge is kept for another try if the code is wrong
+        if !verify_second_factor(&mut tx, &user, &input.code).await? {
+            self.lockout.fail(&user.email, None);
+            return Err(AppError::InvalidMfaCode("invalid code".to_string()));
+        }
+        tx.commit().await?;
+        self.lockout.succeed(&user.email, None);
+
+        if let Some(ws) = self.find_workspace_by_id(user.ws_id as _).await? {
+            user.ws_name = ws.name;
+        }
+        Ok(user)
+    }
+}
+
+async fn fetch_totp(tx: &mut Transaction<'_, Postgres>, user_id: i64) -> Result<TotpRow, AppError> {
+    let row = sqlx::query_as(
+        "SELECT totp_secret, totp_enabled_at, totp_last_step FROM users WHERE id = $1 FOR UPDATE",
+    )
+    .bind(user_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    row.ok_or_else(|| AppError::NotFound(format!("User id {user_id}")))
+}
+
+/// Check a TOTP code, or use a recovery code
+async fn verify_second_factor(
+    tx: &mut Transaction<'_, Postgres>,
+    user: &User,
+    code: &str,
+) -> Result<bool, AppError> {
+    let row = fetch_totp(tx, user.id).await?;
+    let (Some(secret), Some(_)) = (&row.totp_secret, row.totp_enabled_at) else {
+        return Err(AppError::MfaError("TOTP is not enabled".to_string()));
+    };
+
+    let code = code.trim();
+    if code.len() == TOTP_DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+        let last_step = row.totp_last_step.map(|s| s as u64);
+        let Some(step) = check_totp(secret, &user.email, code, last_step)? else {
+            return Ok(false);
+        };
+        sqlx::query("UPDATE users SET totp_last_step = $1 WHERE id = $2")
+            .bind(step as i64)
+            .bind(user.id)
+            .execute(&mut **tx)
+            .await?;
+        return Ok(true);
+    }
+
+    let ret = sqlx::query(
+        r#"
+        UPDATE user_recovery_codes
+        SET used_at = NOW()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user.id)
+    .bind(hash_recovery_code(code))
+    .execute(&mut **tx)
+    .await?;
+    Ok(ret.rows_affected() > 0)
+}
+
+async fn replace_recovery_codes(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i64,
+) -> Result<RecoveryCodes, AppError> {
+    sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+    sqlx::query(
+        r#"
+        INSERT INTO user_recovery_codes (user_id, code_hash)
+        SELECT $1, UNNEST($2::varchar[])
+        "#,
+    )
+    .bind(user_id)
+    .bind(&hashes)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(RecoveryCodes { codes })
+}
+
+fn new_totp(secret: Vec<u8>, email: &str) -> Result<TOTP, AppError> {
+    // steps around the current one are checked by `check_totp`, so no skew here
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        0,
+        TOTP_STEP,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        email.to_string(),
+    )
+    .map_err(|e| AppError::MfaError(e.to_string()))
+}
+
+/// Returns the time step of the code if it's valid. Steps up to the last used one are
+/// rejected, so that a code couldn't be replayed.
+fn check_totp(
+    secret: &str,
+    email: &str,
+    code: &str,
+    last_step: Option<u64>,
+) -> Result<Option<u64>, AppError> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| AppError::MfaError(e.to_string()))?;
+    let totp = new_totp(secret, email)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| AppError::MfaError(e.to_string()))?
+        .as_secs();
+
+    let current = now / TOTP_STEP;
+    let step = (current.saturating_sub(1)..=current + 1)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| totp.check(code, step * TOTP_STEP));
+    Ok(step)
+}
+
+/// Formatted as `xxxxx-xxxxx`
+fn generate_recovery_code() -> String {
+    let mut buf = [0u8; RECOVERY_CODE_BYTES];
+    OsRng.fill_bytes(&mut buf);
+    let code = hex::encode(buf);
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// Recovery codes are random enough to skip salting, the dash is optional
+fn hash_recovery_code(code: &str) -> String {
+    let code = code.trim().replace('-', "").to_lowercase();
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    /// Code of the secret at the time step after `now`
+    fn totp_code(setup: &TotpSetup, email: &str, now: u64, steps: u64) -> Result<String> {
+        let secret = Secret::Encoded(setup.secret.clone()).to_bytes()?;
+        let totp = new_totp(secret, email)?;
+        Ok(totp.generate(now + steps * TOTP_STEP))
+    }
+
+    #[tokio::test]
+    async fn test_totp_should_enable_and_complete_challenge() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        let setup = state.setup_totp(&user).await?;
+        assert!(setup.uri.starts_with("otpauth://totp/"));
+        assert!(!state.is_totp_enabled(1).await?);
+
+        let ret = state.enable_totp(&user, "000000").await;
+        let code = totp_code(&setup, &user.email, now, 0)?;
+        if code != "000000" {
+            assert!(matches!(ret, Err(AppError::InvalidMfaCode(_))));
+        }
+        let codes = state.enable_totp(&user, &code).await?.codes;
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(state.is_totp_enabled(1).await?);
+
+        // the code used to enable TOTP couldn't be replayed
+        let challenge = state.create_mfa_challenge(1).await?;
+        let input = SigninMfa {
+            mfa_token: challenge.mfa_token.clone(),
+            code,
+        };
+        let ret = state.complete_mfa_challenge(&input).await;
+        assert!(matches!(ret, Err(AppError::InvalidMfaCode(_))));
+
+        let input = SigninMfa {
+            mfa_token: challenge.mfa_token,
+            code: totp_code(&setup, &user.email, now, 1)?,
+        };
+        let user = state.complete_mfa_challenge(&input).await?;
+        assert_eq!(user.ws_name, "acme");
+
+        // the challenge is used up
+        let ret = state.complete_mfa_challenge(&input).await;
+        assert!(matches!(ret, Err(AppError::UserTokenError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recovery_code_should_be_used_once() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        let setup = state.setup_totp(&user).await?;
+        let code = totp_code(&setup, &user.email, now, 0)?;
+        let codes = state.enable_totp(&user, &code).await?.codes;
+
+        let challenge = state.create_mfa_challenge(1).await?;
+        let input = SigninMfa {
+            mfa_token: challenge.mfa_token,
+            code: codes[0].to_uppercase(),
+        };
+        state.complete_mfa_challenge(&input).await?;
+
+        let challenge = state.create_mfa_challenge(1).await?;
+        let input = SigninMfa {
+            mfa_token: challenge.mfa_token,
+            code: codes[0].clone(),
+        };
+        let ret = state.complete_mfa_challenge(&input).await;
+        assert!(matches!(ret, Err(AppError::InvalidMfaCode(_))));
+
+        state.disable_totp(&user, &codes[1]).await?;
+        assert!(!state.is_totp_enabled(1).await?);
+
+        Ok(())
+    }
+}
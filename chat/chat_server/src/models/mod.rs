@@ -1,13 +1,48 @@
+mod account;
+mod agent;
+mod api_token;
 mod chat;
 mod file;
+mod identity;
+mod incoming_webhook;
+mod invite;
+mod member;
 mod messages;
+mod mfa;
+mod permission;
+mod reaction;
+mod read_state;
+mod search;
+mod session;
+mod subscription;
 mod user;
 mod workspace;
 
+pub use account::{ForgotPassword, ResetPassword, VerifyEmail};
+pub use agent::{CreateAgent, InvalidAgentArgs, UpdateAgent};
+pub use api_token::{ApiToken, CreateApiToken, CreatedApiToken};
 pub use chat::{CreateChat, UpdateChat};
-pub use messages::{CreateMessage, ListMessages};
+pub use identity::{OidcAuthorize, OidcAuthorizeOutput, OidcCallback, OidcProvider};
+pub use incoming_webhook::{
+    CreateIncomingWebhook, CreatedIncomingWebhook, IncomingWebhook, WebhookAttachment,
+    WebhookMessage,
+};
+pub use invite::{CreateInvite, WorkspaceInvite};
+pub use member::{UpdateMember, WorkspaceMember};
+pub use messages::{CreateMessage, ListMessages, MessagePage, UpdateMessage};
+pub use mfa::{MfaChallenge, RecoveryCodes, SigninMfa, TotpCode, TotpSetup};
+pub use permission::{Permission, UpdateChatRole};
+pub use reaction::ReactionInput;
+pub use read_state::{ChatSummary, MarkRead};
+pub use search::{SearchHit, SearchMessages, SearchResult};
 use serde::{Deserialize, Serialize};
+pub use session::RefreshSession;
+pub use subscription::{
+    CreateSubscription, CreatedSubscription, DeliveryAttempt, DeliveryStatus, EventDelivery,
+    EventDeliveryDetail, EventSubscription, ListDeliveries, UpdateSubscription,
+};
 pub use user::{CreateUser, SigninUser};
+pub use workspace::{JoinWorkspace, TransferWorkspace, UpdateWorkspace, UserWorkspace};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatFile {
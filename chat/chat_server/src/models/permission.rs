@@ -0,0 +1,244 @@
+use crate::{AppError, AppState};
+use chat_core::{ChatRole, WorkspaceRole};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// What a user could do to a chat beyond sending messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    UpdateChat,
+    DeleteChat,
+    ManageAgents,
+    ManageWebhooks,
+    RemoveMembers,
+    /// grant or revoke chat admins
+    ManageRoles,
+}
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize)]
+pub struct UpdateChatRole {
+    /// the new role of the member, plain member if not set
+    #[serde(default)]
+    pub role: Option<ChatRole>,
+}
+
+#[derive(Debug, FromRow)]
+struct Roles {
+    ws_role: WorkspaceRole,
+    chat_role: Option<ChatRole>,
+}
+
+impl Permission {
+    /// Workspace owners and admins could do anything to the chats in the workspace, guests
+    /// could do nothing. Otherwise it depends on the role of the user in the chat.
+    pub fn allowed(self, ws_role: WorkspaceRole, chat_role: Option<ChatRole>) -> bool {
+        match (ws_role, chat_role) {
+            (WorkspaceRole::Owner | WorkspaceRole::Admin, _) => true,
+            (WorkspaceRole::Guest, _) => false,
+            (_, Some(ChatRole::Creator)) => true,
+            (_, Some(ChatRole::Admin)) => !matches!(self, Self::DeleteChat | Self::ManageRoles),
+            (_, None) => false,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Check if the user has the permission on the chat
+    pub async fn check_chat_permission(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        permission: Permission,
+    ) -> Result<(), AppError> {
+        let roles: Option<Roles> = sqlx::query_as(
+            r#"
+            SELECT m.role AS ws_role, r.role AS chat_role
+            FROM chats c
+            JOIN workspace_members m
+              ON m.user_id = $2 AND m.ws_id = c.ws_id AND m.deactivated_at IS NULL
+            LEFT JOIN chat_roles r ON r.chat_id = c.id AND r.user_id = m.user_id
+            WHERE c.id = $1
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match roles {
+            Some(roles) if permission.allowed(roles.ws_role, roles.chat_role) => Ok(()),
+            _ => Err(AppError::PermissionDenied(format!(
+                "User {user_id} is not allowed to {permission:?} in chat {chat_id}"
+            ))),
+        }
+    }
+
+    /// Get the role of the member in the chat, `None` for plain members
+    pub async fn get_chat_role(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Option<ChatRole>, AppError> {
+        let role: Option<(ChatRole,)> =
+            sqlx::query_as("SELECT role FROM chat_roles WHERE chat_id = $1 AND user_id = $2")
+                .bind(chat_id as i64)
+                .bind(user_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(role.map(|r| r.0))
+    }
+
+    /// Make the member a chat admin, or a plain member if role is not set.
+    /// The creator of the chat couldn't be changed.
+    pub async fn update_chat_role(
+        &self,
+        input: UpdateChatRole,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Option<ChatRole>, AppError> {
+        if input.role == Some(ChatRole::Creator) {
+            return Err(AppError::UpdateChatError(
+                "Chat creator couldn't be assigned".to_string(),
+            ));
+        }
+        if !self.is_chat_member(chat_id, user_id).await? {
+            return Err(AppError::NotFound(format!(
+                "Member {user_id} in chat {chat_id}"
+            )));
+        }
+        if self.get_chat_role(chat_id, user_id).await? == Some(ChatRole::Creator) {
+            return Err(AppError::UpdateChatError(
+                "Chat creator couldn't be changed".to_string(),
+            ));
+        }
+
+        match input.role {
+            Some(role) => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO chat_roles (chat_id, user_id, role)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (chat_id, user_id) DO UPDATE SET role = EXCLUDED.role
+                    "#,
+                )
+                .bind(chat_id as i64)
+                .bind(user_id as i64)
+                .bind(role)
+                .execute(&self.pool)
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM chat_roles WHERE chat_id = $1 AND user_id = $2")
+                    .bind(chat_id as i64)
+                    .bind(user_id as i64)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+
+        Ok(input.role)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateChat;
+    use anyhow::Result;
+
+    #[test]
+    fn test_permission_allowed_should_follow_roles() {
+        use Permission::*;
+        let all = [
+            UpdateChat,
+            DeleteChat,
+            ManageAgents,
+            ManageWebhooks,
+            RemoveMembers,
+            ManageRoles,
+        ];
+
+        for p in all {
+            assert!(p.allowed(WorkspaceRole::Owner, None));
+            assert!(p.allowed(WorkspaceRole::Admin, None));
+            assert!(p.allowed(WorkspaceRole::Member, Some(ChatRole::Creator)));
+            assert!(!p.allowed(WorkspaceRole::Member, None));
+            assert!(!p.allowed(WorkspaceRole::Guest, Some(ChatRole::Creator)));
+        }
+        assert!(UpdateChat.allowed(WorkspaceRole::Member, Some(ChatRole::Admin)));
+        assert!(RemoveMembers.allowed(WorkspaceRole::Member, Some(ChatRole::Admin)));
+        assert!(!DeleteChat.allowed(WorkspaceRole::Member, Some(ChatRole::Admin)));
+        assert!(!ManageRoles.allowed(WorkspaceRole::Member, Some(ChatRole::Admin)));
+    }
+
+    #[tokio::test]
+    async fn test_check_chat_permission_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        // workspace owner could manage any chat
+        state
+            .check_chat_permission(1, 1, Permission::DeleteChat)
+            .await?;
+        // plain member couldn't
+        let ret = state
+            .check_chat_permission(1, 2, Permission::UpdateChat)
+            .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // the creator of the chat could
+        let input = CreateChat::new("", &[2, 3], false);
+        let chat = state.create_chat(input, 2, 1).await?;
+        assert_eq!(
+            state.get_chat_role(chat.id as _, 2).await?,
+            Some(ChatRole::Creator)
+        );
+        state
+            .check_chat_permission(chat.id as _, 2, Permission::DeleteChat)
+            .await?;
+        let ret = state
+            .check_chat_permission(chat.id as _, 3, Permission::UpdateChat)
+            .await;
+        assert!(ret.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_chat_role_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = UpdateChatRole {
+            role: Some(ChatRole::Admin),
+        };
+        state.update_chat_role(input, 1, 2).await?;
+        state
+            .check_chat_permission(1, 2, Permission::ManageAgents)
+            .await?;
+        let ret = state
+            .check_chat_permission(1, 2, Permission::DeleteChat)
+            .await;
+        assert!(ret.is_err());
+
+        state
+            .update_chat_role(UpdateChatRole { role: None }, 1, 2)
+            .await?;
+        assert_eq!(state.get_chat_role(1, 2).await?, None);
+
+        // user 5 is not a member of chat 2
+        let ret = state
+            .update_chat_role(UpdateChatRole { role: None }, 2, 5)
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let input = UpdateChatRole {
+            role: Some(ChatRole::Creator),
+        };
+        let ret = state.update_chat_role(input, 1, 2).await;
+        assert!(matches!(ret, Err(AppError::UpdateChatError(_))));
+
+        Ok(())
+    }
+}
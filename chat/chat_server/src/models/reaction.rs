@@ -0,0 +1,224 @@
+use crate::{AppError, AppState};
+use chat_core::{Message, MessageReaction, ReactionCount};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+
+/// Max length of the emoji, in chars
+const MAX_EMOJI_LEN: usize = 16;
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ReactionInput {
+    pub emoji: String,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Add the reaction of the user to the message, adding the same reaction twice is a no-op.
+    /// Returns the reactions of the message.
+    pub async fn add_reaction(
+        &self,
+        input: ReactionInput,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ReactionCount>, AppError> {
+        validate_emoji(&input.emoji)?;
+        self.get_reactable_message(chat_id, message_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(&input.emoji)
+        .execute(&self.pool)
+        .await?;
+
+        self.list_reactions(message_id).await
+    }
+
+    /// Remove the reaction of the user from the message. Returns the reactions of the message.
+    pub async fn remove_reaction(
+        &self,
+        input: ReactionInput,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ReactionCount>, AppError> {
+        self.get_reactable_message(chat_id, message_id).await?;
+
+        let ret = sqlx::query(
+            r#"
+            DELETE FROM message_reactions
+            WHERE message_id = $1 AND user_id = $2 AND emoji = $3
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(&input.emoji)
+        .execute(&self.pool)
+        .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "Reaction {} on message {message_id}",
+                input.emoji
+            )));
+        }
+
+        self.list_reactions(message_id).await
+    }
+
+    pub async fn list_reactions(&self, message_id: u64) -> Result<Vec<ReactionCount>, AppError> {
+        let id = message_id as i64;
+        let mut reactions = self.fetch_reaction_counts(&[id]).await?;
+        Ok(reactions.remove(&id).unwrap_or_default())
+    }
+
+    /// Fill in the aggregated reactions of the messages
+    pub(crate) async fn load_reactions(&self, messages: &mut [Message]) -> Result<(), AppError> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        let mut reactions = self.fetch_reaction_counts(&ids).await?;
+        for message in messages.iter_mut() {
+            message.reactions = reactions.remove(&message.id).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    /// Reactions of the messages grouped by message id, emojis are ordered by their first reaction
+    async fn fetch_reaction_counts(
+        &self,
+        ids: &[i64],
+    ) -> Result<HashMap<i64, Vec<ReactionCount>>, AppError> {
+        let reactions: Vec<MessageReaction> = sqlx::query_as(
+            r#"
+            SELECT message_id, user_id, emoji, created_at
+            FROM message_reactions
+            WHERE message_id = ANY($1)
+            ORDER BY created_at ASC, user_id ASC
+            "#,
+        )
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut grouped: HashMap<i64, Vec<ReactionCount>> = HashMap::new();
+        for reaction in reactions {
+            let counts = grouped.entry(reaction.message_id).or_default();
+            match counts.iter_mut().find(|c| c.emoji == reaction.emoji) {
+                Some(count) => {
+                    count.count += 1;
+                    count.user_ids.push(reaction.user_id);
+                }
+                None => counts.push(ReactionCount {
+                    emoji: reaction.emoji,
+                    count: 1,
+                    user_ids: vec![reaction.user_id],
+                }),
+            }
+        }
+        Ok(grouped)
+    }
+
+    async fn get_reactable_message(&self, chat_id: u64, message_id: u64) -> Result<(), AppError> {
+        match self.get_message_by_id(chat_id, message_id).await? {
+            Some(message) if message.deleted_at.is_none() => Ok(()),
+            _ => Err(AppError::NotFound(format!(
+                "Message id {message_id} in chat {chat_id}"
+            ))),
+        }
+    }
+}
+
+fn validate_emoji(emoji: &str) -> Result<(), AppError> {
+    let len = emoji.chars().count();
+    if len == 0 || len > MAX_EMOJI_LEN || emoji.chars().any(char::is_whitespace) {
+        return Err(AppError::ReactionError(format!(
+            "Invalid emoji: {:?}",
+            emoji
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ListMessages;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_add_and_remove_reactions_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let thumbs = ReactionInput {
+            emoji: "👍".to_string(),
+        };
+        state.add_reaction(thumbs.clone(), 1, 1, 1).await?;
+        // adding twice is a no-op
+        state.add_reaction(thumbs.clone(), 1, 1, 1).await?;
+        state.add_reaction(thumbs.clone(), 1, 1, 2).await?;
+        let input = ReactionInput {
+            emoji: "🎉".to_string(),
+        };
+        let reactions = state.add_reaction(input, 1, 1, 2).await?;
+        assert_eq!(reactions.len(), 2);
+        assert_eq!(reactions[0].emoji, "👍");
+        assert_eq!(reactions[0].count, 2);
+        assert_eq!(reactions[0].user_ids, vec![1, 2]);
+
+        // reactions are included when listing messages
+        let input = ListMessages {
+            last_id: Some(2),
+            limit: 1,
+            ..Default::default()
+        };
+        let messages = state.list_messages(input, 1).await?.messages;
+        assert_eq!(messages[0].reactions, reactions);
+
+        let reactions = state.remove_reaction(thumbs.clone(), 1, 1, 1).await?;
+        assert_eq!(reactions[0].count, 1);
+        assert_eq!(reactions[0].user_ids, vec![2]);
+        assert!(matches!(
+            state.remove_reaction(thumbs, 1, 1, 1).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_reactions_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        for emoji in ["", "thumbs up", "a-very-long-emoji-name"] {
+            let input = ReactionInput {
+                emoji: emoji.to_string(),
+            };
+            assert!(matches!(
+                state.add_reaction(input, 1, 1, 1).await,
+                Err(AppError::ReactionError(_))
+            ));
+        }
+
+        // message 1 is not in chat 2
+        let input = ReactionInput {
+            emoji: "👍".to_string(),
+        };
+        assert!(matches!(
+            state.add_reaction(input, 2, 1, 1).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{AppError, AppState};
+use chat_core::{Chat, ChatReadState, Message};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct MarkRead {
+    /// read up to this message, the latest message of the chat if not set
+    #[serde(default)]
+    pub message_id: Option<u64>,
+}
+
+/// A chat in the chat list with what the user hasn't seen yet
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatSummary {
+    #[serde(flatten)]
+    pub chat: Chat,
+    /// top-level messages after the last read one, not counting the user's own messages
+    pub unread_count: i64,
+    pub last_read_message_id: Option<i64>,
+    pub last_message: Option<Message>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Mark the chat read up to the message for the user, the read state never goes backwards.
+    /// The state is left untouched if it is not moving forward, so that no read receipt is sent.
+    pub async fn mark_chat_read(
+        &self,
+        input: MarkRead,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ChatReadState, AppError> {
+        let message_id = match input.message_id {
+            Some(id) => {
+                self.get_message_by_id(chat_id, id).await?.ok_or_else(|| {
+                    AppError::NotFound(format!("Message id {id} in chat {chat_id}"))
+                })?;
+                id as i64
+            }
+            None => {
+                let (id,): (Option<i64>,) =
+                    sqlx::query_as("SELECT MAX(id) FROM messages WHERE chat_id = $1")
+                        .bind(chat_id as i64)
+                        .fetch_one(&self.pool)
+                        .await?;
+                id.ok_or_else(|| AppError::NotFound(format!("Messages in chat {chat_id}")))?
+            }
+        };
+
+        let state = sqlx::query_as(
+            r#"
+            INSERT INTO chat_read_state (chat_id, user_id, last_read_message_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (chat_id, user_id) DO UPDATE
+            SET last_read_message_id = EXCLUDED.last_read_message_id,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE chat_read_state.last_read_message_id < EXCLUDED.last_read_message_id
+            RETURNING chat_id, user_id, last_read_message_id, updated_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(message_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(state) = state {
+            return Ok(state);
+        }
+
+        let state = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, last_read_message_id, updated_at
+            FROM chat_read_state
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(state)
+    }
+
+    /// Read states of all members who have read the chat, for "seen by"
+    pub async fn list_read_states(&self, chat_id: u64) -> Result<Vec<ChatReadState>, AppError> {
+        let states = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, last_read_message_id, updated_at
+            FROM chat_read_state
+            WHERE chat_id = $1
+            ORDER BY user_id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(states)
+    }
+
+    /// Chats of the user with unread counts and the last message
+    pub async fn fetch_chat_summaries(
+        &self,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<ChatSummary>, AppError> {
+        let chats = self.fetch_chats(user_id, ws_id).await?;
+        let ids: Vec<i64> = chats.iter().map(|c| c.id).collect();
+
+        let unread: Vec<(i64, Option<i64>, i64)> = sqlx::query_as(
+            r#"
+            SELECT c.id, r.last_read_message_id, COUNT(m.id)
+            FROM chats c
+            LEFT JOIN chat_read_state r ON r.chat_id = c.id AND r.user_id = $2
+            LEFT JOIN messages m ON m.chat_id = c.id
+              AND m.id > COALESCE(r.last_read_message_id, 0)
+              AND m.sender_id <> $2
+              AND m.parent_id IS NULL
+              AND m.deleted_at IS NULL
+            WHERE c.id = ANY($1)
+            GROUP BY c.id, r.last_read_message_id
+            "#,
+        )
+        .bind(&ids)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut unread: HashMap<i64, (Option<i64>, i64)> = unread
+            .into_iter()
+            .map(|(id, last_read, count)| (id, (last_read, count)))
+            .collect();
+
+        let last_messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT ON (chat_id) id, chat_id, sender_id, sender_type, content, modified_content, files, parent_id, reply_count, last_reply_at, created_at, edited_at, deleted_at
+            FROM messages
+            WHERE chat_id = ANY($1) AND parent_id IS NULL AND deleted_at IS NULL
+            ORDER BY chat_id, id DESC
+            "#,
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut last_messages: HashMap<i64, Message> =
+            last_messages.into_iter().map(|m| (m.chat_id, m)).collect();
+
+        let summaries = chats
+            .into_iter()
+            .map(|chat| {
+                let (last_read_message_id, unread_count) =
+                    unread.remove(&chat.id).unwrap_or_default();
+                ChatSummary {
+                    unread_count,
+                    last_read_message_id,
+                    last_message: last_messages.remove(&chat.id),
+                    chat,
+                }
+            })
+            .collect();
+
+        Ok(summaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_chat_summaries_should_count_unread() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        // chat 1 has 10 messages, 4 of them sent by user 1
+        let chats = state.fetch_chat_summaries(1, 1).await?;
+        assert_eq!(chats.len(), 4);
+        let chat = chats.iter().find(|c| c.chat.id == 1).expect("chat 1");
+        assert_eq!(chat.unread_count, 6);
+        assert_eq!(chat.last_read_message_id, None);
+        assert_eq!(chat.last_message.as_ref().map(|m| m.id), Some(10));
+        let chat = chats.iter().find(|c| c.chat.id == 2).expect("chat 2");
+        assert_eq!(chat.unread_count, 0);
+        assert!(chat.last_message.is_none());
+
+        let input = MarkRead {
+            message_id: Some(5),
+        };
+        let read = state.mark_chat_read(input, 1, 1).await?;
+        assert_eq!(read.last_read_message_id, 5);
+
+        let chats = state.fetch_chat_summaries(1, 1).await?;
+        let chat = chats.iter().find(|c| c.chat.id == 1).expect("chat 1");
+        // messages 7 and 8 are sent by others
+        assert_eq!(chat.unread_count, 2);
+        assert_eq!(chat.last_read_message_id, Some(5));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mark_chat_read_should_not_go_backwards() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let read = state.mark_chat_read(MarkRead::default(), 1, 2).await?;
+        assert_eq!(read.last_read_message_id, 10);
+
+        let input = MarkRead {
+            message_id: Some(3),
+        };
+        let unchanged = state.mark_chat_read(input, 1, 2).await?;
+        assert_eq!(unchanged, read);
+        // marking the same message again doesn't touch the state either
+        let unchanged = state.mark_chat_read(MarkRead::default(), 1, 2).await?;
+        assert_eq!(unchanged, read);
+
+        let states = state.list_read_states(1).await?;
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].user_id, 2);
+
+        // message 1 is not in chat 2, and chat 2 has no messages
+        let input = MarkRead {
+            message_id: Some(1),
+        };
+        assert!(state.mark_chat_read(input, 2, 1).await.is_err());
+        assert!(state
+            .mark_chat_read(MarkRead::default(), 2, 1)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{AppError, AppState};
+use chat_core::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_SEARCH_LIMIT: u64 = 20;
+const MAX_SEARCH_LIMIT: u64 = 100;
+/// Markers of the matched terms in the headline, turned into `<mark>` tags once escaped
+const MARK_START: char = '\u{2}';
+const MARK_STOP: char = '\u{3}';
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct SearchMessages {
+    /// search terms, supports quoted phrases, `or` and `-` to exclude
+    pub q: String,
+    #[serde(default)]
+    pub chat_id: Option<u64>,
+    #[serde(default)]
+    pub sender_id: Option<u64>,
+    /// only messages created at or after this time
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    /// only messages created before this time
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub has_files: Option<bool>,
+    /// cursor returned by the previous page
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: Message,
+    /// safe html, the escaped content with the matched terms wrapped in `<mark>`
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    pub hits: Vec<SearchHit>,
+    /// pass as `last_id` to get the next page, none if there are no more results
+    pub next_cursor: Option<i64>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Search messages in the chats the user is a member of, newest first
+    pub async fn search_messages(
+        &self,
+        input: SearchMessages,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<SearchResult, AppError> {
+        if input.q.trim().is_empty() {
+            return Err(AppError::SearchError(
+                "Search query cannot be empty".to_string(),
+            ));
+        }
+        let limit = match input.limit {
+            0 => DEFAULT_SEARCH_LIMIT,
+            1..=MAX_SEARCH_LIMIT => input.limit,
+            _ => MAX_SEARCH_LIMIT,
+        };
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+
+        // fetch one more row to know if there is a next page
+        let mut hits: Vec<SearchHit> = sqlx::query_as(
+            r#"
+            SELECT m.id, m.chat_id, m.sender_id, m.sender_type, m.content, m.modified_content, m.files,
+              m.parent_id, m.reply_count, m.last_reply_at, m.created_at, m.edited_at, m.deleted_at,
+              ts_headline('simple', translate(m.content, $11, ''), q.query, $12) AS snippet
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id
+            CROSS JOIN websearch_to_tsquery('simple', $1) AS q(query)
+            WHERE m.search_vector @@ q.query
+              AND c.ws_id = $2 AND $3 = ANY(c.members)
+              AND m.deleted_at IS NULL
+              AND m.id < $4
+              AND ($5::bigint IS NULL OR m.chat_id = $5)
+              AND ($6::bigint IS NULL OR m.sender_id = $6)
+              AND ($7::timestamptz IS NULL OR m.created_at >= $7)
+              AND ($8::timestamptz IS NULL OR m.created_at < $8)
+              AND ($9::boolean IS NULL OR (cardinality(m.files) > 0) = $9)
+            ORDER BY m.id DESC
+            LIMIT $10
+            "#,
+        )
+        .bind(input.q.trim())
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(last_id as i64)
+        .bind(input.chat_id.map(|id| id as i64))
+        .bind(input.sender_id.map(|id| id as i64))
+        .bind(input.since)
+        .bind(input.until)
+        .bind(input.has_files)
+        .bind(limit as i64 + 1)
+        .bind(format!("{MARK_START}{MARK_STOP}"))
+        .bind(format!(r#"StartSel="{MARK_START}", StopSel="{MARK_STOP}""#))
+        .fetch_all(&self.pool)
+        .await?;
+        for hit in &mut hits {
+            hit.snippet = highlight(&hit.snippet);
+        }
+
+        let next_cursor = if hits.len() > limit as usize {
+            hits.truncate(limit as usize);
+            hits.last().map(|hit| hit.message.id)
+        } else {
+            None
+        };
+
+        Ok(SearchResult { hits, next_cursor })
+    }
+}
+
+/// Escape the headline as html, then turn the markers into `<mark>` tags. The markers are
+/// removed from the content before the headline is made, so that users couldn't forge them.
+fn highlight(headline: &str) -> String {
+    let mut ret = String::with_capacity(headline.len() + 16);
+    for c in headline.chars() {
+        match c {
+            MARK_START => ret.push_str("<mark>"),
+            MARK_STOP => ret.push_str("</mark>"),
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&#39;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_search_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        // chat 1 has 4 "Hello, world!" messages
+        let input = SearchMessages {
+            q: "hello".to_string(),
+            limit: 3,
+            ..Default::default()
+        };
+        let ret = state.search_messages(input, 1, 1).await?;
+        assert_eq!(ret.hits.len(), 3);
+        assert_eq!(ret.hits[0].snippet, "<mark>Hello</mark>, world!");
+        assert!(ret
+            .hits
+            .windows(2)
+            .all(|w| w[0].message.id > w[1].message.id));
+        let cursor = ret.next_cursor.expect("should have next page");
+
+        let input = SearchMessages {
+            q: "hello".to_string(),
+            last_id: Some(cursor as _),
+            limit: 3,
+            ..Default::default()
+        };
+        let ret = state.search_messages(input, 1, 1).await?;
+        assert_eq!(ret.hits.len(), 1);
+        assert_eq!(ret.next_cursor, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_messages_should_filter() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = SearchMessages {
+            q: "how are you".to_string(),
+            sender_id: Some(3),
+            has_files: Some(false),
+            ..Default::default()
+        };
+        let ret = state.search_messages(input, 1, 1).await?;
+        assert_eq!(ret.hits.len(), 2);
+
+        let input = SearchMessages {
+            q: "hello".to_string(),
+            chat_id: Some(2),
+            ..Default::default()
+        };
+        assert!(state.search_messages(input, 1, 1).await?.hits.is_empty());
+
+        let input = SearchMessages {
+            q: "hello".to_string(),
+            until: Some(DateTime::UNIX_EPOCH),
+            ..Default::default()
+        };
+        assert!(state.search_messages(input, 1, 1).await?.hits.is_empty());
+
+        // user 5 is not a member of chat 2..4, but is a member of chat 1
+        let input = SearchMessages {
+            q: "hello".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(state.search_messages(input, 5, 1).await?.hits.len(), 4);
+
+        // members of other workspaces don't see the messages
+        let input = SearchMessages {
+            q: "hello".to_string(),
+            ..Default::default()
+        };
+        assert!(state.search_messages(input, 1, 2).await?.hits.is_empty());
+
+        let input = SearchMessages {
+            q: " ".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            state.search_messages(input, 1, 1).await,
+            Err(AppError::SearchError(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_snippet_should_be_escaped() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        sqlx::query("INSERT INTO messages (chat_id, sender_id, content) VALUES (1, 1, $1)")
+            .bind("<img src=x onerror=alert(1)> \u{2}quokka\u{3} & \"quokka\"")
+            .execute(&state.pool)
+            .await?;
+
+        let input = SearchMessages {
+            q: "quokka".to_string(),
+            ..Default::default()
+        };
+        let ret = state.search_messages(input, 1, 1).await?;
+        assert_eq!(
+            ret.hits[0].snippet,
+            "&lt;img src=x onerror=alert(1)&gt; <mark>quokka</mark> &amp; &quot;<mark>quokka</mark>&quot;"
+        );
+
+        Ok(())
+    }
+}
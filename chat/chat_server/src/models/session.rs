@@ -0,0 +1,347 @@
+use crate::{AppError, AppState};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::{SessionId, User, SESSION_REVOKED_CHANNEL};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{postgres::PgListener, FromRow, PgExecutor};
+use tokio::time;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+/// Lifetime of a session, refreshing doesn't extend it
+const SESSION_DAYS: i64 = 30;
+/// Random bytes in a refresh token, hex encoded
+const REFRESH_TOKEN_BYTES: usize = 32;
+/// in seconds, the delay before listening to revoked sessions again, doubled on every failure
+const WATCH_RETRY_SECS: u64 = 1;
+const WATCH_RETRY_MAX_SECS: u64 = 60;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct RefreshSession {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, FromRow)]
+struct SessionRow {
+    id: String,
+    user_id: i64,
+    /// false if the refresh token is the rotated one
+    current: bool,
+    expires_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Create a session for the user, returns the session id and its refresh token
+    pub async fn create_session(&self, user_id: u64) -> Result<(SessionId, String), AppError> {
+        let id = uuid::Uuid::now_v7().to_string();
+        let refresh_token = generate_refresh_token();
+        let expires_at = Utc::now() + Duration::days(SESSION_DAYS);
+
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, refresh_token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(&id)
+        .bind(user_id as i64)
+        .bind(hash_refresh_token(&refresh_token))
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok((SessionId(id), refresh_token))
+    }
+
+    /// Exchange the refresh token for a new one, returns the user with its active workspace.
+    /// Presenting a rotated refresh token revokes the session, as the token might be stolen.
+    pub async fn refresh_session(
+        &self,
+        refresh_token: &str,
+    ) -> Result<(User, SessionId, String), AppError> {
+        let hash = hash_refresh_token(refresh_token);
+        let mut tx = self.pool.begin().await?;
+        let session: Option<SessionRow> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, refresh_token_hash = $1 AS current, expires_at, revoked_at
+            FROM sessions
+            WHERE refresh_token_hash = $1 OR prev_refresh_token_hash = $1
+            FOR UPDATE
+            "#,
+        )
+        .bind(&hash)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(session) = session else {
+            return Err(AppError::SessionError("invalid refresh token".to_string()));
+        };
+        if session.revoked_at.is_some() {
+            return Err(AppError::SessionError("session revoked".to_string()));
+        }
+        if session.expires_at < Utc::now() {
+            return Err(AppError::SessionError("session expired".to_string()));
+        }
+
+        let session_id = SessionId(session.id);
+        if !session.current {
+            revoke(&mut *tx, &session_id).await?;
+            tx.commit().await?;
+            self.revoked.revoke(session_id.0);
+            return Err(AppError::SessionError(
+                "refresh token reused, session revoked".to_string(),
+            ));
+        }
+
+        let refresh_token = rotate(&mut *tx, &session_id).await?;
+        tx.commit().await?;
+
+        let mut user = self
+            .find_user_by_id(session.user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("User id {}", session.user_id)))?;
+        if let Some(ws) = self.find_workspace_by_id(user.ws_id as _).await? {
+            user.ws_name = ws.name;
+        }
+
+        Ok((user, session_id, refresh_token))
+    }
+
+    /// Issue a new refresh token for the session, the previous one couldn't be used any more
+    pub async fn rotate_session(&self, session_id: &SessionId) -> Result<String, AppError> {
+        rotate(&self.pool, session_id).await
+    }
+
+    /// Revoke the session of the user, access tokens of the session are rejected immediately
+    pub async fn revoke_session(
+        &self,
+        session_id: &SessionId,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE sessions
+            SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(&session_id.0)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("Session id {}", session_id.0)));
+        }
+        self.revoked.revoke(session_id.0.clone());
+        Ok(())
+    }
+
+    /// Revoke all sessions of the user, e.g. when the password is reset
+    pub async fn revoke_user_sessions(&self, user_id: u64) -> Result<(), AppError> {
+        let ids: Vec<(String,)> = sqlx::query_as(
+            r#"
+            UPDATE sessions
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            RETURNING id
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        for (id,) in ids {
+            self.revoked.revoke(id);
+        }
+        Ok(())
+    }
+
+    /// Keep the revoked sessions in sync with other instances of the servers. If the listener
+    /// fails, it reconnects with backoff and reloads the sessions revoked in the meantime.
+    pub(crate) async fn watch_revoked_sessions(&self) -> Result<(), AppError> {
+        let mut listener = listen_revoked_sessions(self).await?;
+
+        let state = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.try_recv().await {
+                    Ok(Some(notify)) => {
+                        info!("Session revoked: {}", notify.payload());
+                        state.revoked.revoke(notify.payload());
+                        continue;
+                    }
+                    Ok(None) => warn!("Lost the connection of revoked sessions"),
+                    Err(_) if state.pool.is_closed() => break,
+                    Err(e) => warn!("Failed to receive revoked sessions: {}", e),
+                }
+
+                let mut retry = WATCH_RETRY_SECS;
+                listener = loop {
+                    time::sleep(time::Duration::from_secs(retry)).await;
+                    match listen_revoked_sessions(&state).await {
+                        Ok(listener) => break listener,
+                        Err(e) => {
+                            warn!("Failed to listen to revoked sessions again: {}", e);
+                            retry = (retry * 2).min(WATCH_RETRY_MAX_SECS);
+                        }
+                    }
+                };
+                info!("Listening to revoked sessions again");
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Listen before loading, so that no revocation is missed in between
+async fn listen_revoked_sessions(state: &AppState) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(&state.pool).await?;
+    listener.listen(SESSION_REVOKED_CHANNEL).await?;
+    state.revoked.load(&state.pool).await?;
+    Ok(listener)
+}
+
+async fn rotate<'e>(
+    executor: impl PgExecutor<'e>,
+    session_id: &SessionId,
+) -> Result<String, AppError> {
+    let refresh_token = generate_refresh_token();
+    let ret = sqlx::query(
+        r#"
+        UPDATE sessions
+        SET prev_refresh_token_hash = refresh_token_hash,
+            refresh_token_hash = $1,
+            refreshed_at = NOW()
+        WHERE id = $2 AND revoked_at IS NULL AND expires_at > NOW()
+        "#,
+    )
+    .bind(hash_refresh_token(&refresh_token))
+    .bind(&session_id.0)
+    .execute(executor)
+    .await?;
+
+    if ret.rows_affected() == 0 {
+        return Err(AppError::SessionError(
+            "session revoked or expired".to_string(),
+        ));
+    }
+    Ok(refresh_token)
+}
+
+async fn revoke<'e>(executor: impl PgExecutor<'e>, session_id: &SessionId) -> Result<(), AppError> {
+    sqlx::query("UPDATE sessions SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL")
+        .bind(&session_id.0)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+fn generate_refresh_token() -> String {
+    let mut buf = [0u8; REFRESH_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+/// Only hashes of refresh tokens are stored, the tokens are random enough to skip salting
+fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_refresh_session_should_rotate_token() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let (session_id, token) = state.create_session(1).await?;
+        let (user, session_id2, token2) = state.refresh_session(&token).await?;
+        assert_eq!(user.id, 1);
+        assert_eq!(user.ws_name, "acme");
+        assert_eq!(session_id, session_id2);
+        assert_ne!(token, token2);
+
+        let ret = state.refresh_session("bad-token").await;
+        assert!(matches!(ret, Err(AppError::SessionError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reused_refresh_token_should_revoke_session() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let (session_id, token) = state.create_session(1).await?;
+        let (_, _, token2) = state.refresh_session(&token).await?;
+
+        let ret = state.refresh_session(&token).await;
+        assert!(matches!(ret, Err(AppError::SessionError(_))));
+        assert!(state.revoked.is_revoked(&session_id));
+
+        // the latest token is revoked with the session
+        let ret = state.refresh_session(&token2).await;
+        assert!(matches!(ret, Err(AppError::SessionError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_revoke_session_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let (session_id, token) = state.create_session(1).await?;
+        // only the owner of the session could revoke it
+        let ret = state.revoke_session(&session_id, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        state.revoke_session(&session_id, 1).await?;
+        assert!(state.revoked.is_revoked(&session_id));
+        let ret = state.refresh_session(&token).await;
+        assert!(matches!(ret, Err(AppError::SessionError(_))));
+        let ret = state.rotate_session(&session_id).await;
+        assert!(matches!(ret, Err(AppError::SessionError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_watch_revoked_sessions_should_reconnect() -> Result<()> {
+        let (tdb, state) = AppState::try_new_for_test().await?;
+        state.watch_revoked_sessions().await?;
+
+        // drop the connection of the listener
+        let terminated: Vec<(bool,)> = sqlx::query_as(
+            r#"
+            SELECT pg_terminate_backend(pid)
+            FROM pg_stat_activity
+            WHERE datname = $1 AND query LIKE 'LISTEN%'
+            "#,
+        )
+        .bind(tdb.dbname.as_str())
+        .fetch_all(&state.pool)
+        .await?;
+        assert_eq!(terminated, vec![(true,)]);
+
+        // revoked by another instance while the listener reconnects
+        let session_id = SessionId("session-x".to_string());
+        for _ in 0..50 {
+            if state.revoked.is_revoked(&session_id) {
+                return Ok(());
+            }
+            sqlx::query("SELECT pg_notify($1, $2)")
+                .bind(SESSION_REVOKED_CHANNEL)
+                .bind(&session_id.0)
+                .execute(&state.pool)
+                .await?;
+            time::sleep(time::Duration::from_millis(100)).await;
+        }
+        panic!("revoked session is not received after reconnecting");
+    }
+}
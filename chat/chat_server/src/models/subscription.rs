@@ -0,0 +1,520 @@
+use crate::{AppError, AppState};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::{is_public_ip, WEBHOOK_EVENTS};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::FromRow;
+use std::net::IpAddr;
+use tokio::net::lookup_host;
+use utoipa::{IntoParams, ToSchema};
+
+/// Random bytes in a subscription secret, hex encoded
+const SECRET_BYTES: usize = 24;
+const DEFAULT_DELIVERY_LIMIT: u64 = 20;
+const MAX_DELIVERY_LIMIT: u64 = 100;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateSubscription {
+    /// http(s) endpoint the events are posted to
+    pub url: String,
+    /// names of the events, like `NewMessage`. All events if empty.
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateSubscription {
+    /// events are queued while paused, and delivered once resumed
+    #[serde(default)]
+    pub paused: Option<bool>,
+    #[serde(default)]
+    pub events: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EventSubscription {
+    pub id: i64,
+    pub ws_id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub created_by: i64,
+    pub paused_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedSubscription {
+    /// shown only once, verify the `X-Chat-Signature` of the deliveries with it
+    pub secret: String,
+    #[serde(flatten)]
+    pub subscription: EventSubscription,
+}
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "delivery_status", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    /// given up after all the retries
+    Failed,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EventDelivery {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub event: String,
+    /// the event as sent to SSE clients
+    #[schema(value_type = Object)]
+    pub payload: Value,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliveryAttempt {
+    pub id: i64,
+    /// none if there is no response, e.g. timed out
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventDeliveryDetail {
+    #[serde(flatten)]
+    pub delivery: EventDelivery,
+    /// oldest first
+    pub attempts: Vec<DeliveryAttempt>,
+}
+
+/// Deliveries of the subscription, newest first
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListDeliveries {
+    #[serde(default)]
+    pub status: Option<DeliveryStatus>,
+    /// deliveries older than this id
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+const SUBSCRIPTION_COLUMNS: &str = "id, ws_id, url, events, created_by, paused_at, created_at";
+const DELIVERY_COLUMNS: &str = "id, subscription_id, event, payload, status, attempts, next_attempt_at, delivered_at, created_at";
+
+#[allow(dead_code)]
+impl AppState {
+    /// Subscribe an endpoint to the events of the workspace, only for workspace owner/admins
+    pub async fn create_subscription(
+        &self,
+        input: CreateSubscription,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<CreatedSubscription, AppError> {
+        self.check_workspace_admin(ws_id, user_id).await?;
+        validate_url(&input.url).await?;
+        let events = validate_events(input.events)?;
+
+        let secret = generate_secret();
+        let subscription = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO event_subscriptions (ws_id, url, secret, events, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {SUBSCRIPTION_COLUMNS}
+            "#
+        ))
+        .bind(ws_id as i64)
+        .bind(&input.url)
+        .bind(&secret)
+        .bind(&events)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(CreatedSubscription {
+            secret,
+            subscription,
+        })
+    }
+
+    pub async fn list_subscriptions(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<EventSubscription>, AppError> {
+        self.check_workspace_admin(ws_id, user_id).await?;
+        let subscriptions = sqlx::query_as(&format!(
+            "SELECT {SUBSCRIPTION_COLUMNS} FROM event_subscriptions WHERE ws_id = $1 ORDER BY id"
+        ))
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(subscriptions)
+    }
+
+    /// Pause or resume the subscription, or change its events
+    pub async fn update_subscription(
+        &self,
+        input: UpdateSubscription,
+        id: u64,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<EventSubscription, AppError> {
+        self.check_workspace_admin(ws_id, user_id).await?;
+        let events = input.events.map(validate_events).transpose()?;
+
+        let subscription: Option<EventSubscription> = sqlx::query_as(&format!(
+            r#"
+            UPDATE event_subscriptions
+            SET
+              paused_at = CASE
+                WHEN $3::boolean IS NULL THEN paused_at
+                WHEN $3 THEN COALESCE(paused_at, NOW())
+                ELSE NULL
+              END,
+              events = COALESCE($4, events)
+            WHERE id = $1 AND ws_id = $2
+            RETURNING {SUBSCRIPTION_COLUMNS}
+            "#
+        ))
+        .bind(id as i64)
+        .bind(ws_id as i64)
+        .bind(input.paused)
+        .bind(events)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        subscription.ok_or_else(|| AppError::NotFound(format!("Subscription id {id}")))
+    }
+
+    /// Delete the subscription along with its deliveries
+    pub async fn delete_subscription(
+        &self,
+        id: u64,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        self.check_workspace_admin(ws_id, user_id).await?;
+        let ret = sqlx::query("DELETE FROM event_subscriptions WHERE id = $1 AND ws_id = $2")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("Subscription id {id}")));
+        }
+        Ok(())
+    }
+
+    pub async fn list_deliveries(
+        &self,
+        subscription_id: u64,
+        input: ListDeliveries,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<EventDelivery>, AppError> {
+        self.check_subscription(subscription_id, ws_id, user_id)
+            .await?;
+        let limit = match input.limit {
+            0 => DEFAULT_DELIVERY_LIMIT,
+            n => n.min(MAX_DELIVERY_LIMIT),
+        };
+
+        let deliveries = sqlx::query_as(&format!(
+            r#"
+            SELECT {DELIVERY_COLUMNS}
+            FROM event_deliveries
+            WHERE subscription_id = $1
+              AND ($2::delivery_status IS NULL OR status = $2)
+              AND ($3::bigint IS NULL OR id < $3)
+            ORDER BY id DESC
+            LIMIT $4
+            "#
+        ))
+        .bind(subscription_id as i64)
+        .bind(input.status)
+        .bind(input.last_id.map(|id| id as i64))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(deliveries)
+    }
+
+    /// Get the delivery with all its attempts
+    pub async fn get_delivery(
+        &self,
+        subscription_id: u64,
+        id: u64,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<EventDeliveryDetail, AppError> {
+        self.check_subscription(subscription_id, ws_id, user_id)
+            .await?;
+        let delivery: Option<EventDelivery> = sqlx::query_as(&format!(
+            "SELECT {DELIVERY_COLUMNS} FROM event_deliveries WHERE id = $1 AND subscription_id = $2"
+        ))
+        .bind(id as i64)
+        .bind(subscription_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        let delivery = delivery.ok_or_else(|| AppError::NotFound(format!("Delivery id {id}")))?;
+
+        let attempts = sqlx::query_as(
+            r#"
+            SELECT id, status_code, error, duration_ms, created_at
+            FROM event_delivery_attempts
+            WHERE delivery_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(EventDeliveryDetail { delivery, attempts })
+    }
+
+    /// Queue the delivery to be sent again right away, with a fresh set of retries. Delivered
+    /// ones could be replayed as well, e.g. after the endpoint lost them.
+    pub async fn replay_delivery(
+        &self,
+        subscription_id: u64,
+        id: u64,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<EventDelivery, AppError> {
+        self.check_subscription(subscription_id, ws_id, user_id)
+            .await?;
+        let delivery: Option<EventDelivery> = sqlx::query_as(&format!(
+            r#"
+            UPDATE event_deliveries
+            SET status = 'pending', attempts = 0, next_attempt_at = NOW(), delivered_at = NULL
+            WHERE id = $1 AND subscription_id = $2
+            RETURNING {DELIVERY_COLUMNS}
+            "#
+        ))
+        .bind(id as i64)
+        .bind(subscription_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        delivery.ok_or_else(|| AppError::NotFound(format!("Delivery id {id}")))
+    }
+
+    /// The user should be a workspace owner/admin, and the subscription in the workspace
+    async fn check_subscription(&self, id: u64, ws_id: u64, user_id: u64) -> Result<(), AppError> {
+        self.check_workspace_admin(ws_id, user_id).await?;
+        let exists: Option<(i64,)> =
+            sqlx::query_as("SELECT id FROM event_subscriptions WHERE id = $1 AND ws_id = $2")
+                .bind(id as i64)
+                .bind(ws_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        match exists {
+            Some(_) => Ok(()),
+            None => Err(AppError::NotFound(format!("Subscription id {id}"))),
+        }
+    }
+}
+
+/// The endpoint should be an http(s) url of a public address. Hosts which couldn't be resolved
+/// yet are accepted, the addresses are checked again on every delivery.
+async fn validate_url(url: &str) -> Result<(), AppError> {
+    let invalid = || AppError::WebhookError(format!("invalid url: {url}"));
+    let parsed = Url::parse(url).map_err(|_| invalid())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(invalid());
+    }
+    let blocked = || AppError::WebhookError(format!("url of an internal address: {url}"));
+    let host = parsed.host_str().ok_or_else(invalid)?;
+    // ipv6 hosts are in brackets
+    if let Ok(ip) = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        return if is_public_ip(ip) {
+            Ok(())
+        } else {
+            Err(blocked())
+        };
+    }
+
+    let domain = host.trim_end_matches('.').to_ascii_lowercase();
+    if domain == "localhost" || domain.ends_with(".localhost") {
+        return Err(blocked());
+    }
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    if let Ok(addrs) = lookup_host((domain.as_str(), port)).await {
+        if addrs.into_iter().any(|addr| !is_public_ip(addr.ip())) {
+            return Err(blocked());
+        }
+    }
+    Ok(())
+}
+
+/// Known events without duplicates
+fn validate_events(events: Vec<String>) -> Result<Vec<String>, AppError> {
+    let mut ret: Vec<String> = Vec::with_capacity(events.len());
+    for event in events {
+        if !WEBHOOK_EVENTS.contains(&event.as_str()) {
+            return Err(AppError::WebhookError(format!("unknown event: {event}")));
+        }
+        if !ret.contains(&event) {
+            ret.push(event);
+        }
+    }
+    Ok(ret)
+}
+
+fn generate_secret() -> String {
+    let mut buf = [0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut buf);
+    format!("whsec_{}", hex::encode(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    impl CreateSubscription {
+        pub fn new(url: &str, events: &[&str]) -> Self {
+            Self {
+                url: url.to_string(),
+                events: events.iter().map(|e| e.to_string()).collect(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_should_be_managed_by_admins() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = CreateSubscription::new("https://example.com/hook", &["NewMessage"]);
+        let ret = state.create_subscription(input.clone(), 1, 2).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let created = state.create_subscription(input, 1, 1).await?;
+        assert!(created.secret.starts_with("whsec_"));
+        assert_eq!(created.subscription.events, vec!["NewMessage"]);
+
+        let input = CreateSubscription::new("ftp://example.com", &[]);
+        let ret = state.create_subscription(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::WebhookError(_))));
+        let input = CreateSubscription::new("https://example.com/hook", &["Unknown"]);
+        let ret = state.create_subscription(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::WebhookError(_))));
+
+        let id = created.subscription.id as u64;
+        let input = UpdateSubscription {
+            paused: Some(true),
+            ..Default::default()
+        };
+        let subscription = state.update_subscription(input, id, 1, 1).await?;
+        assert!(subscription.paused_at.is_some());
+        let input = UpdateSubscription {
+            events: Some(vec![]),
+            ..Default::default()
+        };
+        let subscription = state.update_subscription(input, id, 1, 1).await?;
+        assert!(subscription.paused_at.is_some());
+        assert!(subscription.events.is_empty());
+
+        // subscriptions of other workspaces are invisible
+        let ret = state.delete_subscription(id, 2, 1).await;
+        assert!(ret.is_err());
+        assert_eq!(state.list_subscriptions(1, 1).await?.len(), 1);
+        state.delete_subscription(id, 1, 1).await?;
+        assert!(state.list_subscriptions(1, 1).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscription_url_should_be_public() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let internal = [
+            "http://localhost:6687/events",
+            "http://app.localhost/",
+            "http://127.0.0.1:6688/api/users",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.8/hook",
+            "http://192.168.1.1/hook",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+        ];
+        for url in internal {
+            let input = CreateSubscription::new(url, &[]);
+            let ret = state.create_subscription(input, 1, 1).await;
+            assert!(
+                matches!(ret, Err(AppError::WebhookError(_))),
+                "{url} should be rejected"
+            );
+        }
+
+        let input = CreateSubscription::new("https://93.184.215.14/hook", &[]);
+        state.create_subscription(input, 1, 1).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_should_replay() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+        let input = CreateSubscription::new("https://example.com/hook", &[]);
+        let sub_id = state
+            .create_subscription(input, 1, 1)
+            .await?
+            .subscription
+            .id;
+
+        let (id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO event_deliveries (subscription_id, event_key, event, payload, status, attempts)
+            VALUES ($1, 'key', 'NewMessage', '{"event":"NewMessage"}', 'failed', 8)
+            RETURNING id
+            "#,
+        )
+        .bind(sub_id)
+        .fetch_one(&state.pool)
+        .await?;
+        sqlx::query(
+            "INSERT INTO event_delivery_attempts (delivery_id, status_code, duration_ms) VALUES ($1, 500, 12)",
+        )
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+
+        let input = ListDeliveries {
+            status: Some(DeliveryStatus::Failed),
+            ..Default::default()
+        };
+        let deliveries = state.list_deliveries(sub_id as _, input, 1, 1).await?;
+        assert_eq!(deliveries.len(), 1);
+        let detail = state.get_delivery(sub_id as _, id as _, 1, 1).await?;
+        assert_eq!(detail.attempts[0].status_code, Some(500));
+
+        let delivery = state.replay_delivery(sub_id as _, id as _, 1, 1).await?;
+        assert_eq!(delivery.status, DeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 0);
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@ use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use chat_core::{ChatUser, User};
+use chat_core::{ChatUser, User, Workspace, WorkspaceRole};
 use serde::{Deserialize, Serialize};
 use std::mem;
 use utoipa::ToSchema;
@@ -15,10 +15,14 @@ pub struct CreateUser {
     pub fullname: String,
     /// Email of the user
     pub email: String,
-    /// Workspace name - if not exists, create one
+    /// Workspace name - if not exists, create one. Ignored if the user signs up with an invite
+    #[serde(default)]
     pub workspace: String,
     /// Password of the user
     pub password: String,
+    /// Invite token - required to join an existing workspace
+    #[serde(default)]
+    pub invite: Option<String>,
 }
 
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
@@ -54,7 +58,6 @@ impl AppState {
     }
 
     /// Create a new user
-    // TODO: use transaction for workspace creation and user creation
     pub async fn create_user(&self, input: &CreateUser) -> Result<User, AppError> {
         // check if email exists
         let user = self.find_user_by_email(&input.email).await?;
@@ -62,10 +65,41 @@ impl AppState {
             return Err(AppError::EmailAlreadyExists(input.email.clone()));
         }
 
-        // check if workspace exists, if not create one
-        let ws = match self.find_workspace_by_name(&input.workspace).await? {
-            Some(ws) => ws,
-            None => self.create_workspace(&input.workspace, 0).await?,
+        if input.invite.is_none() && input.workspace.trim().is_empty() {
+            return Err(AppError::InviteError(
+                "either an invite or a workspace name is required".to_string(),
+            ));
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        // join the workspace of the invite, or create a new workspace owned by the user.
+        // Existing workspaces could only be joined with an invite, owned or not.
+        let (ws, is_owner) = match &input.invite {
+            Some(token) => (
+                self.accept_invite(&mut tx, token, &input.email).await?,
+                false,
+            ),
+            None => {
+                let ws: Option<Workspace> = sqlx::query_as(
+                    r#"
+                    INSERT INTO workspaces (name, owner_id)
+                    VALUES ($1, 0)
+                    ON CONFLICT (name) DO NOTHING
+                    RETURNING id, name, owner_id, created_at
+                    "#,
+                )
+                .bind(&input.workspace)
+                .fetch_optional(&mut *tx)
+                .await?;
+                let ws = ws.ok_or_else(|| {
+                    AppError::PermissionDenied(format!(
+                        "An invite is required to join workspace {}",
+                        input.workspace
+                    ))
+                })?;
+                (ws, true)
+            }
         };
 
         let password_hash = hash_password(&input.password)?;
@@ -80,23 +114,39 @@ impl AppState {
         .bind(&input.email)
         .bind(&input.fullname)
         .bind(password_hash)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
-        user.ws_name = ws.name.clone();
-
-        if ws.owner_id == 0 {
-            self.update_workspace_owner(ws.id as _, user.id as _)
+        let role = if is_owner {
+            WorkspaceRole::Owner
+        } else {
+            WorkspaceRole::Member
+        };
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id, role) VALUES ($1, $2, $3)")
+            .bind(ws.id)
+            .bind(user.id)
+            .bind(role)
+            .execute(&mut *tx)
+            .await?;
+
+        if is_owner {
+            sqlx::query("UPDATE workspaces SET owner_id = $1 WHERE id = $2")
+                .bind(user.id)
+                .bind(ws.id)
+                .execute(&mut *tx)
                 .await?;
         }
 
+        tx.commit().await?;
+
+        user.ws_name = ws.name;
         Ok(user)
     }
 
     /// Verify email and password
     pub async fn verify_user(&self, input: &SigninUser) -> Result<Option<User>, AppError> {
         let user: Option<User> = sqlx::query_as(
-            "SELECT id, ws_id, fullname, email, password_hash, created_at FROM users WHERE email = $1",
+            "SELECT id, ws_id, fullname, email, password_hash, created_at FROM users WHERE email = $1 AND NOT is_bot",
         )
         .bind(&input.email)
         .fetch_optional(&self.pool)
@@ -104,9 +154,11 @@ impl AppState {
 
         match user {
             Some(mut user) => {
-                let password_hash = mem::take(&mut user.password_hash);
-                let is_valid =
-                    verify_password(&input.password, &password_hash.unwrap_or_default())?;
+                // users signed up with an identity provider have no password
+                let Some(password_hash) = mem::take(&mut user.password_hash) else {
+                    return Ok(None);
+                };
+                let is_valid = verify_password(&input.password, &password_hash)?;
                 if is_valid {
                     // load workspace name
                     let ws = self.find_workspace_by_id(user.ws_id as _).await?.unwrap();
@@ -123,7 +175,7 @@ impl AppState {
     pub async fn fetch_chat_users_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-            SELECT id, fullname, email
+            SELECT id, fullname, email, is_bot
             FROM users
             WHERE id = ANY($1)
             "#,
@@ -138,9 +190,11 @@ impl AppState {
     pub async fn fetch_chat_users(&self, ws_id: u64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-            SELECT id, fullname, email
-            FROM users
-            WHERE ws_id = $1
+            SELECT u.id, u.fullname, u.email, u.is_bot, m.role
+            FROM workspace_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.ws_id = $1
+            ORDER BY u.id
             "#,
         )
         .bind(ws_id as i64)
@@ -151,7 +205,7 @@ impl AppState {
     }
 }
 
-fn hash_password(password: &str) -> Result<String, AppError> {
+pub(crate) fn hash_password(password: &str) -> Result<String, AppError> {
     let salt = SaltString::generate(&mut OsRng);
 
     // Argon2 with default params (Argon2id v19)
@@ -185,6 +239,7 @@ impl CreateUser {
             fullname: fullname.to_string(),
             workspace: ws.to_string(),
             password: password.to_string(),
+            invite: None,
         }
     }
 }
@@ -260,6 +315,59 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_create_user_in_owned_workspace_should_require_invite() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = CreateUser::new("acme", "eve@acme.org", "Eve Chen", "hunter42");
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        assert!(state.find_user_by_email("eve@acme.org").await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_user_in_existing_workspace_should_require_invite() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        // the first signup creates and owns the workspace
+        let input = CreateUser::new("contractor", "eve@acme.org", "Eve Chen", "hunter42");
+        let eve = state.create_user(&input).await?;
+        let ws = state
+            .find_workspace_by_name("contractor")
+            .await?
+            .expect("workspace should exist");
+        assert_eq!(ws.owner_id, eve.id);
+
+        let input = CreateUser::new("contractor", "mallory@acme.org", "Mallory", "hunter42");
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // workspaces without owner, like the seeded `none` one, couldn't be claimed either
+        for name in ["foo", "none"] {
+            let input = CreateUser::new(name, "mallory@acme.org", "Mallory", "hunter42");
+            let ret = state.create_user(&input).await;
+            assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        }
+        assert!(state
+            .find_user_by_email("mallory@acme.org")
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bot_user_should_not_signin() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = SigninUser::new("agent-1@bot.none", "");
+        assert!(state.verify_user(&input).await?.is_none());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_find_user_by_id_should_work() -> Result<()> {
         let (_tdb, state) = AppState::try_new_for_test().await?;
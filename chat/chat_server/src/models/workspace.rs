@@ -1,5 +1,38 @@
 use crate::{AppError, AppState};
-use chat_core::Workspace;
+use chat_core::{User, Workspace, WorkspaceRole};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// Max length of the workspace name, in chars
+const MAX_WORKSPACE_NAME_LEN: usize = 32;
+
+/// A workspace the user belongs to
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UserWorkspace {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub workspace: Workspace,
+    pub role: WorkspaceRole,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateWorkspace {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct TransferWorkspace {
+    /// the member to be the new owner
+    pub owner_id: u64,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct JoinWorkspace {
+    /// the invite token
+    pub invite: String,
+}
 
 impl AppState {
     pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<Workspace, AppError> {
@@ -54,49 +87,336 @@ impl AppState {
         id: u64,
         owner_id: u64,
     ) -> Result<Workspace, AppError> {
-        // update owner_id in two cases 1) owner_id is 0, 2) owner's ws_id = id
-        let ws = sqlx::query_as(
+        // update owner_id only if the new owner is a member of the workspace
+        let mut tx = self.pool.begin().await?;
+        let ws: Workspace = sqlx::query_as(
             r#"
             UPDATE workspaces
             SET owner_id = $1
-            WHERE id = $2 and (SELECT ws_id FROM users WHERE id = $1) = $2
+            WHERE id = $2
+              AND EXISTS (SELECT 1 FROM workspace_members WHERE ws_id = $2 AND user_id = $1)
             RETURNING id, name, owner_id, created_at
             "#,
         )
         .bind(owner_id as i64)
         .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // the previous owner stays as an admin
+        sqlx::query(
+            r#"
+            UPDATE workspace_members
+            SET role = CASE WHEN user_id = $1 THEN 'owner'::workspace_role ELSE 'admin'::workspace_role END
+            WHERE ws_id = $2 AND (user_id = $1 OR role = 'owner')
+            "#,
+        )
+        .bind(owner_id as i64)
+        .bind(id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(ws)
+    }
+
+    /// Rename the workspace, for workspace owner/admins
+    pub async fn update_workspace(
+        &self,
+        input: UpdateWorkspace,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Workspace, AppError> {
+        self.check_workspace_admin(ws_id, user_id).await?;
+
+        let name = input.name.trim();
+        let len = name.chars().count();
+        if !(3..=MAX_WORKSPACE_NAME_LEN).contains(&len) {
+            return Err(AppError::UpdateWorkspaceError(format!(
+                "Workspace name must have 3 to {MAX_WORKSPACE_NAME_LEN} characters"
+            )));
+        }
+        if let Some(ws) = self.find_workspace_by_name(name).await? {
+            if ws.id != ws_id as i64 {
+                return Err(AppError::UpdateWorkspaceError(format!(
+                    "Workspace {name} already exists"
+                )));
+            }
+        }
+
+        let ws = sqlx::query_as(
+            r#"
+            UPDATE workspaces
+            SET name = $1
+            WHERE id = $2
+            RETURNING id, name, owner_id, created_at
+            "#,
+        )
+        .bind(name)
+        .bind(ws_id as i64)
         .fetch_one(&self.pool)
         .await?;
 
         Ok(ws)
     }
+
+    /// Transfer the ownership of the workspace to another active member, for the owner only.
+    /// The previous owner becomes an admin.
+    pub async fn transfer_workspace(
+        &self,
+        input: TransferWorkspace,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Workspace, AppError> {
+        let ws = self
+            .find_workspace_by_id(ws_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Workspace id {ws_id}")))?;
+        if ws.owner_id != user_id as i64 {
+            return Err(AppError::PermissionDenied(
+                "Only the workspace owner could transfer the workspace".to_string(),
+            ));
+        }
+
+        let member = self
+            .get_workspace_member(ws_id, input.owner_id)
+            .await?
+            .filter(|m| m.deactivated_at.is_none())
+            .ok_or_else(|| AppError::NotFound(format!("Member id {}", input.owner_id)))?;
+        if member.is_bot {
+            return Err(AppError::UpdateWorkspaceError(
+                "Bot users couldn't own a workspace".to_string(),
+            ));
+        }
+
+        self.update_workspace_owner(ws_id, input.owner_id).await
+    }
+
+    /// List all workspaces the user belongs to
+    pub async fn list_user_workspaces(&self, user_id: u64) -> Result<Vec<UserWorkspace>, AppError> {
+        let workspaces = sqlx::query_as(
+            r#"
+            SELECT w.id, w.name, w.owner_id, w.created_at, m.role
+            FROM workspace_members m
+            JOIN workspaces w ON w.id = m.ws_id
+            WHERE m.user_id = $1 AND m.deactivated_at IS NULL
+            ORDER BY w.id
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(workspaces)
+    }
+
+    /// Get the role of the user in the workspace, `None` if the user is not an active member
+    pub async fn get_workspace_role(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Option<WorkspaceRole>, AppError> {
+        let role: Option<(WorkspaceRole,)> = sqlx::query_as(
+            r#"
+            SELECT role
+            FROM workspace_members
+            WHERE ws_id = $1 AND user_id = $2 AND deactivated_at IS NULL
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(role.map(|r| r.0))
+    }
+
+    /// Join another workspace with an invite
+    pub async fn join_workspace(
+        &self,
+        input: JoinWorkspace,
+        user: &User,
+    ) -> Result<UserWorkspace, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let ws = self
+            .accept_invite(&mut tx, &input.invite, &user.email)
+            .await?;
+
+        let ret = sqlx::query(
+            r#"
+            INSERT INTO workspace_members (ws_id, user_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(ws.id)
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::InviteError(format!(
+                "already a member of workspace {}",
+                ws.name
+            )));
+        }
+        // the invite lets a removed member back in
+        sqlx::query("DELETE FROM workspace_removals WHERE ws_id = $1 AND user_id = $2")
+            .bind(ws.id)
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(UserWorkspace {
+            workspace: ws,
+            role: WorkspaceRole::Member,
+        })
+    }
+
+    /// Make the workspace active for the user, returns the user scoped to the workspace.
+    /// The active workspace is remembered for the next signin.
+    pub async fn switch_workspace(&self, ws_id: u64, user_id: u64) -> Result<User, AppError> {
+        if self.get_workspace_role(ws_id, user_id).await?.is_none() {
+            return Err(AppError::NotFound(format!("Workspace id {ws_id}")));
+        }
+
+        let mut user: User = sqlx::query_as(
+            r#"
+            UPDATE users
+            SET ws_id = $1
+            WHERE id = $2
+            RETURNING id, ws_id, fullname, email, created_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let ws = self
+            .find_workspace_by_id(ws_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Workspace id {ws_id}")))?;
+        user.ws_name = ws.name;
+
+        Ok(user)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::CreateUser;
+    use crate::models::{CreateInvite, CreateUser, SigninUser};
     use anyhow::Result;
 
     #[tokio::test]
     async fn test_workspace_should_create_and_set_owner() -> Result<()> {
         let (_tdb, state) = AppState::try_new_for_test().await?;
 
-        let ws = state.create_workspace("test", 0).await?;
+        let ws = state.create_workspace("test", 1).await?;
         assert_eq!(ws.name, "test");
+        assert_eq!(ws.owner_id, 1);
 
+        // signing up to a new workspace makes the user its owner
         let email = "rcrwhyg@sina.com";
         let fullname = "Lyn Wong";
         let password = "hunter42";
-        let input = CreateUser::new(&ws.name, email, fullname, password);
+        let input = CreateUser::new("new ws", email, fullname, password);
         let user = state.create_user(&input).await?;
 
-        assert_eq!(user.ws_id, ws.id);
+        let ws = state
+            .find_workspace_by_id(user.ws_id as _)
+            .await?
+            .expect("workspace should exist");
+        assert_eq!(ws.name, "new ws");
+        assert_eq!(ws.owner_id, user.id);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_workspace_join_and_switch_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = CreateUser::new("contractor", "eve@acme.org", "Eve Chen", "hunter42");
+        let eve = state.create_user(&input).await?;
+        let workspaces = state.list_user_workspaces(eve.id as _).await?;
+        assert_eq!(workspaces.len(), 1);
+        assert_eq!(workspaces[0].role, WorkspaceRole::Owner);
+
+        // not a member of acme yet
+        let ret = state.switch_workspace(1, eve.id as _).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let invite = state.create_invite(CreateInvite::default(), 1, 1).await?;
+        let input = JoinWorkspace {
+            invite: invite.token.clone(),
+        };
+        let joined = state.join_workspace(input.clone(), &eve).await?;
+        assert_eq!(joined.workspace.name, "acme");
+        assert_eq!(joined.role, WorkspaceRole::Member);
+        let ret = state.join_workspace(input, &eve).await;
+        assert!(matches!(ret, Err(AppError::InviteError(_))));
+
+        let workspaces = state.list_user_workspaces(eve.id as _).await?;
+        assert_eq!(workspaces.len(), 2);
+
+        let user = state.switch_workspace(1, eve.id as _).await?;
+        assert_eq!(user.ws_id, 1);
+        assert_eq!(user.ws_name, "acme");
+        let users = state.fetch_chat_users(1).await?;
+        assert!(users.iter().any(|u| u.id == eve.id));
+
+        // the active workspace is used for the next signin
+        let input = SigninUser::new("eve@acme.org", "hunter42");
+        let user = state
+            .verify_user(&input)
+            .await?
+            .expect("user should signin");
+        assert_eq!(user.ws_id, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_workspace_rename_and_transfer_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_for_test().await?;
+
+        let input = UpdateWorkspace {
+            name: "acme corp".to_string(),
+        };
+        let ws = state.update_workspace(input, 1, 1).await?;
+        assert_eq!(ws.name, "acme corp");
+
+        let input = UpdateWorkspace {
+            name: "foo".to_string(),
+        };
+        let ret = state.update_workspace(input.clone(), 1, 1).await;
+        assert!(matches!(ret, Err(AppError::UpdateWorkspaceError(_))));
+        let ret = state.update_workspace(input, 1, 2).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let ret = state
+            .transfer_workspace(TransferWorkspace { owner_id: 1 }, 1, 2)
+            .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = state
+            .transfer_workspace(TransferWorkspace { owner_id: 6 }, 1, 1)
+            .await;
+        assert!(matches!(ret, Err(AppError::UpdateWorkspaceError(_))));
 
         let ws = state
-            .update_workspace_owner(ws.id as _, user.id as _)
+            .transfer_workspace(TransferWorkspace { owner_id: 2 }, 1, 1)
             .await?;
-        assert_eq!(ws.owner_id, user.id);
+        assert_eq!(ws.owner_id, 2);
+        assert_eq!(
+            state.get_workspace_role(1, 2).await?,
+            Some(WorkspaceRole::Owner)
+        );
+        assert_eq!(
+            state.get_workspace_role(1, 1).await?,
+            Some(WorkspaceRole::Admin)
+        );
 
         Ok(())
     }
@@ -113,27 +433,38 @@ mod tests {
     async fn test_workspace_should_fetch_all_chat_users() -> Result<()> {
         let (_tdb, state) = AppState::try_new_for_test().await?;
 
+        // bot users are listed so that clients could render agent replies
         let users = state.fetch_chat_users(1).await?;
-        assert_eq!(users.len(), 5);
+        assert_eq!(users.len(), 6);
+        assert_eq!(users.iter().filter(|u| u.is_bot).count(), 1);
         // assert_eq!(users.clone().split_off(2), users);
 
-        let ws = state.create_workspace("test", 0).await?;
-
         let email = "rcrwhyg@sina.com";
         let fullname = "Lyn Wong";
         let password = "hunter42";
-        let input = CreateUser::new(&ws.name, email, fullname, password);
+        let input = CreateUser::new("test", email, fullname, password);
         let user1 = state.create_user(&input).await?;
+        let ws = state
+            .find_workspace_by_id(user1.ws_id as _)
+            .await?
+            .expect("workspace should exist");
 
+        // the owner invites others to the workspace
+        let invite = state
+            .create_invite(CreateInvite::default(), ws.id as _, user1.id as _)
+            .await?;
         let email = "rcrwhyg2@sina.com";
         let fullname = "Lyn Wong2";
-        let input = CreateUser::new(&ws.name, email, fullname, password);
+        let mut input = CreateUser::new("", email, fullname, password);
+        input.invite = Some(invite.token);
         let user2 = state.create_user(&input).await?;
 
         let users = state.fetch_chat_users(ws.id as _).await?;
         assert_eq!(users.len(), 2);
         assert_eq!(users[0].id, user1.id);
         assert_eq!(users[1].id, user2.id);
+        assert_eq!(users[0].role, WorkspaceRole::Owner);
+        assert_eq!(users[1].role, WorkspaceRole::Member);
 
         Ok(())
     }
@@ -0,0 +1,226 @@
+//! A minimal OpenID Connect provider for tests.
+//!
+//! `/authorize` approves every request at once, for the user in `login_hint`, and redirects
+//! back with the code. The ID token carries `MOCK_TENANT` in the `tid` claim, and the email
+//! is verified unless it starts with `unverified`.
+
+use crate::config::OidcProviderConfig;
+use axum::{
+    extract::{Query, State},
+    http::{header::LOCATION, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use jwt_simple::prelude::*;
+use reqwest::{redirect::Policy, Url};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::net::TcpListener;
+
+pub const MOCK_TENANT: &str = "mock-tenant";
+pub const MOCK_CLIENT_ID: &str = "mock-client";
+
+#[derive(Debug, Clone)]
+pub struct MockIdp {
+    addr: SocketAddr,
+}
+
+#[derive(Clone)]
+struct MockState {
+    issuer: String,
+    key: Arc<Ed25519KeyPair>,
+    codes: Arc<Mutex<HashMap<String, PendingCode>>>,
+}
+
+struct PendingCode {
+    redirect_uri: String,
+    nonce: String,
+    code_challenge: String,
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthorizeParams {
+    redirect_uri: String,
+    state: String,
+    nonce: String,
+    code_challenge: String,
+    login_hint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenParams {
+    code: String,
+    redirect_uri: String,
+    client_id: String,
+    code_verifier: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MockClaims {
+    email: String,
+    email_verified: bool,
+    name: String,
+    tid: String,
+}
+
+impl MockIdp {
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = MockState {
+            issuer: format!("http://{addr}"),
+            key: Arc::new(Ed25519KeyPair::generate().with_key_id("mock-key")),
+            codes: Default::default(),
+        };
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery_handler))
+            .route("/authorize", get(authorize_handler))
+            .route("/token", post(token_handler))
+            .route("/jwks", get(jwks_handler))
+            .with_state(state);
+
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .expect("mock idp failed");
+        });
+
+        Ok(Self { addr })
+    }
+
+    pub fn issuer(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Provider config pointing to this server, users of `MOCK_TENANT` join the workspace
+    pub fn provider(&self, name: &str, workspace: &str) -> OidcProviderConfig {
+        OidcProviderConfig {
+            name: name.to_string(),
+            issuer: self.issuer(),
+            client_id: MOCK_CLIENT_ID.to_string(),
+            client_secret: None,
+            redirect_url: format!("http://localhost:1420/oidc/{name}/callback"),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+            tenant_claim: Some("tid".to_string()),
+            workspaces: [(MOCK_TENANT.to_string(), workspace.to_string())].into(),
+        }
+    }
+
+    /// Follow the authorization url like a browser, returns the code and the state of the
+    /// redirect back to the app
+    pub async fn authorize(&self, url: &str) -> anyhow::Result<(String, String)> {
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .build()?;
+        let resp = client.get(url).send().await?.error_for_status()?;
+        let location = resp
+            .headers()
+            .get(LOCATION)
+            .ok_or_else(|| anyhow::anyhow!("no redirect from the mock idp"))?;
+        let location = Url::parse(location.to_str()?)?;
+        let query: HashMap<_, _> = location.query_pairs().into_owned().collect();
+        match (query.get("code"), query.get("state")) {
+            (Some(code), Some(state)) => Ok((code.clone(), state.clone())),
+            _ => anyhow::bail!("no code in the redirect: {location}"),
+        }
+    }
+}
+
+async fn discovery_handler(State(state): State<MockState>) -> Json<serde_json::Value> {
+    let issuer = &state.issuer;
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "jwks_uri": format!("{issuer}/jwks"),
+    }))
+}
+
+async fn authorize_handler(
+    State(state): State<MockState>,
+    Query(params): Query<AuthorizeParams>,
+) -> Response {
+    let code = uuid::Uuid::now_v7().to_string();
+    let Ok(mut url) = Url::parse(&params.redirect_uri) else {
+        return (StatusCode::BAD_REQUEST, "invalid redirect_uri").into_response();
+    };
+    url.query_pairs_mut()
+        .append_pair("code", &code)
+        .append_pair("state", &params.state);
+
+    state.codes.lock().unwrap().insert(
+        code,
+        PendingCode {
+            redirect_uri: params.redirect_uri,
+            nonce: params.nonce,
+            code_challenge: params.code_challenge,
+            email: params.login_hint,
+        },
+    );
+    (StatusCode::FOUND, [(LOCATION, url.to_string())]).into_response()
+}
+
+async fn token_handler(
+    State(state): State<MockState>,
+    Form(params): Form<TokenParams>,
+) -> Response {
+    let Some(pending) = state.codes.lock().unwrap().remove(&params.code) else {
+        return error("invalid_grant", "unknown code");
+    };
+    let challenge =
+        Base64UrlSafeNoPadding::encode_to_string(Sha256::digest(params.code_verifier.as_bytes()))
+            .unwrap_or_default();
+    if pending.redirect_uri != params.redirect_uri || pending.code_challenge != challenge {
+        return error("invalid_grant", "redirect_uri or code_verifier mismatch");
+    }
+
+    let custom = MockClaims {
+        email_verified: !pending.email.starts_with("unverified"),
+        name: pending
+            .email
+            .split('@')
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        email: pending.email.clone(),
+        tid: MOCK_TENANT.to_string(),
+    };
+    let claims = Claims::with_custom_claims(custom, Duration::from_mins(5))
+        .with_issuer(&state.issuer)
+        .with_audience(&params.client_id)
+        .with_subject(format!("mock|{}", pending.email))
+        .with_nonce(pending.nonce);
+    match state.key.sign(claims) {
+        Ok(id_token) => Json(json!({
+            "access_token": "mock-access-token",
+            "token_type": "Bearer",
+            "expires_in": 300,
+            "id_token": id_token,
+        }))
+        .into_response(),
+        Err(e) => error("server_error", &e.to_string()),
+    }
+}
+
+async fn jwks_handler(State(state): State<MockState>) -> Json<serde_json::Value> {
+    let x = Base64UrlSafeNoPadding::encode_to_string(state.key.public_key().to_bytes())
+        .unwrap_or_default();
+    Json(json!({
+        "keys": [{ "kty": "OKP", "crv": "Ed25519", "kid": "mock-key", "use": "sig", "x": x }]
+    }))
+}
+
+fn error(code: &str, description: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": code, "error_description": description })),
+    )
+        .into_response()
+}
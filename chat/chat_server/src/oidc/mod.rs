@@ -0,0 +1,277 @@
+#[cfg(feature = "test-util")]
+pub mod mock;
+
+use crate::{config::OidcProviderConfig, AppError};
+use jwt_simple::prelude::*;
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Endpoints of the provider, from its discovery document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// Parameters of an authorization request, kept until its callback
+#[derive(Debug, Clone)]
+pub struct AuthRequest<'a> {
+    pub state: &'a str,
+    pub nonce: &'a str,
+    /// `BASE64URL(SHA256(code_verifier))`
+    pub code_challenge: &'a str,
+    pub login_hint: Option<&'a str>,
+}
+
+/// The user at the provider, taken from a verified ID token
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+    pub tenant: Option<String>,
+}
+
+/// Authorization code flow with PKCE against OpenID Connect providers. The discovery document
+/// and the keys are fetched on every signin, so key rotations of providers just work.
+#[derive(Debug, Clone, Default)]
+pub struct OidcClient {
+    http: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdpJwks {
+    keys: Vec<IdpJwk>,
+}
+
+/// Public keys of the provider, only the fields needed for the supported key types
+#[derive(Debug, Deserialize)]
+struct IdpJwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(rename = "use", default)]
+    usage: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+}
+
+enum IdpKey {
+    Rs256(Box<RS256PublicKey>),
+    Es256(ES256PublicKey),
+    Ed25519(Ed25519PublicKey),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IdTokenClaims {
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+impl OidcClient {
+    pub async fn discover(&self, provider: &OidcProviderConfig) -> Result<Discovery, AppError> {
+        let issuer = provider.issuer.trim_end_matches('/');
+        let url = format!("{issuer}/.well-known/openid-configuration");
+        let discovery: Discovery = self.get_json(&url).await?;
+        if discovery.issuer.trim_end_matches('/') != issuer {
+            return Err(AppError::OidcError(format!(
+                "issuer mismatch: {}",
+                discovery.issuer
+            )));
+        }
+        Ok(discovery)
+    }
+
+    /// Url of the provider to redirect the user to
+    pub async fn authorize_url(
+        &self,
+        provider: &OidcProviderConfig,
+        req: &AuthRequest<'_>,
+    ) -> Result<String, AppError> {
+        let discovery = self.discover(provider).await?;
+        let mut url = Url::parse(&discovery.authorization_endpoint).map_err(oidc_error)?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &provider.client_id)
+                .append_pair("redirect_uri", &provider.redirect_url)
+                .append_pair("scope", &provider.scopes.join(" "))
+                .append_pair("state", req.state)
+                .append_pair("nonce", req.nonce)
+                .append_pair("code_challenge", req.code_challenge)
+                .append_pair("code_challenge_method", "S256");
+            if let Some(hint) = req.login_hint {
+                query.append_pair("login_hint", hint);
+            }
+        }
+        Ok(url.into())
+    }
+
+    /// Exchange the authorization code for an ID token, returns the identity in the token
+    pub async fn exchange(
+        &self,
+        provider: &OidcProviderConfig,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<Identity, AppError> {
+        let discovery = self.discover(provider).await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &provider.redirect_url),
+            ("client_id", &provider.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &provider.client_secret {
+            form.push(("client_secret", secret));
+        }
+
+        let resp = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(oidc_error)?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            return Err(AppError::OidcError(format!(
+                "token endpoint returned {status}: {body}"
+            )));
+        }
+        let token: TokenResponse = resp.json().await.map_err(oidc_error)?;
+
+        let jwks: IdpJwks = self.get_json(&discovery.jwks_uri).await?;
+        verify_id_token(provider, &discovery.issuer, &jwks, &token.id_token, nonce)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, AppError> {
+        let resp = self.http.get(url).send().await.map_err(oidc_error)?;
+        let resp = resp.error_for_status().map_err(oidc_error)?;
+        resp.json().await.map_err(oidc_error)
+    }
+}
+
+impl IdpKey {
+    /// Keys of unsupported types, or for encryption, are skipped
+    fn from_jwk(jwk: &IdpJwk) -> Option<Self> {
+        if jwk.usage.as_deref().is_some_and(|u| u != "sig") {
+            return None;
+        }
+        let decode = |v: &Option<String>| {
+            v.as_deref()
+                .and_then(|v| Base64UrlSafeNoPadding::decode_to_vec(v, None).ok())
+        };
+
+        match (jwk.kty.as_str(), jwk.crv.as_deref()) {
+            ("RSA", _) => {
+                let key = RS256PublicKey::from_components(&decode(&jwk.n)?, &decode(&jwk.e)?);
+                key.ok().map(|key| Self::Rs256(Box::new(key)))
+            }
+            ("EC", Some("P-256")) => {
+                // uncompressed SEC1 point
+                let mut point = vec![0x04];
+                point.extend(decode(&jwk.x)?);
+                point.extend(decode(&jwk.y)?);
+                ES256PublicKey::from_bytes(&point).ok().map(Self::Es256)
+            }
+            ("OKP", Some("Ed25519")) => Ed25519PublicKey::from_bytes(&decode(&jwk.x)?)
+                .ok()
+                .map(Self::Ed25519),
+            _ => None,
+        }
+    }
+
+    fn verify(
+        &self,
+        token: &str,
+        options: VerificationOptions,
+    ) -> Result<JWTClaims<IdTokenClaims>, jwt_simple::Error> {
+        match self {
+            Self::Rs256(key) => key.verify_token(token, Some(options)),
+            Self::Es256(key) => key.verify_token(token, Some(options)),
+            Self::Ed25519(key) => key.verify_token(token, Some(options)),
+        }
+    }
+}
+
+fn verify_id_token(
+    provider: &OidcProviderConfig,
+    issuer: &str,
+    jwks: &IdpJwks,
+    token: &str,
+    nonce: &str,
+) -> Result<Identity, AppError> {
+    let metadata = Token::decode_metadata(token).map_err(oidc_error)?;
+    // tokens without a key id are accepted if the provider has a single key
+    let jwk = match metadata.key_id() {
+        Some(kid) => jwks.keys.iter().find(|k| k.kid.as_deref() == Some(kid)),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    };
+    let key = jwk
+        .and_then(IdpKey::from_jwk)
+        .ok_or_else(|| AppError::OidcError("no key to verify the ID token".to_string()))?;
+
+    let options = VerificationOptions {
+        allowed_issuers: Some(HashSet::from_strings(&[issuer])),
+        allowed_audiences: Some(HashSet::from_strings(&[&provider.client_id])),
+        required_nonce: Some(nonce.to_string()),
+        ..Default::default()
+    };
+    let claims = key.verify(token, options).map_err(oidc_error)?;
+
+    let subject = claims
+        .subject
+        .ok_or_else(|| AppError::OidcError("ID token without subject".to_string()))?;
+    let extra = &claims.custom.extra;
+    // some providers send the flag as a string
+    let email_verified = match extra.get("email_verified") {
+        Some(Value::Bool(v)) => *v,
+        Some(Value::String(v)) => v == "true",
+        _ => false,
+    };
+    let tenant = provider
+        .tenant_claim
+        .as_ref()
+        .and_then(|claim| extra.get(claim))
+        .and_then(Value::as_str)
+        .map(ToString::to_string);
+
+    Ok(Identity {
+        subject,
+        email: claims.custom.email,
+        email_verified,
+        name: claims.custom.name,
+        tenant,
+    })
+}
+
+fn oidc_error(e: impl ToString) -> AppError {
+    AppError::OidcError(e.to_string())
+}
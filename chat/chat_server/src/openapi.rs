@@ -1,9 +1,22 @@
 use crate::handlers::*;
 use crate::{
-    AppState, CreateChat, CreateMessage, CreateUser, ErrorOutput, ListMessages, SigninUser,
+    ApiToken, AppState, ChatSummary, CreateAgent, CreateApiToken, CreateChat,
+    CreateIncomingWebhook, CreateInvite, CreateMessage, CreateSubscription, CreateUser,
+    CreatedApiToken, CreatedIncomingWebhook, CreatedSubscription, DeliveryAttempt, DeliveryStatus,
+    ErrorOutput, EventDelivery, EventDeliveryDetail, EventSubscription, ForgotPassword,
+    IncomingWebhook, JoinWorkspace, ListDeliveries, ListMessages, MarkRead, MessagePage,
+    MfaChallenge, OidcAuthorizeOutput, OidcCallback, OidcProvider, ReactionInput, RecoveryCodes,
+    RefreshSession, ResetPassword, SearchHit, SearchMessages, SearchResult, SigninMfa, SigninUser,
+    TotpCode, TotpSetup, TransferWorkspace, UpdateAgent, UpdateChatRole, UpdateMember,
+    UpdateMessage, UpdateSubscription, UpdateWorkspace, UserWorkspace, VerifyEmail,
+    WebhookAttachment, WebhookMessage, WorkspaceInvite, WorkspaceMember,
 };
 use axum::Router;
-use chat_core::{Chat, ChatType, ChatUser, Message, User, Workspace};
+use chat_core::{
+    AgentArgs, AgentType, ApiScope, Chat, ChatAgent, ChatReadState, ChatRole, ChatType, ChatUser,
+    Jwk, Jwks, Message, MessageReaction, ModelArgs, ReactionCount, SenderType, User, Workspace,
+    WorkspaceRole,
+};
 use utoipa::{
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi,
@@ -21,17 +34,69 @@ pub(crate) trait OpenApiRouter {
     paths(
         signup_handler,
         signin_handler,
+        signin_mfa_handler,
+        refresh_handler,
+        signout_handler,
+        jwks_handler,
+        forgot_password_handler,
+        reset_password_handler,
+        verify_email_handler,
+        setup_totp_handler,
+        enable_totp_handler,
+        disable_totp_handler,
+        list_oidc_provider_handler,
+        oidc_authorize_handler,
+        oidc_callback_handler,
         list_chat_handler,
         create_chat_handler,
         get_chat_handler,
         update_chat_handler,
+        update_chat_role_handler,
         list_message_handler,
+        list_thread_handler,
+        update_message_handler,
+        delete_message_handler,
+        add_reaction_handler,
+        remove_reaction_handler,
         delete_chat_handler,
+        mark_read_handler,
+        list_read_handler,
         send_message_handler,
         list_chat_users_handler,
+        get_workspace_handler,
+        update_workspace_handler,
+        transfer_workspace_handler,
+        list_member_handler,
+        update_member_handler,
+        remove_member_handler,
+        list_workspace_handler,
+        join_workspace_handler,
+        switch_workspace_handler,
+        list_invite_handler,
+        create_invite_handler,
+        revoke_invite_handler,
+        list_api_token_handler,
+        create_api_token_handler,
+        revoke_api_token_handler,
+        list_incoming_webhook_handler,
+        create_incoming_webhook_handler,
+        revoke_incoming_webhook_handler,
+        post_webhook_message_handler,
+        list_subscription_handler,
+        create_subscription_handler,
+        update_subscription_handler,
+        delete_subscription_handler,
+        list_delivery_handler,
+        get_delivery_handler,
+        replay_delivery_handler,
+        search_messages_handler,
+        list_agent_handler,
+        create_agent_handler,
+        update_agent_handler,
+        delete_agent_handler,
     ),
     components  (
-        schemas(Chat, ChatType, ChatUser, Message, User, Workspace, CreateChat, CreateMessage, CreateUser, ErrorOutput, ListMessages, SigninUser),
+        schemas(Chat, ChatReadState, ChatSummary, ChatType, ChatUser, Message, MessageReaction, ReactionCount, SenderType, User, Workspace, AgentArgs, AgentType, ChatAgent, ModelArgs, CreateAgent, CreateChat, CreateInvite, CreateMessage, CreateUser, ErrorOutput, ListMessages, MarkRead, MessagePage, ReactionInput, SearchHit, SearchMessages, SearchResult, SigninUser, UpdateAgent, UpdateChatRole, UpdateMessage, WorkspaceInvite, ChatRole, WorkspaceRole, JoinWorkspace, UserWorkspace, AuthOutput, UpdateWorkspace, TransferWorkspace, UpdateMember, WorkspaceMember, RefreshSession, Jwks, Jwk, ForgotPassword, ResetPassword, VerifyEmail, OidcProvider, OidcAuthorizeOutput, OidcCallback, TotpSetup, TotpCode, RecoveryCodes, MfaChallenge, SigninMfa, ApiScope, ApiToken, CreateApiToken, CreatedApiToken, IncomingWebhook, CreateIncomingWebhook, CreatedIncomingWebhook, WebhookMessage, WebhookAttachment, EventSubscription, CreateSubscription, CreatedSubscription, UpdateSubscription, DeliveryStatus, EventDelivery, DeliveryAttempt, EventDeliveryDetail, ListDeliveries),
     ),
     modifiers(
         &SecurityAddon,
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use chat_core::{Chat, ChatType, Message};
+use chat_core::{Chat, ChatType, Message, SenderType};
 use chat_server::AppState;
 use futures::StreamExt as _;
 use reqwest::{
@@ -88,6 +88,7 @@ impl NotifyServer {
                             assert_eq!(message.content, "hello");
                             assert_eq!(message.files.len(), 1);
                             assert_eq!(message.sender_id, 1);
+                            assert_eq!(message.sender_type, SenderType::User);
                         }
                         _ => {
                             panic!("Unexpected event: {:?}", message);
@@ -0,0 +1,19 @@
+-- Add migration script here
+
+-- agent args are typed now, existing rows are left as they are. Rows that couldn't be typed are
+-- reported here, and the server reports all rows that don't parse on startup.
+DO $$
+DECLARE
+  agent record;
+BEGIN
+  FOR agent IN
+    SELECT id, name, args FROM chat_agents
+    WHERE jsonb_typeof(args) <> 'object' OR NOT args ? 'adapter'
+  LOOP
+    RAISE WARNING 'agent % (%) has invalid args: %', agent.name, agent.id, agent.args;
+  END LOOP;
+END $$;
+
+-- only new and updated rows are checked, so that invalid rows are kept for the report
+ALTER TABLE chat_agents
+  ADD CONSTRAINT chat_agents_args_adapter CHECK (jsonb_typeof(args) = 'object' AND args ? 'adapter') NOT VALID;
@@ -0,0 +1,28 @@
+-- Add migration script here
+
+-- bot users are the identity agents post as, they can't sign in
+ALTER TABLE users ADD COLUMN is_bot boolean NOT NULL DEFAULT FALSE;
+
+-- who authored the message
+CREATE TYPE sender_type AS ENUM ('user', 'agent');
+
+ALTER TABLE messages ADD COLUMN sender_type sender_type NOT NULL DEFAULT 'user';
+
+-- every agent has a bot user in the workspace of its chat
+ALTER TABLE chat_agents ADD COLUMN user_id bigint REFERENCES users(id);
+
+INSERT INTO users(ws_id, fullname, email, password_hash, is_bot)
+SELECT
+  c.ws_id, a.name, 'agent-' || a.id || '@bot.none', '', TRUE
+FROM
+  chat_agents a
+  JOIN chats c ON c.id = a.chat_id
+WHERE
+  a.user_id IS NULL;
+
+UPDATE chat_agents a
+SET user_id = u.id
+FROM users u
+WHERE a.user_id IS NULL AND u.email = 'agent-' || a.id || '@bot.none';
+
+ALTER TABLE chat_agents ALTER COLUMN user_id SET NOT NULL;
@@ -0,0 +1,46 @@
+-- Add migration script here
+
+-- edits keep the time of the last edit, deletes are tombstones
+ALTER TABLE messages ADD COLUMN edited_at timestamptz;
+
+ALTER TABLE messages ADD COLUMN deleted_at timestamptz;
+
+-- notify message created, updated and deleted with message data
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+  CHANNEL text;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    CHANNEL := 'chat_message_created';
+  ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    CHANNEL := 'chat_message_deleted';
+  ELSIF NEW.edited_at IS DISTINCT FROM OLD.edited_at THEN
+    CHANNEL := 'chat_message_updated';
+  ELSE
+    RETURN NEW;
+  END IF;
+
+  RAISE NOTICE 'add_to_message: % %', CHANNEL, NEW;
+  -- select chat with chat_id in NEW
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  PERFORM
+    pg_notify(CHANNEL, json_build_object('message', NEW, 'members', USERS)::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS add_to_message_trigger ON messages;
+
+CREATE TRIGGER add_to_message_trigger
+  AFTER INSERT OR UPDATE ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_message();
@@ -0,0 +1,11 @@
+-- Add migration script here
+
+-- thread replies reference the top-level message, which keeps the reply stats
+ALTER TABLE messages ADD COLUMN parent_id bigint REFERENCES messages(id);
+
+ALTER TABLE messages ADD COLUMN reply_count integer NOT NULL DEFAULT 0;
+
+ALTER TABLE messages ADD COLUMN last_reply_at timestamptz;
+
+-- create index for thread replies
+CREATE INDEX IF NOT EXISTS parent_id_index ON messages(parent_id, id DESC) WHERE parent_id IS NOT NULL;
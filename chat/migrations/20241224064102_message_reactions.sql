@@ -0,0 +1,45 @@
+-- Add migration script here
+
+-- one row per user and emoji on a message
+CREATE TABLE IF NOT EXISTS message_reactions(
+  message_id bigint NOT NULL REFERENCES messages(id),
+  user_id bigint NOT NULL REFERENCES users(id),
+  emoji varchar(32) NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (message_id, user_id, emoji)
+);
+
+-- if reaction added or removed, notify with reaction data
+CREATE OR REPLACE FUNCTION add_to_message_reaction()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  REACTION message_reactions;
+  CHAT_ID bigint;
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    REACTION := NEW;
+  ELSE
+    REACTION := OLD;
+  END IF;
+
+  RAISE NOTICE 'add_to_message_reaction: % %', TG_OP, REACTION;
+  SELECT
+    c.id, c.members INTO CHAT_ID, USERS
+  FROM
+    messages m
+    JOIN chats c ON c.id = m.chat_id
+  WHERE
+    m.id = REACTION.message_id;
+  PERFORM
+    pg_notify('chat_message_reaction', json_build_object('op', TG_OP, 'chat_id', CHAT_ID, 'reaction', REACTION, 'members', USERS)::text);
+  RETURN REACTION;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_message_reaction_trigger
+  AFTER INSERT OR DELETE ON message_reactions
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_message_reaction();
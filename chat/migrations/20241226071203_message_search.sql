@@ -0,0 +1,8 @@
+-- Add migration script here
+
+-- full-text search on message content, 'simple' config since chats mix languages
+ALTER TABLE messages
+  ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED;
+
+-- create index for message search
+CREATE INDEX IF NOT EXISTS messages_search_index ON messages USING GIN(search_vector);
@@ -0,0 +1,19 @@
+-- Add migration script here
+
+-- invites to join a workspace, issued by the workspace owner
+CREATE TABLE IF NOT EXISTS workspace_invites(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  token varchar(64) NOT NULL UNIQUE,
+  -- if set, only the user with this email could accept the invite
+  email varchar(64),
+  created_by bigint NOT NULL REFERENCES users(id),
+  expires_at timestamptz NOT NULL,
+  -- if not set, the invite could be used until it expires
+  max_uses int,
+  use_count int NOT NULL DEFAULT 0,
+  revoked_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS workspace_invites_ws_id_idx ON workspace_invites(ws_id, created_at DESC);
@@ -0,0 +1,35 @@
+-- Add migration script here
+
+-- role of the user in the workspace
+CREATE TYPE workspace_role AS ENUM(
+  'owner',
+  'admin',
+  'member',
+  'guest'
+);
+
+ALTER TABLE users
+  ADD COLUMN role workspace_role NOT NULL DEFAULT 'member';
+
+UPDATE
+  users
+SET
+  role = 'owner'
+FROM
+  workspaces
+WHERE
+  workspaces.owner_id = users.id;
+
+-- role of the member in the chat, plain members have no row
+CREATE TYPE chat_role AS ENUM(
+  'creator',
+  'admin'
+);
+
+CREATE TABLE IF NOT EXISTS chat_roles(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  role chat_role NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, user_id)
+);
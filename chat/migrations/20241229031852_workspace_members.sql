@@ -0,0 +1,24 @@
+-- Add migration script here
+
+-- users could belong to multiple workspaces, users.ws_id is the active one
+CREATE TABLE IF NOT EXISTS workspace_members(
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  user_id bigint NOT NULL REFERENCES users(id),
+  role workspace_role NOT NULL DEFAULT 'member',
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (ws_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS workspace_members_user_id_idx ON workspace_members(user_id);
+
+INSERT INTO workspace_members(ws_id, user_id, role)
+SELECT
+  ws_id,
+  id,
+  role
+FROM
+  users;
+
+-- the role is per workspace now
+ALTER TABLE users
+  DROP COLUMN role;
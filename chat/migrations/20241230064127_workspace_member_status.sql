@@ -0,0 +1,5 @@
+-- Add migration script here
+
+-- deactivated members keep their chats but couldn't access the workspace
+ALTER TABLE workspace_members
+  ADD COLUMN deactivated_at timestamptz;
@@ -0,0 +1,38 @@
+-- Add migration script here
+
+-- a session is created on signin/signup, the access tokens of the session carry its id.
+-- refresh tokens are rotated on every refresh and only their hashes are stored.
+CREATE TABLE IF NOT EXISTS sessions(
+  id varchar(64) PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id),
+  refresh_token_hash varchar(64) NOT NULL UNIQUE,
+  -- the rotated refresh token, presenting it again means the token was stolen
+  prev_refresh_token_hash varchar(64),
+  expires_at timestamptz NOT NULL,
+  revoked_at timestamptz,
+  refreshed_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_id_idx ON sessions(user_id);
+CREATE INDEX IF NOT EXISTS sessions_prev_refresh_token_hash_idx ON sessions(prev_refresh_token_hash);
+
+-- if session revoked, notify token verifiers with the session id
+CREATE OR REPLACE FUNCTION session_revoked()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF OLD.revoked_at IS NULL AND NEW.revoked_at IS NOT NULL THEN
+    RAISE NOTICE 'session_revoked: %', NEW.id;
+    PERFORM
+      pg_notify('session_revoked', NEW.id);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER session_revoked_trigger
+  AFTER UPDATE ON sessions
+  FOR EACH ROW
+  EXECUTE FUNCTION session_revoked();
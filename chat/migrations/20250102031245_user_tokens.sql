@@ -0,0 +1,20 @@
+-- Add migration script here
+
+-- users proving the ownership of their emails, existing users are trusted
+ALTER TABLE users
+  ADD COLUMN email_verified_at timestamptz;
+
+UPDATE users SET email_verified_at = created_at WHERE NOT is_bot;
+
+-- one-time tokens mailed to users, e.g. password reset. The tokens are signed and carry
+-- the id, so only the id is stored to make sure each token is used once.
+CREATE TABLE IF NOT EXISTS user_tokens(
+  id varchar(64) PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id),
+  purpose varchar(32) NOT NULL,
+  expires_at timestamptz NOT NULL,
+  used_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS user_tokens_user_id_idx ON user_tokens(user_id, purpose);
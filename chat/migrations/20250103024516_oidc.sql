@@ -0,0 +1,26 @@
+-- Add migration script here
+
+-- users signing up with an identity provider have no password
+ALTER TABLE users
+  ALTER COLUMN password_hash DROP NOT NULL;
+
+-- identities of users at the OpenID Connect providers, subjects are unique per provider
+CREATE TABLE IF NOT EXISTS user_identities(
+  provider varchar(64) NOT NULL,
+  subject varchar(255) NOT NULL,
+  user_id bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX IF NOT EXISTS user_identities_user_id_idx ON user_identities(user_id);
+
+-- pending authorization requests, each is consumed by its callback
+CREATE TABLE IF NOT EXISTS oidc_requests(
+  state varchar(64) PRIMARY KEY,
+  provider varchar(64) NOT NULL,
+  nonce varchar(64) NOT NULL,
+  code_verifier varchar(128) NOT NULL,
+  expires_at timestamptz NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
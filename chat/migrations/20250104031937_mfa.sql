@@ -0,0 +1,19 @@
+-- Add migration script here
+
+-- TOTP of users, the secret is kept while enrolling and it is enabled once a code is verified.
+-- the last used time step prevents codes from being replayed.
+ALTER TABLE users
+  ADD COLUMN totp_secret varchar(64),
+  ADD COLUMN totp_enabled_at timestamptz,
+  ADD COLUMN totp_last_step bigint;
+
+-- recovery codes in case the authenticator is lost, each could be used once
+CREATE TABLE IF NOT EXISTS user_recovery_codes(
+  id bigserial PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id),
+  code_hash varchar(64) NOT NULL,
+  used_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS user_recovery_codes_user_id_idx ON user_recovery_codes(user_id);
@@ -0,0 +1,20 @@
+-- Add migration script here
+
+CREATE TYPE api_scope AS ENUM('read_chats', 'send_messages', 'upload_files');
+
+-- personal access tokens for scripts, bound to the workspace they are created in. Only the
+-- hashes are stored, the tokens are shown once on creation.
+CREATE TABLE IF NOT EXISTS api_tokens(
+  id bigserial PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id),
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  name varchar(64) NOT NULL,
+  token_hash varchar(64) NOT NULL UNIQUE,
+  scopes api_scope[] NOT NULL,
+  expires_at timestamptz,
+  last_used_at timestamptz,
+  revoked_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS api_tokens_user_id_idx ON api_tokens(user_id);
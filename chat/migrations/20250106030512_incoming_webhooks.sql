@@ -0,0 +1,20 @@
+-- Add migration script here
+
+-- messages posted by integrations through incoming webhooks
+ALTER TYPE sender_type ADD VALUE IF NOT EXISTS 'integration';
+
+-- every webhook posts into its chat as its own bot user. Only the hashes of the secret
+-- tokens are stored, the webhook urls are shown once on creation.
+CREATE TABLE IF NOT EXISTS incoming_webhooks(
+  id bigserial PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  name varchar(64) NOT NULL,
+  token_hash varchar(64) NOT NULL UNIQUE,
+  created_by bigint NOT NULL REFERENCES users(id),
+  last_used_at timestamptz,
+  revoked_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS incoming_webhooks_chat_id_idx ON incoming_webhooks(chat_id);
@@ -0,0 +1,51 @@
+-- Add migration script here
+
+-- http endpoints of workspaces receiving the events, the deliveries are signed with the secret
+CREATE TABLE IF NOT EXISTS event_subscriptions(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  url varchar(2048) NOT NULL,
+  secret varchar(64) NOT NULL,
+  -- all events if empty
+  events varchar(32)[] NOT NULL DEFAULT '{}',
+  created_by bigint NOT NULL REFERENCES users(id),
+  -- events are still queued while paused, and delivered once resumed
+  paused_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS event_subscriptions_ws_id_idx ON event_subscriptions(ws_id);
+
+CREATE TYPE delivery_status AS ENUM('pending', 'delivered', 'failed');
+
+-- the delivery queue. Every notify server queues the events it receives, the event key
+-- makes sure each event is queued once per subscription.
+CREATE TABLE IF NOT EXISTS event_deliveries(
+  id bigserial PRIMARY KEY,
+  subscription_id bigint NOT NULL REFERENCES event_subscriptions(id) ON DELETE CASCADE,
+  event_key varchar(64) NOT NULL,
+  event varchar(32) NOT NULL,
+  payload jsonb NOT NULL,
+  status delivery_status NOT NULL DEFAULT 'pending',
+  attempts int NOT NULL DEFAULT 0,
+  next_attempt_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  delivered_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(subscription_id, event_key)
+);
+
+CREATE INDEX IF NOT EXISTS event_deliveries_due_idx ON event_deliveries(next_attempt_at)
+WHERE status = 'pending';
+
+-- every try of a delivery, for debugging the endpoints
+CREATE TABLE IF NOT EXISTS event_delivery_attempts(
+  id bigserial PRIMARY KEY,
+  delivery_id bigint NOT NULL REFERENCES event_deliveries(id) ON DELETE CASCADE,
+  -- none if the request failed without a response, e.g. timed out
+  status_code int,
+  error text,
+  duration_ms int NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS event_delivery_attempts_delivery_id_idx ON event_delivery_attempts(delivery_id);
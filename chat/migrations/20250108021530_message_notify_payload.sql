@@ -0,0 +1,37 @@
+-- Add migration script here
+
+-- the search vector of a message could be larger than its content, leave it out of the
+-- notifications so that long messages stay within the 8000 bytes limit of pg_notify
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+  CHANNEL text;
+  MESSAGE jsonb;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    CHANNEL := 'chat_message_created';
+  ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    CHANNEL := 'chat_message_deleted';
+  ELSIF NEW.edited_at IS DISTINCT FROM OLD.edited_at THEN
+    CHANNEL := 'chat_message_updated';
+  ELSE
+    RETURN NEW;
+  END IF;
+
+  MESSAGE := to_jsonb(NEW) - 'search_vector';
+  RAISE NOTICE 'add_to_message: % %', CHANNEL, MESSAGE;
+  -- select chat with chat_id in NEW
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  PERFORM
+    pg_notify(CHANNEL, json_build_object('message', MESSAGE, 'members', USERS)::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,98 @@
+-- Add migration script here
+
+-- notifications carry the time of the change, so that together with the row and the operation
+-- every event has an identity, e.g. to queue webhook deliveries once across notify servers
+CREATE OR REPLACE FUNCTION add_to_chat()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  RAISE NOTICE 'add_to_chat: %', NEW;
+  PERFORM
+    pg_notify('chat_updated', json_build_object('op', TG_OP, 'old', OLD, 'new', NEW, 'at', clock_timestamp())::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+  CHANNEL text;
+  MESSAGE jsonb;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    CHANNEL := 'chat_message_created';
+  ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    CHANNEL := 'chat_message_deleted';
+  ELSIF NEW.edited_at IS DISTINCT FROM OLD.edited_at THEN
+    CHANNEL := 'chat_message_updated';
+  ELSE
+    RETURN NEW;
+  END IF;
+
+  MESSAGE := to_jsonb(NEW) - 'search_vector';
+  RAISE NOTICE 'add_to_message: % %', CHANNEL, MESSAGE;
+  -- select chat with chat_id in NEW
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  PERFORM
+    pg_notify(CHANNEL, json_build_object('message', MESSAGE, 'members', USERS, 'at', clock_timestamp())::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION add_to_message_reaction()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  REACTION message_reactions;
+  CHAT_ID bigint;
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    REACTION := NEW;
+  ELSE
+    REACTION := OLD;
+  END IF;
+
+  RAISE NOTICE 'add_to_message_reaction: % %', TG_OP, REACTION;
+  SELECT
+    c.id, c.members INTO CHAT_ID, USERS
+  FROM
+    messages m
+    JOIN chats c ON c.id = m.chat_id
+  WHERE
+    m.id = REACTION.message_id;
+  PERFORM
+    pg_notify('chat_message_reaction', json_build_object('op', TG_OP, 'chat_id', CHAT_ID, 'reaction', REACTION, 'members', USERS, 'at', clock_timestamp())::text);
+  RETURN REACTION;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION add_to_chat_read_state()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  RAISE NOTICE 'add_to_chat_read_state: %', NEW;
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  PERFORM
+    pg_notify('chat_read_state_updated', json_build_object('read_state', NEW, 'members', USERS, 'at', clock_timestamp())::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
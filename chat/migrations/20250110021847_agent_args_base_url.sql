@@ -0,0 +1,6 @@
+-- Add migration script here
+
+-- the provider url of agents is set by the server only
+UPDATE chat_agents
+SET args = args - 'baseUrl'
+WHERE args ? 'baseUrl';
@@ -0,0 +1,9 @@
+-- Add migration script here
+
+-- members removed by admins, so that they are not added back automatically, e.g. by SSO
+CREATE TABLE IF NOT EXISTS workspace_removals(
+  ws_id bigint NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  removed_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (ws_id, user_id)
+);
@@ -6,11 +6,28 @@ use std::{env, fs::File};
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub pk: String,
+    /// public keys before the key rotation, tokens signed with them are still valid
+    #[serde(default)]
+    pub prev_pks: Vec<String>,
+    /// if set, the keys are replaced by the key set periodically
+    #[serde(default)]
+    pub jwks: Option<JwksConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JwksConfig {
+    /// a JWKS file, or the url of the JWKS endpoint of chat server
+    pub source: String,
+    /// in seconds
+    #[serde(default = "default_refresh_interval")]
+    pub refresh_interval: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +36,69 @@ pub struct ServerConfig {
     pub db_url: String,
 }
 
+/// Deliveries of the event subscriptions. A failed delivery is retried after `base_backoff`
+/// seconds, doubled on every further failure up to `max_backoff`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// in seconds, how often the due deliveries are picked up
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+    /// deliveries sent at once
+    #[serde(default = "default_batch_size")]
+    pub batch_size: u32,
+    /// in seconds, of each request to the endpoint
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: u64,
+    /// the delivery is marked as failed after these attempts
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_base_backoff")]
+    pub base_backoff: u64,
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: default_poll_interval(),
+            batch_size: default_batch_size(),
+            timeout: default_webhook_timeout(),
+            max_attempts: default_max_attempts(),
+            base_backoff: default_base_backoff(),
+            max_backoff: default_max_backoff(),
+        }
+    }
+}
+
+fn default_refresh_interval() -> u64 {
+    300
+}
+
+fn default_poll_interval() -> u64 {
+    5
+}
+
+fn default_batch_size() -> u32 {
+    20
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+fn default_max_attempts() -> u32 {
+    8
+}
+
+fn default_base_backoff() -> u64 {
+    10
+}
+
+fn default_max_backoff() -> u64 {
+    3600
+}
+
 impl AppConfig {
     pub fn try_load() -> Result<Self> {
         // read from ./notify.yml, or /etc/config/notify.yml, or from env NOTIFY_CONFIG
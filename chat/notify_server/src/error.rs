@@ -19,6 +19,12 @@ pub enum AppError {
 
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
+
+    #[error("session revoked: {0}")]
+    SessionRevoked(String),
+
+    #[error("api tokens are not accepted: {0}")]
+    ApiTokenNotAccepted(String),
 }
 
 impl ErrorOutput {
@@ -34,6 +40,8 @@ impl IntoResponse for AppError {
         let status = match &self {
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::JwtError(_) => StatusCode::FORBIDDEN,
+            Self::SessionRevoked(_) => StatusCode::UNAUTHORIZED,
+            Self::ApiTokenNotAccepted(_) => StatusCode::FORBIDDEN,
         };
 
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
@@ -0,0 +1,110 @@
+use crate::AppState;
+use anyhow::Result;
+use chat_core::{DecodingKey, Jwks};
+use std::time::Duration;
+use tokio::{fs, time};
+use tracing::{info, warn};
+
+/// Refresh the public keys from the configured key set periodically. If a refresh fails,
+/// the current keys are kept.
+pub fn setup_jwks_refresher(state: AppState) {
+    let Some(config) = &state.config.auth.jwks else {
+        return;
+    };
+    let source = config.source.clone();
+    let period = Duration::from_secs(config.refresh_interval.max(1));
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(period);
+        loop {
+            interval.tick().await;
+            match load_keys(&source).await {
+                Ok(keys) => {
+                    info!("Refreshed public keys from {}", source);
+                    state.dk.update(keys);
+                }
+                Err(e) => warn!("Failed to refresh public keys from {}: {}", source, e),
+            }
+        }
+    });
+}
+
+async fn load_keys(source: &str) -> Result<DecodingKey> {
+    let jwks: Jwks = if source.starts_with("http://") || source.starts_with("https://") {
+        reqwest::get(source)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?
+    } else {
+        serde_json::from_str(&fs::read_to_string(source).await?)?
+    };
+
+    DecodingKey::from_jwks(&jwks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::JwksConfig, AppConfig};
+    use chat_core::{EncodingKey, SessionId, User};
+    use jwt_simple::prelude::Ed25519KeyPair;
+    use std::{env, future::Future, path::Path, process};
+
+    #[tokio::test]
+    async fn jwks_refresher_should_follow_the_rotated_key_set() -> Result<()> {
+        let path = env::temp_dir().join(format!("notify_jwks_{}.json", process::id()));
+        let (old, old_token) = signed_token()?;
+        let (new, new_token) = signed_token()?;
+        write_jwks(&path, &[&old]).await?;
+
+        let mut config = AppConfig::try_load()?;
+        config.auth.jwks = Some(JwksConfig {
+            source: path.to_string_lossy().to_string(),
+            refresh_interval: 1,
+        });
+        let state = AppState::new(config);
+        // not in the configured public keys
+        assert!(state.dk.verify(&old_token).is_err());
+
+        setup_jwks_refresher(state.clone());
+        wait_until(|| async { state.dk.verify(&old_token).is_ok() }).await;
+        // an unknown kid is rejected
+        let err = state.dk.verify(&new_token).unwrap_err();
+        assert!(err.to_string().contains("unknown key id"));
+
+        // rotate the key set, the old key is dropped
+        write_jwks(&path, &[&new]).await?;
+        wait_until(|| async { state.dk.verify(&new_token).is_ok() }).await;
+        assert!(state.dk.verify(&old_token).is_err());
+
+        fs::remove_file(&path).await?;
+        Ok(())
+    }
+
+    /// A new key pair, and a token signed with it
+    fn signed_token() -> Result<(Ed25519KeyPair, String)> {
+        let key = Ed25519KeyPair::generate();
+        let ek = EncodingKey::load(&key.to_pem())?;
+        let user = User::new(1, "Tyr Chen", "tchen@acme.org");
+        let token = ek.sign(user, &SessionId("session-1".to_string()))?;
+        Ok((key, token))
+    }
+
+    async fn write_jwks(path: &Path, keys: &[&Ed25519KeyPair]) -> Result<()> {
+        let pems: Vec<_> = keys.iter().map(|key| key.public_key().to_pem()).collect();
+        let jwks = DecodingKey::load_all(pems)?.jwks();
+        fs::write(path, serde_json::to_string(&jwks)?).await?;
+        Ok(())
+    }
+
+    async fn wait_until<F: Future<Output = bool>>(f: impl Fn() -> F) {
+        for _ in 0..50 {
+            if f().await {
+                return;
+            }
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("timed out waiting for the key set to refresh");
+    }
+}
@@ -1,7 +1,9 @@
 mod config;
 mod error;
+mod jwks;
 mod notify;
 mod sse;
+mod webhook;
 
 use anyhow::Result;
 use axum::{
@@ -13,11 +15,12 @@ use axum::{
 };
 use chat_core::{
     middlewares::{verify_token, TokenVerify},
-    DecodingKey, User,
+    DecodingKey, RevokedSessions, SessionId, User,
 };
 use dashmap::DashMap;
+use sqlx::PgPool;
 use sse::sse_handler;
-use std::{ops::Deref, sync::Arc};
+use std::{iter, ops::Deref, sync::Arc};
 use tokio::sync::broadcast;
 use tower_http::cors::{self, CorsLayer};
 
@@ -36,11 +39,16 @@ pub struct AppStateInner {
     pub config: AppConfig,
     users: UserMap,
     dk: DecodingKey,
+    revoked: RevokedSessions,
+    /// connected on demand, for the deliveries of the event subscriptions
+    pool: PgPool,
 }
 
 pub async fn get_router(config: AppConfig) -> Result<Router> {
     let state = AppState::new(config);
     notify::setup_pg_listener(state.clone()).await?;
+    jwks::setup_jwks_refresher(state.clone());
+    webhook::setup_delivery_worker(state.clone())?;
 
     let cors = CorsLayer::new()
         // allow `GET` and `POST` requests when accessing the resource
@@ -71,8 +79,19 @@ async fn index_handler() -> impl IntoResponse {
 impl TokenVerify for AppState {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    fn verify(&self, token: &str) -> Result<(User, SessionId), Self::Error> {
+        let (user, session_id) = self.dk.verify(token)?;
+        if self.revoked.is_revoked(&session_id) {
+            return Err(AppError::SessionRevoked(session_id.0));
+        }
+        Ok((user, session_id))
+    }
+
+    /// Events are for the apps of users, API tokens need the database to be verified
+    async fn verify_api_token(&self, _token: &str) -> Result<User, Self::Error> {
+        Err(AppError::ApiTokenNotAccepted(
+            "sign in to receive events".to_string(),
+        ))
     }
 }
 
@@ -86,10 +105,45 @@ impl Deref for AppState {
 
 impl AppState {
     fn new(config: AppConfig) -> Self {
-        let dk = DecodingKey::load(&config.auth.pk).expect("Failed to load public key");
+        let pks = iter::once(&config.auth.pk).chain(&config.auth.prev_pks);
+        let dk = DecodingKey::load_all(pks).expect("Failed to load public keys");
         let users = Arc::new(DashMap::new());
-        let inner = Arc::new(AppStateInner { config, users, dk });
+        let revoked = RevokedSessions::default();
+        let pool = PgPool::connect_lazy(&config.server.db_url).expect("Invalid db_url");
+        let inner = Arc::new(AppStateInner {
+            config,
+            users,
+            dk,
+            revoked,
+            pool,
+        });
 
         Self(inner)
     }
 }
+
+#[cfg(test)]
+mod test_util {
+    use sqlx::{Executor, PgPool};
+    use sqlx_db_tester::TestPg;
+    use std::path::Path;
+
+    /// A new database with the migrations and the fixtures of chat server
+    pub async fn get_test_pool() -> (TestPg, PgPool) {
+        let config = crate::AppConfig::try_load().expect("Failed to load config");
+        let tdb = TestPg::new(config.server.db_url, Path::new("../migrations"));
+        let pool = tdb.get_pool().await;
+
+        let sql = include_str!("../../chat_server/fixtures/test.sql").split(';');
+        let mut ts = pool.begin().await.expect("Begin transaction failed");
+        for s in sql {
+            if s.trim().is_empty() {
+                continue;
+            }
+            ts.execute(s).await.expect("Execute sql failed");
+        }
+        ts.commit().await.expect("Commit transaction failed");
+
+        (tdb, pool)
+    }
+}
@@ -1,8 +1,8 @@
 use std::{collections::HashSet, sync::Arc};
 
-use crate::AppState;
+use crate::{webhook, AppState};
 use anyhow::Result;
-use chat_core::{Chat, Message};
+use chat_core::{Chat, ChatReadState, Message, MessageReaction, SESSION_REVOKED_CHANNEL};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgListener;
@@ -15,6 +15,38 @@ pub enum AppEvent {
     AddToChat(Chat),
     RemoveFromChat(Chat),
     NewMessage(Message),
+    /// a reply in a thread, `parent_id` of the message is the thread id
+    NewThreadReply(Message),
+    MessageUpdated(Message),
+    MessageDeleted(Message),
+    ReactionChanged(ReactionChanged),
+    ReadReceipt(ChatReadState),
+}
+
+impl AppEvent {
+    /// The `event` tag of the event
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NewChat(_) => "NewChat",
+            Self::AddToChat(_) => "AddToChat",
+            Self::RemoveFromChat(_) => "RemoveFromChat",
+            Self::NewMessage(_) => "NewMessage",
+            Self::NewThreadReply(_) => "NewThreadReply",
+            Self::MessageUpdated(_) => "MessageUpdated",
+            Self::MessageDeleted(_) => "MessageDeleted",
+            Self::ReactionChanged(_) => "ReactionChanged",
+            Self::ReadReceipt(_) => "ReadReceipt",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionChanged {
+    pub chat_id: i64,
+    /// true if the reaction is added, false if removed
+    pub added: bool,
+    pub reaction: MessageReaction,
 }
 
 #[derive(Debug)]
@@ -22,98 +54,339 @@ struct Notification {
     // users being impacted, so we should send the notification to them
     user_ids: HashSet<u64>,
     event: Arc<AppEvent>,
+    // identity of the event, the changed row, the operation and the time of the change
+    key: String,
 }
 
-// pg_notify('chat_updated', json_build_object('op', TG_OP, 'old', OLD, 'new', NEW)::text);
+// pg_notify('chat_updated', json_build_object('op', TG_OP, 'old', OLD, 'new', NEW, 'at', clock_timestamp())::text);
 #[derive(Debug, Serialize, Deserialize)]
 struct ChatUpdated {
     op: String,
     old: Option<Chat>,
     new: Option<Chat>,
+    at: String,
 }
 
-// pg_notify('chat_message_created', row_to_json(NEW)::text);
+// pg_notify('chat_message_created' | 'chat_message_updated' | 'chat_message_deleted',
+//   json_build_object('message', NEW, 'members', USERS, 'at', clock_timestamp())::text);
 #[derive(Debug, Serialize, Deserialize)]
-struct ChatMessageCreated {
+struct ChatMessageChanged {
     message: Message,
     members: Vec<u64>,
+    at: String,
+}
+
+// pg_notify('chat_message_reaction',
+//   json_build_object('op', TG_OP, 'chat_id', CHAT_ID, 'reaction', REACTION, 'members', USERS, 'at', clock_timestamp())::text);
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatMessageReaction {
+    op: String,
+    chat_id: i64,
+    reaction: MessageReaction,
+    members: Vec<u64>,
+    at: String,
+}
+
+// pg_notify('chat_read_state_updated', json_build_object('read_state', NEW, 'members', USERS, 'at', clock_timestamp())::text);
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatReadStateUpdated {
+    read_state: ChatReadState,
+    members: Vec<u64>,
+    at: String,
 }
 
 pub async fn setup_pg_listener(state: AppState) -> Result<()> {
     let mut listener = PgListener::connect(&state.config.server.db_url).await?;
     listener.listen("chat_updated").await?;
     listener.listen("chat_message_created").await?;
+    listener.listen("chat_message_updated").await?;
+    listener.listen("chat_message_deleted").await?;
+    listener.listen("chat_message_reaction").await?;
+    listener.listen("chat_read_state_updated").await?;
+    listener.listen(SESSION_REVOKED_CHANNEL).await?;
+    // sessions revoked before the server started, the rest come from the listener
+    state.revoked.load(&mut listener).await?;
 
     let mut stream = listener.into_stream();
+    // webhook deliveries are queued by another task, so that the database doesn't slow down SSE
+    let webhooks = webhook::setup_enqueuer(state.pool.clone());
 
     tokio::spawn(async move {
         while let Some(Ok(notify)) = stream.next().await {
             info!("Received notification: {:?}", notify);
-            let notification = Notification::load(notify.channel(), notify.payload())?;
+            if notify.channel() == SESSION_REVOKED_CHANNEL {
+                state.revoked.revoke(notify.payload());
+                continue;
+            }
+            // a malformed notification is skipped, the rest are still delivered
+            let notifications = match Notification::load(notify.channel(), notify.payload()) {
+                Ok(notifications) => notifications,
+                Err(e) => {
+                    warn!("Failed to load notification of {}: {}", notify.channel(), e);
+                    continue;
+                }
+            };
             let users = &state.users;
-            for user_id in notification.user_ids {
-                if let Some(tx) = users.get(&user_id) {
-                    info!("Sending notification to user[{}]", user_id);
-                    if let Err(e) = tx.send(notification.event.clone()) {
-                        warn!("Failed to send notification to user[{}]: {}", user_id, e);
+            for notification in notifications {
+                if let Err(e) = webhooks.send((notification.key, notification.event.clone())) {
+                    warn!("Failed to queue deliveries of {}: {}", e.0 .1.name(), e);
+                }
+                for user_id in notification.user_ids {
+                    if let Some(tx) = users.get(&user_id) {
+                        info!("Sending notification to user[{}]", user_id);
+                        if let Err(e) = tx.send(notification.event.clone()) {
+                            warn!("Failed to send notification to user[{}]: {}", user_id, e);
+                        }
                     }
                 }
             }
         }
-        Ok::<_, anyhow::Error>(())
     });
 
     Ok(())
 }
 
 impl Notification {
-    fn load(r#type: &str, payload: &str) -> Result<Self> {
+    /// `id` is the identity of the change, a change could have several events
+    fn new(user_ids: HashSet<u64>, event: AppEvent, id: &str) -> Self {
+        Self {
+            user_ids,
+            key: format!("{id}:{}", event.name()),
+            event: Arc::new(event),
+        }
+    }
+
+    // a change could notify different users with different events
+    fn load(r#type: &str, payload: &str) -> Result<Vec<Self>> {
         match r#type {
             "chat_updated" => {
                 let payload = serde_json::from_str::<ChatUpdated>(payload)?;
                 info!("Chat updated: {:?}", payload);
-                let user_ids =
-                    get_affected_chat_user_ids(payload.old.as_ref(), payload.new.as_ref());
-                let event = match payload.op.as_str() {
-                    "INSERT" => AppEvent::NewChat(payload.new.expect("new should exist")),
-                    "UPDATE" => AppEvent::AddToChat(payload.old.expect("new should exist")),
-                    "DELETE" => AppEvent::RemoveFromChat(payload.old.expect("old should exist")),
+                let id = |chat: &Chat| format!("chats:{}:{}:{}", chat.id, payload.op, payload.at);
+                let notifications = match (payload.op.as_str(), payload.old, payload.new) {
+                    ("INSERT", _, Some(new)) => {
+                        let id = id(&new);
+                        vec![Self::new(chat_user_ids(&new), AppEvent::NewChat(new), &id)]
+                    }
+                    ("UPDATE", Some(old), Some(new)) => {
+                        // if members are identical, no need to notify. Otherwise removed members
+                        // are notified with `RemoveFromChat`, the rest with `AddToChat`
+                        let old_members = chat_user_ids(&old);
+                        let new_members = chat_user_ids(&new);
+                        if old_members == new_members {
+                            vec![]
+                        } else {
+                            let id = id(&new);
+                            let removed: HashSet<u64> =
+                                old_members.difference(&new_members).copied().collect();
+                            let mut notifications = vec![Self::new(
+                                new_members,
+                                AppEvent::AddToChat(new.clone()),
+                                &id,
+                            )];
+                            if !removed.is_empty() {
+                                notifications.push(Self::new(
+                                    removed,
+                                    AppEvent::RemoveFromChat(new),
+                                    &id,
+                                ));
+                            }
+                            notifications
+                        }
+                    }
+                    ("DELETE", Some(old), _) => {
+                        let id = id(&old);
+                        vec![Self::new(
+                            chat_user_ids(&old),
+                            AppEvent::RemoveFromChat(old),
+                            &id,
+                        )]
+                    }
                     _ => return Err(anyhow::anyhow!("Invalid operation")),
                 };
-                Ok(Self {
+                Ok(notifications)
+            }
+            "chat_message_created" | "chat_message_updated" | "chat_message_deleted" => {
+                let payload = serde_json::from_str::<ChatMessageChanged>(payload)?;
+                let user_ids = payload.members.iter().copied().collect();
+                let id = format!("messages:{}:{}:{}", payload.message.id, r#type, payload.at);
+                let event = match r#type {
+                    "chat_message_created" if payload.message.parent_id.is_some() => {
+                        AppEvent::NewThreadReply(payload.message)
+                    }
+                    "chat_message_created" => AppEvent::NewMessage(payload.message),
+                    "chat_message_updated" => AppEvent::MessageUpdated(payload.message),
+                    _ => AppEvent::MessageDeleted(payload.message),
+                };
+                Ok(vec![Self::new(user_ids, event, &id)])
+            }
+            "chat_message_reaction" => {
+                let payload = serde_json::from_str::<ChatMessageReaction>(payload)?;
+                let user_ids = payload.members.iter().copied().collect();
+                let reaction = &payload.reaction;
+                let id = format!(
+                    "message_reactions:{}:{}:{}:{}:{}",
+                    reaction.message_id, reaction.user_id, reaction.emoji, payload.op, payload.at
+                );
+                let event = ReactionChanged {
+                    chat_id: payload.chat_id,
+                    added: payload.op == "INSERT",
+                    reaction: payload.reaction,
+                };
+                Ok(vec![Self::new(
                     user_ids,
-                    event: Arc::new(event),
-                })
+                    AppEvent::ReactionChanged(event),
+                    &id,
+                )])
             }
-            "chat_message_created" => {
-                let payload = serde_json::from_str::<ChatMessageCreated>(payload)?;
+            "chat_read_state_updated" => {
+                let payload = serde_json::from_str::<ChatReadStateUpdated>(payload)?;
                 let user_ids = payload.members.iter().copied().collect();
-                Ok(Self {
+                let read_state = &payload.read_state;
+                let id = format!(
+                    "chat_read_state:{}:{}:{}",
+                    read_state.chat_id, read_state.user_id, payload.at
+                );
+                Ok(vec![Self::new(
                     user_ids,
-                    event: Arc::new(AppEvent::NewMessage(payload.message)),
-                })
+                    AppEvent::ReadReceipt(payload.read_state),
+                    &id,
+                )])
             }
             _ => Err(anyhow::anyhow!("Invalid notification type")),
         }
     }
 }
 
-fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet<u64> {
-    match (old, new) {
-        (Some(old), Some(new)) => {
-            // diff old/new members, if identical, no need to notify, otherwise notify the union of both
-            let old_members: HashSet<_> = old.members.iter().map(|v| *v as u64).collect();
-            let new_members: HashSet<_> = new.members.iter().map(|v| *v as u64).collect();
-
-            if old_members == new_members {
-                HashSet::new()
-            } else {
-                old_members.union(&new_members).copied().collect()
-            }
+fn chat_user_ids(chat: &Chat) -> HashSet<u64> {
+    chat.members.iter().map(|v| *v as u64).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const AT: &str = "2025-01-02T03:04:05.678901+00:00";
+
+    #[test]
+    fn message_notifications_should_load() -> Result<()> {
+        let payload = message_payload(json!({ "parent_id": null }));
+        let notifications = Notification::load("chat_message_created", &payload)?;
+        assert!(matches!(&*notifications[0].event, AppEvent::NewMessage(m) if m.id == 10));
+
+        let payload = message_payload(json!({ "parent_id": 5 }));
+        let notifications = Notification::load("chat_message_created", &payload)?;
+        let AppEvent::NewThreadReply(message) = &*notifications[0].event else {
+            panic!("expect a thread reply");
+        };
+        assert_eq!(message.parent_id, Some(5));
+
+        let payload = message_payload(json!({ "content": "hi", "edited_at": AT }));
+        let notifications = Notification::load("chat_message_updated", &payload)?;
+        let AppEvent::MessageUpdated(message) = &*notifications[0].event else {
+            panic!("expect an updated message");
+        };
+        assert_eq!(message.content, "hi");
+        assert!(message.edited_at.is_some());
+        assert_eq!(notifications[0].user_ids, HashSet::from([1, 2]));
+
+        let payload = message_payload(json!({ "content": "", "files": [], "deleted_at": AT }));
+        let notifications = Notification::load("chat_message_deleted", &payload)?;
+        let AppEvent::MessageDeleted(message) = &*notifications[0].event else {
+            panic!("expect a deleted message");
+        };
+        assert!(message.deleted_at.is_some());
+        assert_eq!(
+            notifications[0].key,
+            format!("messages:10:chat_message_deleted:{AT}:MessageDeleted")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reaction_notification_should_load() -> Result<()> {
+        let payload = |op: &str| {
+            json!({
+                "op": op,
+                "chat_id": 1,
+                "reaction": {
+                    "message_id": 10,
+                    "user_id": 2,
+                    "emoji": "👍",
+                    "created_at": AT,
+                },
+                "members": [1, 2],
+                "at": AT,
+            })
+            .to_string()
+        };
+
+        let notifications = Notification::load("chat_message_reaction", &payload("INSERT"))?;
+        let AppEvent::ReactionChanged(changed) = &*notifications[0].event else {
+            panic!("expect a reaction change");
+        };
+        assert!(changed.added);
+        assert_eq!(changed.reaction.emoji, "👍");
+
+        let notifications = Notification::load("chat_message_reaction", &payload("DELETE"))?;
+        let AppEvent::ReactionChanged(changed) = &*notifications[0].event else {
+            panic!("expect a reaction change");
+        };
+        assert!(!changed.added);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_receipt_notification_should_load() -> Result<()> {
+        let payload = json!({
+            "read_state": {
+                "chat_id": 1,
+                "user_id": 2,
+                "last_read_message_id": 10,
+                "updated_at": AT,
+            },
+            "members": [1, 2, 3],
+            "at": AT,
+        });
+        let notifications = Notification::load("chat_read_state_updated", &payload.to_string())?;
+        let AppEvent::ReadReceipt(read_state) = &*notifications[0].event else {
+            panic!("expect a read receipt");
+        };
+        assert_eq!(read_state.last_read_message_id, 10);
+        assert_eq!(notifications[0].user_ids, HashSet::from([1, 2, 3]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_notification_should_fail() {
+        assert!(Notification::load("chat_message_created", "{}").is_err());
+        assert!(Notification::load("unknown", &message_payload(json!({}))).is_err());
+    }
+
+    /// Payload of the message triggers, with the given fields of the message replaced
+    fn message_payload(fields: Value) -> String {
+        let mut message = json!({
+            "id": 10,
+            "chat_id": 1,
+            "sender_id": 1,
+            "sender_type": "user",
+            "content": "hello",
+            "modified_content": null,
+            "files": [],
+            "parent_id": null,
+            "reply_count": 0,
+            "last_reply_at": null,
+            "created_at": AT,
+            "edited_at": null,
+            "deleted_at": null,
+        });
+        for (k, v) in fields.as_object().unwrap() {
+            message[k] = v.clone();
         }
-        // (Some(chat), None) | (None, Some(chat)) => chat.user_ids.clone(),
-        (Some(old), None) => old.members.iter().map(|v| *v as u64).collect(),
-        (None, Some(new)) => new.members.iter().map(|v| *v as u64).collect(),
-        _ => HashSet::new(),
+        json!({ "message": message, "members": [1, 2], "at": AT }).to_string()
     }
 }
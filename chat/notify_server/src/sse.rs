@@ -1,4 +1,4 @@
-use crate::{AppEvent, AppState};
+use crate::AppState;
 use axum::{
     extract::State,
     response::{sse::Event, Sse},
@@ -30,12 +30,7 @@ pub(crate) async fn sse_handler(
     info!("User {} subscribed", user_id);
 
     let stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
-        let name = match v.as_ref() {
-            AppEvent::NewChat(_) => "NewChat",
-            AppEvent::AddToChat(_) => "AddToChat",
-            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
-            AppEvent::NewMessage(_) => "NewMessage",
-        };
+        let name = v.name();
         let v = serde_json::to_string(&v).expect("Failed to serialize event");
         debug!("Sending event {}: {:?}", name, v);
         Ok(Event::default().data(v).event(name))
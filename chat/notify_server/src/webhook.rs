@@ -0,0 +1,519 @@
+use crate::{config::WebhookConfig, AppEvent, AppState};
+use anyhow::Result;
+use chat_core::{is_public_ip, sign_webhook, ChatType, WEBHOOK_SIGNATURE_HEADER};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::CONTENT_TYPE,
+    redirect, Client, Url,
+};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{types::Json, FromRow, PgPool};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{net::lookup_host, sync::mpsc, time};
+use tracing::{info, warn};
+
+#[derive(Debug, FromRow)]
+struct DueDelivery {
+    id: i64,
+    event: String,
+    payload: Value,
+    /// including the current one
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// Resolves the hosts of the endpoints to their public addresses only. The addresses are
+/// checked on every connection, so that a host couldn't be rebound to an internal address
+/// after the subscription is created.
+struct PublicResolver;
+
+/// Events to queue for the subscriptions, with the identity of each event
+pub type WebhookSender = mpsc::UnboundedSender<(String, Arc<AppEvent>)>;
+
+/// Queue the events sent to the returned sender one by one, off the task of the pg listener
+pub fn setup_enqueuer(pool: PgPool) -> WebhookSender {
+    let (tx, mut rx) = mpsc::unbounded_channel::<(String, Arc<AppEvent>)>();
+    tokio::spawn(async move {
+        while let Some((key, event)) = rx.recv().await {
+            if let Err(e) = enqueue_deliveries(&pool, &key, &event).await {
+                warn!("Failed to queue deliveries of {}: {}", event.name(), e);
+            }
+        }
+    });
+    tx
+}
+
+/// Queue the event for the subscriptions of its workspace, the events of a chat only for the
+/// subscriptions created by its members unless it is a public channel. Every notify server
+/// receives the same notifications, the key of the event makes sure it is queued only once.
+async fn enqueue_deliveries(pool: &PgPool, key: &str, event: &AppEvent) -> Result<()> {
+    let Some((ws_id, members)) = audience_of(pool, event).await? else {
+        return Ok(());
+    };
+    // hashed to fit the column
+    let key = hex::encode(Sha256::digest(key.as_bytes()));
+
+    let ret = sqlx::query(
+        r#"
+        INSERT INTO event_deliveries (subscription_id, event_key, event, payload)
+        SELECT id, $2, $3, $4
+        FROM event_subscriptions
+        WHERE ws_id = $1 AND (cardinality(events) = 0 OR $3 = ANY(events))
+          AND ($5::bigint[] IS NULL OR created_by = ANY($5))
+        ON CONFLICT (subscription_id, event_key) DO NOTHING
+        "#,
+    )
+    .bind(ws_id)
+    .bind(key)
+    .bind(event.name())
+    .bind(Json(event))
+    .bind(members)
+    .execute(pool)
+    .await?;
+
+    if ret.rows_affected() > 0 {
+        info!(
+            "Queued {} deliveries of {} in workspace[{}]",
+            ret.rows_affected(),
+            event.name(),
+            ws_id
+        );
+    }
+    Ok(())
+}
+
+/// Send the due deliveries periodically. Deliveries are claimed with a lease, so that multiple
+/// notify servers could share the queue, and a delivery is retried if its server is gone.
+pub fn setup_delivery_worker(state: AppState) -> Result<()> {
+    let config = state.config.webhook.clone();
+    let client = Client::builder()
+        .timeout(Duration::from_secs(config.timeout))
+        .redirect(redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()?;
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(config.poll_interval.max(1)));
+        loop {
+            interval.tick().await;
+            // a full batch means more deliveries may be due
+            loop {
+                match deliver_due(&state.pool, &client, &config).await {
+                    Ok(n) if n >= config.batch_size as usize => continue,
+                    Ok(_) => break,
+                    Err(e) => {
+                        warn!("Failed to deliver events: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn deliver_due(pool: &PgPool, client: &Client, config: &WebhookConfig) -> Result<usize> {
+    // paused subscriptions keep their deliveries pending
+    let deliveries: Vec<DueDelivery> = sqlx::query_as(
+        r#"
+        WITH due AS (
+          SELECT d.id
+          FROM event_deliveries d
+          JOIN event_subscriptions s ON s.id = d.subscription_id
+          WHERE d.status = 'pending' AND d.next_attempt_at <= NOW() AND s.paused_at IS NULL
+          ORDER BY d.next_attempt_at
+          LIMIT $1
+          FOR UPDATE OF d SKIP LOCKED
+        )
+        UPDATE event_deliveries d
+        SET attempts = d.attempts + 1, next_attempt_at = NOW() + make_interval(secs => $2)
+        FROM due, event_subscriptions s
+        WHERE d.id = due.id AND s.id = d.subscription_id
+        RETURNING d.id, d.event, d.payload, d.attempts, s.url, s.secret
+        "#,
+    )
+    .bind(config.batch_size as i64)
+    .bind((config.timeout * 2) as f64)
+    .fetch_all(pool)
+    .await?;
+
+    let n = deliveries.len();
+    let tasks = deliveries
+        .into_iter()
+        .map(|delivery| deliver(pool, client, config, delivery));
+    for ret in futures::future::join_all(tasks).await {
+        if let Err(e) = ret {
+            warn!("Failed to record the delivery: {}", e);
+        }
+    }
+
+    Ok(n)
+}
+
+/// Post the event to the endpoint and log the attempt. The delivery is done on any 2xx response,
+/// otherwise it is retried later until it runs out of attempts.
+async fn deliver(
+    pool: &PgPool,
+    client: &Client,
+    config: &WebhookConfig,
+    delivery: DueDelivery,
+) -> Result<()> {
+    let body = serde_json::to_vec(&delivery.payload)?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let signature = sign_webhook(&delivery.secret, timestamp, &body);
+
+    let start = Instant::now();
+    let (status_code, error) = match check_ip_host(&delivery.url) {
+        Err(e) => (None, Some(e)),
+        Ok(()) => {
+            let ret = client
+                .post(&delivery.url)
+                .header(CONTENT_TYPE, "application/json")
+                .header(WEBHOOK_SIGNATURE_HEADER, signature)
+                .header("x-chat-event", &delivery.event)
+                .header("x-chat-delivery", delivery.id.to_string())
+                .body(body)
+                .send()
+                .await;
+            match ret {
+                Ok(res) if res.status().is_success() => (Some(res.status().as_u16() as i32), None),
+                Ok(res) => (
+                    Some(res.status().as_u16() as i32),
+                    Some(format!("unexpected status {}", res.status())),
+                ),
+                Err(e) => (None, Some(e.to_string())),
+            }
+        }
+    };
+    let duration_ms = start.elapsed().as_millis() as i32;
+
+    sqlx::query(
+        r#"
+        INSERT INTO event_delivery_attempts (delivery_id, status_code, error, duration_ms)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(delivery.id)
+    .bind(status_code)
+    .bind(&error)
+    .bind(duration_ms)
+    .execute(pool)
+    .await?;
+
+    let (status, backoff) = match &error {
+        None => ("delivered", 0),
+        Some(_) if delivery.attempts as u32 >= config.max_attempts => ("failed", 0),
+        Some(_) => ("pending", backoff_secs(config, delivery.attempts as u32)),
+    };
+    if let Some(e) = &error {
+        warn!(
+            "Delivery[{}] to {} failed on attempt {}: {}",
+            delivery.id, delivery.url, delivery.attempts, e
+        );
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE event_deliveries
+        SET
+          status = $2::delivery_status,
+          delivered_at = CASE WHEN $2 = 'delivered' THEN NOW() END,
+          next_attempt_at = NOW() + make_interval(secs => $3)
+        WHERE id = $1
+        "#,
+    )
+    .bind(delivery.id)
+    .bind(status)
+    .bind(backoff as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Urls with an ip as the host are not resolved, so they are checked here
+fn check_ip_host(url: &str) -> Result<(), String> {
+    let url = Url::parse(url).map_err(|e| e.to_string())?;
+    let ip = match url.host_str() {
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>(),
+        None => return Err("url without host".to_string()),
+    };
+    match ip {
+        Ok(ip) if !is_public_ip(ip) => Err(format!("{ip} is not a public address")),
+        _ => Ok(()),
+    }
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Seconds before the next attempt, doubled after every failed attempt
+fn backoff_secs(config: &WebhookConfig, attempts: u32) -> u64 {
+    let factor = 1u64 << attempts.saturating_sub(1).min(32);
+    config
+        .base_backoff
+        .saturating_mul(factor)
+        .min(config.max_backoff)
+}
+
+/// The workspace of the event, and the members of its chat if the chat is not a public channel
+async fn audience_of(pool: &PgPool, event: &AppEvent) -> Result<Option<(i64, Option<Vec<i64>>)>> {
+    let chat_id = match event {
+        AppEvent::NewChat(chat) | AppEvent::AddToChat(chat) | AppEvent::RemoveFromChat(chat) => {
+            let members = (chat.r#type != ChatType::PublicChannel).then(|| chat.members.clone());
+            return Ok(Some((chat.ws_id, members)));
+        }
+        AppEvent::NewMessage(message)
+        | AppEvent::NewThreadReply(message)
+        | AppEvent::MessageUpdated(message)
+        | AppEvent::MessageDeleted(message) => message.chat_id,
+        AppEvent::ReactionChanged(changed) => changed.chat_id,
+        AppEvent::ReadReceipt(read_state) => read_state.chat_id,
+    };
+
+    let audience = sqlx::query_as(
+        r#"
+        SELECT ws_id, CASE WHEN type = 'public_channel' THEN NULL ELSE members END
+        FROM chats
+        WHERE id = $1
+        "#,
+    )
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(audience)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get_test_pool;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn backoff_should_grow_up_to_the_cap() {
+        let config = WebhookConfig {
+            base_backoff: 10,
+            max_backoff: 3600,
+            ..Default::default()
+        };
+        let backoffs: Vec<_> = (1..=4).map(|n| backoff_secs(&config, n)).collect();
+        assert_eq!(backoffs, vec![10, 20, 40, 80]);
+        assert_eq!(backoff_secs(&config, 9), 2560);
+        assert_eq!(backoff_secs(&config, 10), 3600);
+        assert_eq!(backoff_secs(&config, u32::MAX), 3600);
+    }
+
+    #[test]
+    fn check_ip_host_should_reject_internal_addresses() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://10.0.0.1/hook",
+            "http://192.168.1.1:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[fe80::1]/hook",
+        ] {
+            assert!(check_ip_host(url).is_err(), "{url} should be rejected");
+        }
+
+        assert!(check_ip_host("https://93.184.216.34/hook").is_ok());
+        // hosts are checked when they are resolved
+        assert!(check_ip_host("https://example.com/hook").is_ok());
+        assert!(check_ip_host("not a url").is_err());
+    }
+
+    #[tokio::test]
+    async fn public_resolver_should_reject_loopback_hosts() {
+        let name = Name::from_str("localhost").unwrap();
+        assert!(PublicResolver.resolve(name).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn enqueue_deliveries_should_queue_an_event_once() -> Result<()> {
+        let (_tdb, pool) = get_test_pool().await;
+        // workspace 2 has its own subscription, it shouldn't receive the events of workspace 1
+        let sub_id = create_subscription(&pool, 1, 1, &[]).await?;
+        create_subscription(&pool, 2, 1, &[]).await?;
+        create_subscription(&pool, 1, 1, &["NewChat"]).await?;
+
+        let event = new_message(1);
+        enqueue_deliveries(&pool, "messages:1:created", &event).await?;
+        // the same event received by another notify server
+        enqueue_deliveries(&pool, "messages:1:created", &event).await?;
+        enqueue_deliveries(&pool, "messages:1:updated", &event).await?;
+
+        let rows: Vec<(i64, String)> =
+            sqlx::query_as("SELECT subscription_id, event FROM event_deliveries ORDER BY id")
+                .fetch_all(&pool)
+                .await?;
+        assert_eq!(
+            rows,
+            vec![
+                (sub_id, "NewMessage".to_string()),
+                (sub_id, "NewMessage".to_string())
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn enqueue_deliveries_should_keep_chats_to_their_members() -> Result<()> {
+        let (_tdb, pool) = get_test_pool().await;
+        // alice is a member of the private channel, daisy is not
+        let alice = create_subscription(&pool, 1, 2, &[]).await?;
+        let daisy = create_subscription(&pool, 1, 5, &[]).await?;
+
+        let queued_for = |key: &'static str| {
+            let pool = pool.clone();
+            async move {
+                let ids: Vec<(i64,)> = sqlx::query_as(
+                    "SELECT subscription_id FROM event_deliveries WHERE event_key = $1 ORDER BY subscription_id",
+                )
+                .bind(hex::encode(Sha256::digest(key.as_bytes())))
+                .fetch_all(&pool)
+                .await?;
+                Ok::<_, anyhow::Error>(ids.into_iter().map(|v| v.0).collect::<Vec<_>>())
+            }
+        };
+
+        // chat 1 is a public channel, chat 2 a private channel of users 1, 2 and 3
+        enqueue_deliveries(&pool, "public", &new_message(1)).await?;
+        enqueue_deliveries(&pool, "private", &new_message(2)).await?;
+        assert_eq!(queued_for("public").await?, vec![alice, daisy]);
+        assert_eq!(queued_for("private").await?, vec![alice]);
+
+        let chat = json!({
+            "id": 3,
+            "ws_id": 1,
+            "name": null,
+            "type": "single",
+            "members": [1, 2],
+            "agents": [],
+            "created_at": "2025-01-01T00:00:00Z",
+        });
+        let event = AppEvent::NewChat(serde_json::from_value(chat)?);
+        enqueue_deliveries(&pool, "chat", &event).await?;
+        assert_eq!(queued_for("chat").await?, vec![alice]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_should_be_retried_until_max_attempts() -> Result<()> {
+        let (_tdb, pool) = get_test_pool().await;
+        let config = WebhookConfig {
+            max_attempts: 2,
+            ..Default::default()
+        };
+        let client = Client::new();
+        // rejected before sending, so that the test doesn't need an endpoint
+        let sub_id = create_subscription(&pool, 1, 1, &[]).await?;
+        sqlx::query("UPDATE event_subscriptions SET url = 'http://127.0.0.1:1/hook' WHERE id = $1")
+            .bind(sub_id)
+            .execute(&pool)
+            .await?;
+        enqueue_deliveries(&pool, "messages:1:created", &new_message(1)).await?;
+
+        assert_eq!(deliver_due(&pool, &client, &config).await?, 1);
+        let (status, attempts, backoff) = delivery_state(&pool).await?;
+        assert_eq!((status.as_str(), attempts), ("pending", 1));
+        assert!((backoff - config.base_backoff as f64).abs() < 5.0);
+
+        // not due yet
+        assert_eq!(deliver_due(&pool, &client, &config).await?, 0);
+
+        sqlx::query("UPDATE event_deliveries SET next_attempt_at = NOW()")
+            .execute(&pool)
+            .await?;
+        assert_eq!(deliver_due(&pool, &client, &config).await?, 1);
+        let (status, attempts, _) = delivery_state(&pool).await?;
+        assert_eq!((status.as_str(), attempts), ("failed", 2));
+
+        let (count, error): (i64, Option<String>) =
+            sqlx::query_as("SELECT COUNT(*), MAX(error) FROM event_delivery_attempts")
+                .fetch_one(&pool)
+                .await?;
+        assert_eq!(count, 2);
+        assert!(error.unwrap().contains("not a public address"));
+
+        // failed deliveries are not picked up again
+        sqlx::query("UPDATE event_deliveries SET next_attempt_at = NOW()")
+            .execute(&pool)
+            .await?;
+        assert_eq!(deliver_due(&pool, &client, &config).await?, 0);
+
+        Ok(())
+    }
+
+    async fn create_subscription(
+        pool: &PgPool,
+        ws_id: i64,
+        created_by: i64,
+        events: &[&str],
+    ) -> Result<i64> {
+        let (id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO event_subscriptions (ws_id, url, secret, events, created_by)
+            VALUES ($1, 'https://example.com/hook', 'secret', $2, $3)
+            RETURNING id
+            "#,
+        )
+        .bind(ws_id)
+        .bind(events)
+        .bind(created_by)
+        .fetch_one(pool)
+        .await?;
+        Ok(id)
+    }
+
+    /// status, attempts and seconds to the next attempt of the only delivery
+    async fn delivery_state(pool: &PgPool) -> Result<(String, i32, f64)> {
+        let row = sqlx::query_as(
+            r#"
+            SELECT status::text, attempts, EXTRACT(EPOCH FROM next_attempt_at - NOW())::float8
+            FROM event_deliveries
+            "#,
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(row)
+    }
+
+    fn new_message(chat_id: i64) -> AppEvent {
+        let message = json!({
+            "id": 1,
+            "chat_id": chat_id,
+            "sender_id": 1,
+            "content": "hello",
+            "modified_content": null,
+            "files": [],
+            "created_at": "2025-01-01T00:00:00Z",
+        });
+        AppEvent::NewMessage(serde_json::from_value(message).unwrap())
+    }
+}
@@ -1,4 +1,5 @@
 import { createApp } from "vue";
+import axios from "axios";
 import App from "./App.vue";
 import router from "./router";
 import store from "./store";
@@ -9,6 +10,30 @@ const app = createApp(App);
 // Load user state from local storage when the app starts
 store.dispatch("loadUserState");
 
+// access tokens are short-lived, refresh it once and retry the request when it expires
+axios.interceptors.response.use(undefined, async (error) => {
+  const { config, response } = error;
+  const expired =
+    response &&
+    response.status === 403 &&
+    typeof response.data === "string" &&
+    response.data.startsWith("Failed to verify token");
+  if (!expired || config._retried || !store.state.refreshToken) {
+    throw error;
+  }
+
+  config._retried = true;
+  try {
+    const token = await store.dispatch("refreshToken");
+    config.headers.Authorization = `Bearer ${token}`;
+  } catch (e) {
+    store.dispatch("logout");
+    router.push("/login");
+    throw error;
+  }
+  return axios(config);
+});
+
 app.use(store);
 app.use(router);
 
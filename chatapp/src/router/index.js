@@ -2,11 +2,17 @@ import { createRouter, createWebHistory } from "vue-router";
 import Login from "../views/Login.vue";
 import Register from "../views/Register.vue";
 import Chat from "../views/Chat.vue";
+import OidcCallback from "../views/OidcCallback.vue";
 
 const routes = [
   { path: "/", name: "Chat", component: Chat, meta: { requiresAuth: true } },
   { path: "/login", name: "Login", component: Login },
   { path: "/register", name: "Register", component: Register },
+  {
+    path: "/oidc/:provider/callback",
+    name: "OidcCallback",
+    component: OidcCallback,
+  },
 ];
 
 const router = createRouter({
@@ -9,6 +9,7 @@ export default createStore({
   state: {
     user: null, // User information
     token: null, // Authentication token
+    refreshToken: null, // Exchanged for a new token once the token expires
     workspace: {}, // Current workspace
     channels: [], // List of channels
     messages: {}, // Messages hashmap, keyed by channel ID
@@ -26,6 +27,9 @@ export default createStore({
     setToken(state, token) {
       state.token = token;
     },
+    setRefreshToken(state, refreshToken) {
+      state.refreshToken = refreshToken;
+    },
     setWorkspace(state, workspace) {
       state.workspace = workspace;
     },
@@ -57,6 +61,17 @@ export default createStore({
         state.messages[channelId] = [message];
       }
     },
+    updateMessage(state, { channelId, message }) {
+      const messages = state.messages[channelId];
+      if (!messages) {
+        return;
+      }
+      const index = messages.findIndex((m) => m.id === message.id);
+      if (index !== -1) {
+        message.formattedCreatedAt = formatMessageDate(message.createdAt);
+        messages.splice(index, 1, message);
+      }
+    },
     setActiveChannel(state, channelId) {
       const channel = state.channels.find((c) => c.id === channelId);
       state.activeChannel = channel;
@@ -64,6 +79,7 @@ export default createStore({
     loadUserState(state) {
       const storedUser = localStorage.getItem("user");
       const storedToken = localStorage.getItem("token");
+      const storedRefreshToken = localStorage.getItem("refreshToken");
       const storedWorkspace = localStorage.getItem("workspace");
       const storedChannels = localStorage.getItem("channels");
       // we do not store messages in local storage, so this is always empty
@@ -76,6 +92,9 @@ export default createStore({
       if (storedToken) {
         state.token = storedToken;
       }
+      if (storedRefreshToken) {
+        state.refreshToken = storedRefreshToken;
+      }
       if (storedWorkspace) {
         state.workspace = JSON.parse(storedWorkspace);
       }
@@ -104,13 +123,14 @@ export default createStore({
         commit("setSSE", null);
       }
     },
-    async signup({ commit }, { email, fullname, password, workspace }) {
+    async signup({ commit }, { email, fullname, password, workspace, invite }) {
       try {
         const response = await axios.post(`${getUrlBase()}/signup`, {
           email,
           fullname,
           password,
           workspace,
+          invite,
         });
 
         const user = await loadState(response, this, commit);
@@ -127,6 +147,10 @@ export default createStore({
           email,
           password,
         });
+        // 2FA is on, the signin is completed with `signinMfa`
+        if (response.status === 202) {
+          return { mfaToken: response.data.mfa_token };
+        }
 
         const user = await loadState(response, this, commit);
         return user;
@@ -135,16 +159,87 @@ export default createStore({
         throw error;
       }
     },
-    logout({ commit }) {
+    async signinMfa({ commit }, { mfaToken, code }) {
+      try {
+        const response = await axios.post(`${getUrlBase()}/signin/mfa`, {
+          mfa_token: mfaToken,
+          code,
+        });
+
+        const user = await loadState(response, this, commit);
+        return user;
+      } catch (error) {
+        console.error("MFA failed:", error);
+        throw error;
+      }
+    },
+    async fetchOidcProviders() {
+      const response = await axios.get(`${getUrlBase()}/oidc/providers`);
+      return response.data;
+    },
+    async oidcAuthorize(_, provider) {
+      // the provider redirects back to /oidc/:provider/callback
+      const response = await axios.get(
+        `${getUrlBase()}/oidc/${provider}/authorize`,
+      );
+      window.location.href = response.data.url;
+    },
+    async oidcSignin({ commit }, { provider, code, state }) {
+      try {
+        const response = await axios.post(
+          `${getUrlBase()}/oidc/${provider}/callback`,
+          { code, state },
+        );
+        // 2FA is on, the signin is completed with `signinMfa`
+        if (response.status === 202) {
+          return { mfaToken: response.data.mfa_token };
+        }
+
+        const user = await loadState(response, this, commit);
+        return user;
+      } catch (error) {
+        console.error("SSO signin failed:", error);
+        throw error;
+      }
+    },
+    async refreshToken({ state, commit }) {
+      const response = await axios.post(`${getUrlBase()}/refresh`, {
+        refresh_token: state.refreshToken,
+      });
+      const { token, refresh_token } = response.data;
+
+      localStorage.setItem("token", token);
+      localStorage.setItem("refreshToken", refresh_token);
+      commit("setToken", token);
+      commit("setRefreshToken", refresh_token);
+
+      // reconnect SSE with the new token
+      await this.dispatch("initSSE");
+      return token;
+    },
+    logout({ state, commit }) {
+      // revoke the session on the server, the local state is cleared anyway
+      if (state.token) {
+        axios
+          .post(`${getUrlBase()}/signout`, null, {
+            headers: {
+              Authorization: `Bearer ${state.token}`,
+            },
+          })
+          .catch((error) => console.error("Signout failed:", error));
+      }
+
       // Clear local storage and state
       localStorage.removeItem("user");
       localStorage.removeItem("token");
+      localStorage.removeItem("refreshToken");
       localStorage.removeItem("workspace");
       localStorage.removeItem("channels");
       localStorage.removeItem("messages");
 
       commit("setUser", null);
       commit("setToken", null);
+      commit("setRefreshToken", null);
       commit("setWorkspace", "");
       commit("setChannels", []);
       commit("setMessages", {});
@@ -176,7 +271,7 @@ export default createStore({
               },
             }
           );
-          let messages = response.data;
+          let messages = response.data.messages;
           // messages = messages.map((message) => {
           //   const user = state.users[message.senderId];
           //   return {
@@ -317,6 +412,7 @@ async function loadState(response, self, commit) {
     // Store user info, token, and workspace in localStorage
     localStorage.setItem("user", JSON.stringify(user));
     localStorage.setItem("token", token);
+    localStorage.setItem("refreshToken", response.data.refresh_token);
     localStorage.setItem("workspace", JSON.stringify(workspace));
     localStorage.setItem("users", JSON.stringify(usersMap));
     localStorage.setItem("channels", JSON.stringify(channels));
@@ -324,6 +420,7 @@ async function loadState(response, self, commit) {
     // Commit the mutations to update the state
     commit("setUser", user);
     commit("setToken", token);
+    commit("setRefreshToken", response.data.refresh_token);
     commit("setWorkspace", workspace);
     commit("setChannels", channels);
     commit("setUsers", usersMap);
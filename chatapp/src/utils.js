@@ -38,6 +38,14 @@ const initSSE = (store) => {
     store.commit("addMessage", { channelId: data.chatId, message: data });
   });
 
+  const updateMessage = (e) => {
+    let data = JSON.parse(e.data);
+    delete data.event;
+    store.commit("updateMessage", { channelId: data.chatId, message: data });
+  };
+  sse.addEventListener("MessageUpdated", updateMessage);
+  sse.addEventListener("MessageDeleted", updateMessage);
+
   sse.onmessage = (event) => {
     console.log("got event:", event);
     // const data = JSON.parse(event.data);